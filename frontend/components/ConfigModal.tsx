@@ -5,7 +5,7 @@ import { AnimatePresence, motion } from "framer-motion";
 import { useEffect, useMemo, useRef, useState } from "react";
 
 import { StatusMessage } from "@/components/StatusMessage";
-import { ensureAuthConfig } from "@/lib/cloudconfig";
+import { endSession, ensureAuthConfig } from "@/lib/cloudconfig";
 import type { ServerConfig } from "@/lib/store";
 import { useConfigStore, useIsConfigured } from "@/lib/store";
 
@@ -217,6 +217,7 @@ export function ConfigModal({ isOpen, onClose }: ConfigModalProps) {
     if (!window.confirm(confirmMessage)) {
       return;
     }
+    void endSession(server).catch(() => undefined);
     removeServer(server.id);
     if (editingServerId === server.id) {
       setEditingServerId(null);
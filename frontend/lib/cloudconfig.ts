@@ -4,12 +4,22 @@ export type AuthConfig = {
   baseUrl: string;
   clientId: string;
   privateKeyPem: string;
+  /** Base64 key from `GET /server-key`; when set, `/api` responses must carry a valid `X-Server-Signature`. */
+  serverKey?: string;
 };
 
 const encoder = new TextEncoder();
 const uuidPattern =
   /^[0-9a-f]{8}-[0-9a-f]{4}-[1-5][0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$/i;
 
+// The client each same-origin server's session cookie was opened for, and
+// whether it has the admin scope. The cookie is per origin, so switching
+// clients has to open a new session.
+const sessions = new Map<
+  string,
+  { clientId: string; admin: boolean; ready: Promise<void> }
+>();
+
 export async function signedJsonRequest<TResponse>(
   auth: AuthConfig,
   method: HttpMethod,
@@ -19,39 +29,86 @@ export async function signedJsonRequest<TResponse>(
   const normalizedPath = normalizePath(path);
   const normalizedBaseUrl = normalizeBaseUrl(auth.baseUrl);
   const bodyString = body === undefined ? "" : JSON.stringify(body);
-  const timestamp = Math.floor(Date.now() / 1_000).toString();
-  const nonce = crypto.randomUUID();
-  const canonical = await createCanonical(
-    timestamp,
-    method,
-    normalizedPath,
-    nonce,
-    bodyString,
-  );
+  const contentType = body === undefined ? "" : "application/json";
+  const url = `${normalizedBaseUrl}${normalizedPath}`;
 
-  const signature = await signCanonical(auth.privateKeyPem, canonical);
-  const headers: Record<string, string> = {
-    "X-Client-Id": auth.clientId.trim(),
-    "X-Timestamp": timestamp,
-    "X-Nonce": nonce,
-    "X-Signature": signature,
+  const send = async (nonce: string, otp?: string): Promise<Response> => {
+    const extra: Record<string, string> = otp ? { "X-OTP": otp } : {};
+    if (isSameOrigin(normalizedBaseUrl)) {
+      // The console's own server: sign once for an HttpOnly session cookie.
+      // `X-Nonce` still goes along so signed responses stay bound to the request.
+      const headers: Record<string, string> = { ...extra, "X-Nonce": nonce };
+      if (contentType) {
+        headers["Content-Type"] = contentType;
+      }
+      const init: RequestInit = {
+        method,
+        headers,
+        body: bodyString || undefined,
+      };
+      const admin = normalizedPath.startsWith("/admin/");
+      const ready = ensureSession(auth, normalizedBaseUrl, admin);
+      await ready;
+      const response = await fetch(url, init);
+      if (response.status !== 401) {
+        return response;
+      }
+      // Expired or revoked: sign a fresh challenge and try once more.
+      await ensureSession(auth, normalizedBaseUrl, admin, ready);
+      return fetch(url, init);
+    }
+
+    const headers = await signatureHeaders(auth, {
+      timestamp: Math.floor(Date.now() / 1_000).toString(),
+      method,
+      host: new URL(normalizedBaseUrl).host,
+      path: normalizedPath,
+      contentType,
+      nonce,
+      keyId: await keyIdFromPrivateKey(auth.privateKeyPem),
+      signedHeaders: extra,
+      bodyString,
+    });
+    return fetch(url, {
+      method,
+      headers,
+      body: bodyString || undefined,
+    });
   };
 
-  if (body !== undefined) {
-    headers["Content-Type"] = "application/json";
+  let nonce = crypto.randomUUID();
+  let response = await send(nonce);
+  if (response.status === 403 && typeof window !== "undefined") {
+    // Admins with one-time codes enabled confirm destructive operations.
+    const detail = parseJsonIfPossible(await response.clone().text());
+    const message =
+      typeof detail === "object" && detail !== null && "error" in detail
+        ? String(detail.error)
+        : "";
+    const otp = message.includes("one-time code")
+      ? window.prompt(`${message}. Enter your authenticator or recovery code:`)
+      : null;
+    if (otp?.trim()) {
+      nonce = crypto.randomUUID();
+      response = await send(nonce, otp.trim());
+    }
   }
 
-  const response = await fetch(`${normalizedBaseUrl}${normalizedPath}`, {
-    method,
-    headers,
-    body: bodyString || undefined,
-  });
+  const rawBytes = new Uint8Array(await response.arrayBuffer());
+  if (auth.serverKey && normalizedPath.startsWith("/api/")) {
+    await verifyServerSignature(
+      auth.serverKey,
+      { nonce, method, path: normalizedPath },
+      response,
+      rawBytes,
+    );
+  }
 
   if (response.status === 204) {
     return undefined as TResponse;
   }
 
-  const raw = await response.text();
+  const raw = new TextDecoder().decode(rawBytes);
   const parsed = parseJsonIfPossible(raw);
   if (!response.ok) {
     const detail =
@@ -62,6 +119,16 @@ export async function signedJsonRequest<TResponse>(
   return parsed as TResponse;
 }
 
+/** Revokes the session cookie for `auth`'s server, if it uses one. */
+export async function endSession(auth: AuthConfig): Promise<void> {
+  const baseUrl = normalizeBaseUrl(auth.baseUrl);
+  if (!isSameOrigin(baseUrl)) {
+    return;
+  }
+  sessions.delete(baseUrl);
+  await fetch(`${baseUrl}/session`, { method: "DELETE" });
+}
+
 export function ensureAuthConfig(auth: AuthConfig): void {
   if (!auth.baseUrl.trim()) {
     throw new Error("Base URL is required.");
@@ -103,34 +170,249 @@ export function toPathSegment(value: string): string {
   return encodeURIComponent(value.trim());
 }
 
-async function createCanonical(
-  timestamp: string,
-  method: HttpMethod,
-  path: string,
-  nonce: string,
-  bodyString: string,
-): Promise<string> {
-  const bodyHash = await sha256Hex(encoder.encode(bodyString));
-  return `${timestamp}\n${method}\n${path}\n${nonce}\n${bodyHash}`;
+type CanonicalInput = {
+  timestamp: string;
+  method: HttpMethod;
+  host: string;
+  path: string;
+  contentType: string;
+  nonce: string;
+  keyId: string;
+  // Sent as well and listed in `X-Signed-Headers`.
+  signedHeaders: Record<string, string>;
+  bodyString: string;
+};
+
+function isSameOrigin(baseUrl: string): boolean {
+  return (
+    typeof window !== "undefined" &&
+    new URL(baseUrl).origin === window.location.origin
+  );
+}
+
+// Reuses the open session unless it belongs to another client, lacks the
+// admin scope an `/admin` request needs, or is `stale`. Concurrent callers
+// share a single login.
+function ensureSession(
+  auth: AuthConfig,
+  baseUrl: string,
+  admin: boolean,
+  stale?: Promise<void>,
+): Promise<void> {
+  const clientId = auth.clientId.trim();
+  const current = sessions.get(baseUrl);
+  if (
+    current &&
+    current.clientId === clientId &&
+    (current.admin || !admin) &&
+    current.ready !== stale
+  ) {
+    return current.ready;
+  }
+
+  const ready = openSession(auth, baseUrl, admin);
+  sessions.set(baseUrl, { clientId, admin, ready });
+  ready.catch(() => {
+    if (sessions.get(baseUrl)?.ready === ready) {
+      sessions.delete(baseUrl);
+    }
+  });
+  return ready;
+}
+
+// The admin scope is only asked for once the console makes an `/admin`
+// request, so browsing with an admin key does not hold admin rights.
+async function openSession(
+  auth: AuthConfig,
+  baseUrl: string,
+  admin: boolean,
+): Promise<void> {
+  const clientId = auth.clientId.trim();
+  const scopes = admin ? ["read", "write", "admin"] : ["read", "write"];
+  const { challenge } = await postJson<{ challenge: string }>(
+    `${baseUrl}/session/challenge`,
+    { client_id: clientId },
+  );
+  const canonical = [
+    "session",
+    challenge,
+    new URL(baseUrl).host.toLowerCase(),
+    clientId,
+    scopes.join(" "),
+  ].join("\n");
+  const signature = await signCanonical(auth.privateKeyPem, canonical);
+  const response = await fetch(`${baseUrl}/session`, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify({
+      client_id: clientId,
+      challenge,
+      signature,
+      scopes,
+    }),
+  });
+  if (!response.ok) {
+    throw new Error(`HTTP ${response.status}: ${await response.text()}`);
+  }
+}
+
+async function postJson<TResponse>(
+  url: string,
+  body: unknown,
+): Promise<TResponse> {
+  const response = await fetch(url, {
+    method: "POST",
+    headers: { "Content-Type": "application/json" },
+    body: JSON.stringify(body),
+  });
+  if (!response.ok) {
+    throw new Error(`HTTP ${response.status}: ${await response.text()}`);
+  }
+  return (await response.json()) as TResponse;
+}
+
+async function signatureHeaders(
+  auth: AuthConfig,
+  input: CanonicalInput,
+): Promise<Record<string, string>> {
+  const signature = await signCanonical(
+    auth.privateKeyPem,
+    await createCanonical(input),
+  );
+  const headers: Record<string, string> = {
+    "X-Client-Id": auth.clientId.trim(),
+    "X-Timestamp": input.timestamp,
+    "X-Nonce": input.nonce,
+    "X-Signature-Version": "2",
+    "X-Key-Id": input.keyId,
+    "X-Signature": signature,
+    ...input.signedHeaders,
+  };
+  const signedNames = Object.keys(input.signedHeaders);
+  if (signedNames.length > 0) {
+    headers["X-Signed-Headers"] = signedNames.join(",");
+  }
+  if (input.contentType) {
+    headers["Content-Type"] = input.contentType;
+  }
+  return headers;
+}
+
+// Signature version 2; see `CanonicalRequestV2` in the server's crypto.rs.
+async function createCanonical(input: CanonicalInput): Promise<string> {
+  const bodyHash = await sha256Hex(encoder.encode(input.bodyString));
+  return [
+    "v2",
+    input.timestamp,
+    input.method,
+    input.host.toLowerCase(),
+    input.path,
+    input.contentType,
+    input.nonce,
+    input.keyId,
+    ...Object.entries(input.signedHeaders).map(
+      ([name, value]) => `${name.toLowerCase()}:${value.trim()}`,
+    ),
+    bodyHash,
+  ].join("\n");
+}
+
+type SigningKey = {
+  key: CryptoKey;
+  params: AlgorithmIdentifier | EcdsaParams;
+};
+
+// Client keys are Ed25519 or ECDSA P-256 PKCS#8; try them in that order.
+async function importSigningKey(
+  privateKeyPem: string,
+  extractable: boolean,
+): Promise<SigningKey> {
+  const der = pemToBytes(privateKeyPem);
+  try {
+    const key = await crypto.subtle.importKey(
+      "pkcs8",
+      der,
+      "Ed25519",
+      extractable,
+      ["sign"],
+    );
+    return { key, params: "Ed25519" };
+  } catch {
+    const key = await crypto.subtle.importKey(
+      "pkcs8",
+      der,
+      { name: "ECDSA", namedCurve: "P-256" },
+      extractable,
+      ["sign"],
+    );
+    return { key, params: { name: "ECDSA", hash: "SHA-256" } };
+  }
+}
+
+// First 16 hex digits of the SHA-256 of the raw public key: 32 bytes for
+// Ed25519, the uncompressed point for P-256.
+async function keyIdFromPrivateKey(privateKeyPem: string): Promise<string> {
+  const { key } = await importSigningKey(privateKeyPem, true);
+  const jwk = await crypto.subtle.exportKey("jwk", key);
+  const x = base64UrlToBytes(jwk.x ?? "");
+  const publicKey =
+    jwk.kty === "EC"
+      ? Uint8Array.from([4, ...x, ...base64UrlToBytes(jwk.y ?? "")])
+      : x;
+  return (await sha256Hex(publicKey)).slice(0, 16);
 }
 
 async function signCanonical(
   privateKeyPem: string,
   canonical: string,
 ): Promise<string> {
-  const privateKey = await crypto.subtle.importKey(
-    "pkcs8",
-    pemToBytes(privateKeyPem),
+  const { key, params } = await importSigningKey(privateKeyPem, false);
+  const signature = await crypto.subtle.sign(
+    params,
+    key,
+    toArrayBuffer(encoder.encode(canonical)),
+  );
+  return bytesToBase64(new Uint8Array(signature));
+}
+
+// See `CanonicalResponse` in the server's crypto.rs.
+async function verifyServerSignature(
+  serverKey: string,
+  request: { nonce: string; method: HttpMethod; path: string },
+  response: Response,
+  body: Uint8Array,
+): Promise<void> {
+  const signature = response.headers.get("X-Server-Signature");
+  const timestamp = response.headers.get("X-Server-Timestamp");
+  if (!signature || !timestamp) {
+    throw new Error("Response is missing X-Server-Signature.");
+  }
+
+  const bodyHash = await sha256Hex(body);
+  const canonical = [
+    request.nonce,
+    request.method,
+    request.path,
+    timestamp,
+    response.status,
+    bodyHash,
+  ].join("\n");
+  const publicKey = await crypto.subtle.importKey(
+    "raw",
+    toArrayBuffer(base64ToBytes(serverKey.trim())),
     "Ed25519",
     false,
-    ["sign"],
+    ["verify"],
   );
-  const signature = await crypto.subtle.sign(
+  const valid = await crypto.subtle.verify(
     "Ed25519",
-    privateKey,
+    publicKey,
+    toArrayBuffer(base64ToBytes(signature)),
     toArrayBuffer(encoder.encode(canonical)),
   );
-  return bytesToBase64(new Uint8Array(signature));
+  if (!valid) {
+    throw new Error("Response signature does not match the server key.");
+  }
 }
 
 async function sha256Hex(input: Uint8Array): Promise<string> {
@@ -171,6 +453,14 @@ function bytesToBase64(bytes: Uint8Array): string {
   return btoa(binary);
 }
 
+function base64ToBytes(value: string): Uint8Array {
+  return Uint8Array.from(atob(value), (char) => char.charCodeAt(0));
+}
+
+function base64UrlToBytes(value: string): Uint8Array {
+  return base64ToBytes(value.replaceAll("-", "+").replaceAll("_", "/"));
+}
+
 function bytesToHex(bytes: Uint8Array): string {
   return Array.from(bytes, (value) => value.toString(16).padStart(2, "0")).join(
     "",
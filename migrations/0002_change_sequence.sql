@@ -0,0 +1,68 @@
+-- Bumped by triggers on every change to data the read cache holds, so each
+-- server instance can tell when another one has written.
+CREATE TABLE IF NOT EXISTS change_seq (
+    id   INTEGER PRIMARY KEY CHECK (id = 1),
+    seq  INTEGER NOT NULL
+);
+
+INSERT OR IGNORE INTO change_seq (id, seq) VALUES (1, 0);
+
+CREATE TRIGGER IF NOT EXISTS clients_insert_change_seq AFTER INSERT ON clients
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS clients_update_change_seq AFTER UPDATE ON clients
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS clients_delete_change_seq AFTER DELETE ON clients
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS projects_insert_change_seq AFTER INSERT ON projects
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS projects_update_change_seq AFTER UPDATE ON projects
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS projects_delete_change_seq AFTER DELETE ON projects
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS configs_insert_change_seq AFTER INSERT ON configs
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS configs_update_change_seq AFTER UPDATE ON configs
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS configs_delete_change_seq AFTER DELETE ON configs
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS client_permissions_insert_change_seq AFTER INSERT ON client_permissions
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS client_permissions_update_change_seq AFTER UPDATE ON client_permissions
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS client_permissions_delete_change_seq AFTER DELETE ON client_permissions
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
@@ -0,0 +1,44 @@
+-- Every config write bumps its project's revision and is logged in
+-- config_changes under that revision, which backs the change feed.
+ALTER TABLE projects ADD COLUMN revision INTEGER NOT NULL DEFAULT 0;
+
+-- Lowest revision the change log is complete from. Clients that last synced
+-- before it have to refetch the full config list.
+ALTER TABLE projects ADD COLUMN history_start INTEGER NOT NULL DEFAULT 0;
+
+UPDATE projects SET revision = 1, history_start = 1
+WHERE EXISTS (SELECT 1 FROM configs WHERE configs.project_id = projects.id);
+
+CREATE TABLE IF NOT EXISTS config_changes (
+    project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    revision    INTEGER NOT NULL,
+    key         TEXT NOT NULL,
+    op          TEXT NOT NULL,
+    value       TEXT,
+    version     INTEGER NOT NULL,
+    changed_at  TEXT NOT NULL DEFAULT (datetime('now')),
+    PRIMARY KEY (project_id, revision)
+);
+
+CREATE TRIGGER IF NOT EXISTS configs_insert_revision AFTER INSERT ON configs
+BEGIN
+    UPDATE projects SET revision = revision + 1 WHERE id = NEW.project_id;
+    INSERT INTO config_changes (project_id, revision, key, op, value, version)
+    SELECT id, revision, NEW.key, 'set', NEW.value, NEW.version FROM projects WHERE id = NEW.project_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS configs_update_revision AFTER UPDATE ON configs
+BEGIN
+    UPDATE projects SET revision = revision + 1 WHERE id = NEW.project_id;
+    INSERT INTO config_changes (project_id, revision, key, op, value, version)
+    SELECT id, revision, NEW.key, 'set', NEW.value, NEW.version FROM projects WHERE id = NEW.project_id;
+END;
+
+-- Selecting through projects skips the log when the project itself is being
+-- deleted and the configs go with it.
+CREATE TRIGGER IF NOT EXISTS configs_delete_revision AFTER DELETE ON configs
+BEGIN
+    UPDATE projects SET revision = revision + 1 WHERE id = OLD.project_id;
+    INSERT INTO config_changes (project_id, revision, key, op, value, version)
+    SELECT id, revision, OLD.key, 'delete', NULL, OLD.version FROM projects WHERE id = OLD.project_id;
+END;
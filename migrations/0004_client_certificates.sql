@@ -0,0 +1,27 @@
+-- Client certificates issued by the internal CA. A TLS client certificate is
+-- only accepted if its fingerprint is here, unexpired and not revoked.
+CREATE TABLE IF NOT EXISTS client_certificates (
+    serial       TEXT PRIMARY KEY,
+    client_id    TEXT NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    fingerprint  TEXT NOT NULL UNIQUE,
+    issued_at    INTEGER NOT NULL,
+    expires_at   INTEGER NOT NULL,
+    revoked_at   INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_client_certificates_client_id ON client_certificates(client_id);
+
+CREATE TRIGGER IF NOT EXISTS client_certificates_insert_change_seq AFTER INSERT ON client_certificates
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS client_certificates_update_change_seq AFTER UPDATE ON client_certificates
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
+
+CREATE TRIGGER IF NOT EXISTS client_certificates_delete_change_seq AFTER DELETE ON client_certificates
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+END;
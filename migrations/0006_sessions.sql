@@ -0,0 +1,19 @@
+-- Short-lived bearer sessions for the web console. A client proves its key by
+-- signing a one-time challenge; only a hash of the issued token is stored.
+CREATE TABLE IF NOT EXISTS session_challenges (
+    challenge    TEXT PRIMARY KEY,
+    client_id    TEXT NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    expires_at   INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    id           TEXT PRIMARY KEY,
+    client_id    TEXT NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    token_hash   TEXT NOT NULL UNIQUE,
+    scopes       TEXT NOT NULL,
+    created_at   INTEGER NOT NULL,
+    expires_at   INTEGER NOT NULL,
+    revoked_at   INTEGER
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_client_id ON sessions(client_id);
@@ -0,0 +1,16 @@
+-- Optional TOTP second factor for admin clients, plus single-use recovery
+-- codes stored as SHA-256 hashes. `last_step` rejects a code seen before.
+CREATE TABLE IF NOT EXISTS client_otp (
+    client_id    TEXT PRIMARY KEY REFERENCES clients(id) ON DELETE CASCADE,
+    secret       TEXT NOT NULL,
+    enabled_at   INTEGER,
+    last_step    INTEGER NOT NULL DEFAULT 0,
+    failures     INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes (
+    client_id    TEXT NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    code_hash    TEXT NOT NULL,
+    used_at      INTEGER,
+    PRIMARY KEY (client_id, code_hash)
+);
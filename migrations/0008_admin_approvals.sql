@@ -0,0 +1,22 @@
+-- Admin actions that wait for approval by several admins before they run.
+-- `operation` is the JSON of the request to replay once approved.
+CREATE TABLE IF NOT EXISTS pending_actions (
+    id                  TEXT PRIMARY KEY,
+    operation           TEXT NOT NULL,
+    requested_by        TEXT NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    required_approvals  INTEGER NOT NULL,
+    status              TEXT NOT NULL DEFAULT 'pending',
+    created_at          INTEGER NOT NULL,
+    expires_at          INTEGER NOT NULL,
+    resolved_at         INTEGER,
+    resolved_by         TEXT REFERENCES clients(id) ON DELETE SET NULL
+);
+
+CREATE TABLE IF NOT EXISTS pending_action_approvals (
+    action_id    TEXT NOT NULL REFERENCES pending_actions(id) ON DELETE CASCADE,
+    client_id    TEXT NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    approved_at  INTEGER NOT NULL,
+    PRIMARY KEY (action_id, client_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_pending_actions_status ON pending_actions(status);
@@ -0,0 +1,3 @@
+-- Comma-separated CIDR ranges a client may connect from. Empty allows any
+-- address.
+ALTER TABLE clients ADD COLUMN allowed_networks TEXT NOT NULL DEFAULT '';
@@ -0,0 +1,39 @@
+CREATE TABLE IF NOT EXISTS clients (
+    id          TEXT PRIMARY KEY,
+    name        TEXT NOT NULL,
+    public_key  TEXT NOT NULL,
+    is_admin    BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at  TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')
+);
+
+CREATE TABLE IF NOT EXISTS projects (
+    id          TEXT PRIMARY KEY,
+    name        TEXT NOT NULL UNIQUE,
+    description TEXT NOT NULL DEFAULT '',
+    created_at  TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')
+);
+
+CREATE TABLE IF NOT EXISTS configs (
+    id          TEXT PRIMARY KEY,
+    project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    key         TEXT NOT NULL,
+    value       TEXT NOT NULL,
+    version     BIGINT NOT NULL DEFAULT 1,
+    updated_at  TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    UNIQUE(project_id, key)
+);
+
+CREATE TABLE IF NOT EXISTS client_permissions (
+    client_id   TEXT NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    can_read    BOOLEAN NOT NULL DEFAULT TRUE,
+    can_write   BOOLEAN NOT NULL DEFAULT FALSE,
+    PRIMARY KEY (client_id, project_id)
+);
+
+CREATE TABLE IF NOT EXISTS used_nonces (
+    client_id   TEXT NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    nonce       TEXT NOT NULL,
+    created_at  BIGINT NOT NULL,
+    PRIMARY KEY (client_id, nonce)
+);
@@ -0,0 +1,27 @@
+-- Bumped by triggers on every change to data the read cache holds, so each
+-- server instance can tell when another one has written.
+CREATE TABLE IF NOT EXISTS change_seq (
+    id   INTEGER PRIMARY KEY CHECK (id = 1),
+    seq  BIGINT NOT NULL
+);
+
+INSERT INTO change_seq (id, seq) VALUES (1, 0) ON CONFLICT DO NOTHING;
+
+CREATE OR REPLACE FUNCTION bump_change_seq() RETURNS trigger AS $$
+BEGIN
+    UPDATE change_seq SET seq = seq + 1 WHERE id = 1;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER clients_change_seq AFTER INSERT OR UPDATE OR DELETE ON clients
+    FOR EACH STATEMENT EXECUTE FUNCTION bump_change_seq();
+
+CREATE TRIGGER projects_change_seq AFTER INSERT OR UPDATE OR DELETE ON projects
+    FOR EACH STATEMENT EXECUTE FUNCTION bump_change_seq();
+
+CREATE TRIGGER configs_change_seq AFTER INSERT OR UPDATE OR DELETE ON configs
+    FOR EACH STATEMENT EXECUTE FUNCTION bump_change_seq();
+
+CREATE TRIGGER client_permissions_change_seq AFTER INSERT OR UPDATE OR DELETE ON client_permissions
+    FOR EACH STATEMENT EXECUTE FUNCTION bump_change_seq();
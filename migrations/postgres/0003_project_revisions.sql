@@ -0,0 +1,48 @@
+-- Every config write bumps its project's revision and is logged in
+-- config_changes under that revision, which backs the change feed.
+ALTER TABLE projects ADD COLUMN revision BIGINT NOT NULL DEFAULT 0;
+
+-- Lowest revision the change log is complete from. Clients that last synced
+-- before it have to refetch the full config list.
+ALTER TABLE projects ADD COLUMN history_start BIGINT NOT NULL DEFAULT 0;
+
+UPDATE projects SET revision = 1, history_start = 1
+WHERE EXISTS (SELECT 1 FROM configs WHERE configs.project_id = projects.id);
+
+CREATE TABLE IF NOT EXISTS config_changes (
+    project_id  TEXT NOT NULL REFERENCES projects(id) ON DELETE CASCADE,
+    revision    BIGINT NOT NULL,
+    key         TEXT NOT NULL,
+    op          TEXT NOT NULL,
+    value       TEXT,
+    version     BIGINT NOT NULL,
+    changed_at  TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    PRIMARY KEY (project_id, revision)
+);
+
+-- The row lock taken by the UPDATE orders concurrent writers, so revisions
+-- become visible in the order they were assigned. The INSERT finds no
+-- project when the configs are being deleted along with it.
+CREATE OR REPLACE FUNCTION log_config_change() RETURNS trigger AS $$
+DECLARE
+    changed configs%ROWTYPE;
+BEGIN
+    IF TG_OP = 'DELETE' THEN
+        changed := OLD;
+    ELSE
+        changed := NEW;
+    END IF;
+
+    UPDATE projects SET revision = revision + 1 WHERE id = changed.project_id;
+    INSERT INTO config_changes (project_id, revision, key, op, value, version)
+    SELECT id, revision, changed.key,
+           CASE WHEN TG_OP = 'DELETE' THEN 'delete' ELSE 'set' END,
+           CASE WHEN TG_OP = 'DELETE' THEN NULL ELSE changed.value END,
+           changed.version
+    FROM projects WHERE id = changed.project_id;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER configs_revision AFTER INSERT OR UPDATE OR DELETE ON configs
+    FOR EACH ROW EXECUTE FUNCTION log_config_change();
@@ -0,0 +1,15 @@
+-- Client certificates issued by the internal CA. A TLS client certificate is
+-- only accepted if its fingerprint is here, unexpired and not revoked.
+CREATE TABLE IF NOT EXISTS client_certificates (
+    serial       TEXT PRIMARY KEY,
+    client_id    TEXT NOT NULL REFERENCES clients(id) ON DELETE CASCADE,
+    fingerprint  TEXT NOT NULL UNIQUE,
+    issued_at    BIGINT NOT NULL,
+    expires_at   BIGINT NOT NULL,
+    revoked_at   BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS idx_client_certificates_client_id ON client_certificates(client_id);
+
+CREATE TRIGGER client_certificates_change_seq AFTER INSERT OR UPDATE OR DELETE ON client_certificates
+    FOR EACH STATEMENT EXECUTE FUNCTION bump_change_seq();
@@ -0,0 +1,3 @@
+-- Clients may use ECDSA P-256 keys as well as Ed25519. Existing keys are all
+-- Ed25519.
+ALTER TABLE clients ADD COLUMN key_algorithm TEXT NOT NULL DEFAULT 'ed25519';
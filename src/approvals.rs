@@ -0,0 +1,227 @@
+//! M-of-N approval for sensitive admin actions. A gated request is recorded
+//! as a pending action and runs once `APPROVALS_REQUIRED` distinct admins,
+//! the requester included, have approved it.
+
+use uuid::Uuid;
+
+use crate::{
+    AppState,
+    auth::{self, AuthenticatedClient},
+    error::{AppError, AppResult},
+    models::{AdminOperation, PendingAction, PendingActionStatus},
+};
+
+/// Records `operation` as pending if the approval policy gates it. `None`
+/// means it is not gated and the caller should run it right away.
+pub async fn hold(
+    state: &AppState,
+    requester: &AuthenticatedClient,
+    operation: AdminOperation,
+) -> AppResult<Option<PendingAction>> {
+    if !is_gated(state, &operation).await? {
+        return Ok(None);
+    }
+
+    let policy = &state.config.approvals;
+    let now = auth::current_unix_timestamp()?;
+    let action = PendingAction {
+        id: Uuid::new_v4(),
+        action: operation.action(),
+        operation,
+        requested_by: requester.id,
+        approvals: vec![requester.id],
+        required_approvals: policy.required,
+        status: PendingActionStatus::Pending,
+        created_at: now,
+        expires_at: now.saturating_add(i64::try_from(policy.ttl_seconds).unwrap_or(i64::MAX)),
+        resolved_at: None,
+        resolved_by: None,
+    };
+    state.db.create_pending_action(&action).await?;
+    tracing::info!(
+        "{} {} requested by {}, awaiting {} approvals",
+        action.action.as_str(),
+        action.id,
+        requester.id,
+        action.required_approvals
+    );
+    Ok(Some(action))
+}
+
+/// Adds `approver`'s approval and runs the action once it has enough. If the
+/// operation fails the action is marked failed and the error returned.
+pub async fn approve(
+    state: &AppState,
+    approver: &AuthenticatedClient,
+    action_id: &Uuid,
+) -> AppResult<PendingAction> {
+    let now = auth::current_unix_timestamp()?;
+    state.db.expire_pending_actions(now).await?;
+    if !state
+        .db
+        .approve_pending_action(action_id, &approver.id, now)
+        .await?
+    {
+        let action = load(state, action_id).await?;
+        return Err(not_pending(&action).unwrap_or_else(|| {
+            AppError::Conflict(String::from("already approved by this admin"))
+        }));
+    }
+
+    let action = load(state, action_id).await?;
+    if current_admins(state, &action.approvals).await? < action.required_approvals
+        || !state
+            .db
+            .resolve_pending_action(
+                action_id,
+                PendingActionStatus::Pending,
+                PendingActionStatus::Executed,
+                now,
+                Some(&approver.id),
+            )
+            .await?
+    {
+        return Ok(action);
+    }
+
+    if let Err(error) = execute(state, &action.operation).await {
+        tracing::warn!(
+            "approved {} {} failed: {error}",
+            action.action.as_str(),
+            action.id
+        );
+        state
+            .db
+            .resolve_pending_action(
+                action_id,
+                PendingActionStatus::Executed,
+                PendingActionStatus::Failed,
+                now,
+                Some(&approver.id),
+            )
+            .await?;
+        return Err(error);
+    }
+
+    tracing::info!(
+        "{} {} executed after approval by {}",
+        action.action.as_str(),
+        action.id,
+        approver.id
+    );
+    load(state, action_id).await
+}
+
+pub async fn reject(
+    state: &AppState,
+    rejecter: &AuthenticatedClient,
+    action_id: &Uuid,
+) -> AppResult<PendingAction> {
+    let now = auth::current_unix_timestamp()?;
+    state.db.expire_pending_actions(now).await?;
+    if !state
+        .db
+        .resolve_pending_action(
+            action_id,
+            PendingActionStatus::Pending,
+            PendingActionStatus::Rejected,
+            now,
+            Some(&rejecter.id),
+        )
+        .await?
+    {
+        let action = load(state, action_id).await?;
+        return Err(not_pending(&action)
+            .unwrap_or_else(|| AppError::Conflict(String::from("action changed, try again"))));
+    }
+
+    tracing::info!("pending action {action_id} rejected by {}", rejecter.id);
+    load(state, action_id).await
+}
+
+/// How many of `approvals` still belong to an admin. Approvals from clients
+/// deleted since then no longer count towards the threshold.
+async fn current_admins(state: &AppState, approvals: &[Uuid]) -> AppResult<u32> {
+    let mut admins = 0;
+    for client_id in approvals {
+        if state
+            .db
+            .get_client_by_id(client_id)
+            .await?
+            .is_some_and(|client| client.is_admin)
+        {
+            admins += 1;
+        }
+    }
+    Ok(admins)
+}
+
+async fn is_gated(state: &AppState, operation: &AdminOperation) -> AppResult<bool> {
+    let policy = &state.config.approvals;
+    if !policy.gates(operation.action()) {
+        return Ok(false);
+    }
+
+    match operation {
+        AdminOperation::SetPermission { project_id, .. }
+        | AdminOperation::RevokePermission { project_id, .. } => {
+            let project = state.db.get_project_by_id(project_id).await?;
+            Ok(project.is_some_and(|project| policy.production_projects.contains(&project.name)))
+        }
+        AdminOperation::CreateAdmin { .. } | AdminOperation::DeleteProject { .. } => Ok(true),
+    }
+}
+
+async fn execute(state: &AppState, operation: &AdminOperation) -> AppResult<()> {
+    match operation {
+        AdminOperation::CreateAdmin {
+            name,
+            public_key,
+            key_algorithm,
+        } => {
+            state
+                .db
+                .create_client(name, public_key, *key_algorithm, true)
+                .await?;
+        }
+        AdminOperation::DeleteProject { project_id } => {
+            if !state.db.delete_project(project_id).await? {
+                return Err(AppError::NotFound(String::from("project not found")));
+            }
+        }
+        AdminOperation::SetPermission {
+            client_id,
+            project_id,
+            can_read,
+            can_write,
+        } => {
+            state
+                .db
+                .set_permission(client_id, project_id, *can_read, *can_write)
+                .await?;
+        }
+        AdminOperation::RevokePermission {
+            client_id,
+            project_id,
+        } => {
+            if !state.db.delete_permission(client_id, project_id).await? {
+                return Err(AppError::NotFound(String::from("permission not found")));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn load(state: &AppState, action_id: &Uuid) -> AppResult<PendingAction> {
+    state
+        .db
+        .get_pending_action(action_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("pending action not found")))
+}
+
+fn not_pending(action: &PendingAction) -> Option<AppError> {
+    (action.status != PendingActionStatus::Pending)
+        .then(|| AppError::Conflict(format!("action is {}", action.status.as_str())))
+}
@@ -1,49 +1,103 @@
+use std::net::IpAddr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 use axum::{
     body::{Body, to_bytes},
-    extract::{Request, State},
-    http::request::Parts,
+    extract::{ConnectInfo, OriginalUri, Request, State},
+    http::{Uri, header, request::Parts},
     middleware::Next,
     response::Response,
 };
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
 use uuid::Uuid;
 
 use crate::{
-    AppState, crypto,
+    AppState,
+    crypto::{self, SignatureVersion},
     error::{AppError, AppResult},
+    http_signature::{self, HttpSignature, Message},
+    models::{Client, SessionScope},
+    network::{self, IpNetwork},
+    otp, session,
+    tls::PeerInfo,
 };
 
 #[derive(Debug, Clone)]
 pub struct AuthenticatedClient {
     pub id: Uuid,
     pub is_admin: bool,
+    /// Serial of the mTLS certificate that authenticated the request, if the
+    /// request was not signed.
+    pub certificate: Option<String>,
+    /// Id of the console session that authenticated the request, if any.
+    pub session: Option<Uuid>,
+    /// The `X-OTP` header, checked by [`require_admin_otp`].
+    pub otp: Option<String>,
+    /// Where the request came from, see [`request_source`].
+    pub source: Option<IpAddr>,
 }
 
+/// Accepts `X-Signature` headers, an RFC 9421 `Signature-Input`, a session
+/// token, or an mTLS client certificate, tried in that order. Clients with
+/// allowed networks must also connect from one of them.
 pub async fn require_client_signature(
     State(state): State<AppState>,
     request: Request,
     next: Next,
 ) -> AppResult<Response> {
     let (parts, body) = request.into_parts();
-    let client_id = parse_client_id(&parts)?;
-    let signature = parse_header_value(&parts, "X-Signature")?;
-    let timestamp = parse_timestamp(&parts)?;
-    let nonce = parse_nonce(&parts)?;
+    let source = request_source(&state, &parts);
+    let client_certificate = parts
+        .extensions
+        .get::<ConnectInfo<PeerInfo>>()
+        .and_then(|info| info.0.client_certificate.clone());
+    let body_bytes = read_body(body, state.config.max_body_size_bytes).await?;
 
-    let now_timestamp = validate_timestamp(timestamp, state.config.max_clock_drift_seconds)?;
+    let mut client = if parts.headers.contains_key("X-Signature") {
+        authenticate_signature(&state, &parts, &body_bytes, source).await?
+    } else if parts.headers.contains_key("Signature-Input") {
+        authenticate_http_signature(&state, &parts, &body_bytes, source).await?
+    } else if let Some(token) = session::token_from_headers(&parts.headers) {
+        authenticate_session(&state, &parts, &token, source).await?
+    } else if let Some(fingerprint) = client_certificate {
+        authenticate_certificate(&state, &fingerprint, source).await?
+    } else {
+        // Reports the first missing `X-*` header.
+        authenticate_signature(&state, &parts, &body_bytes, source).await?
+    };
 
-    let body_bytes = to_bytes(body, state.config.max_body_size_bytes)
-        .await
-        .map_err(|_| AppError::BadRequest(String::from("request body exceeds allowed size")))?;
+    client.otp = parts
+        .headers
+        .get(otp::OTP_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    client.source = source;
+
+    let mut request = Request::from_parts(parts, Body::from(body_bytes));
+    request.extensions_mut().insert(client);
+    Ok(next.run(request).await)
+}
+
+async fn authenticate_signature(
+    state: &AppState,
+    parts: &Parts,
+    body_bytes: &[u8],
+    source: Option<IpAddr>,
+) -> AppResult<AuthenticatedClient> {
+    let client_id = parse_client_id(parts)?;
+    let signature = parse_header_value(parts, "X-Signature")?;
+    let version = parse_signature_version(parts, state.config.min_signature_version)?;
+    let timestamp = parse_timestamp(parts)?;
+    let nonce = parse_nonce(parts)?;
 
-    let method = parts.method.as_str().to_owned();
-    let path_and_query = parts.uri.path_and_query().map_or_else(
-        || parts.uri.path().to_owned(),
-        |value| value.as_str().to_owned(),
-    );
-    let canonical =
-        crypto::canonical_string(timestamp, &method, &path_and_query, &nonce, &body_bytes);
+    let now_timestamp = validate_timestamp(timestamp, state.config.max_clock_drift_seconds)?;
+
+    let method = parts.method.as_str();
+    let uri = original_uri(parts);
+    let path_and_query = uri
+        .path_and_query()
+        .map_or_else(|| uri.path().to_owned(), |value| value.as_str().to_owned());
 
     let client = state
         .db
@@ -51,19 +105,312 @@ pub async fn require_client_signature(
         .await?
         .ok_or_else(|| AppError::Unauthorized(String::from("invalid client credentials")))?;
 
-    crypto::verify_signature(&client.public_key, &canonical, &signature)?;
+    let canonical = match version {
+        SignatureVersion::V1 => {
+            check_signed_headers(&state.config.signed_headers, parts, |_| false)?;
+            crypto::canonical_string(timestamp, method, &path_and_query, &nonce, body_bytes)
+        }
+        SignatureVersion::V2 => {
+            let key_id = parse_header_value(parts, "X-Key-Id")?;
+            if key_id != crypto::key_id(&client.public_key)? {
+                return Err(AppError::Unauthorized(String::from(
+                    "key id does not match the client's current key",
+                )));
+            }
+            let signed_headers = parse_signed_headers(parts)?;
+            check_signed_headers(&state.config.signed_headers, parts, |name| {
+                matches!(name, "host" | "content-type")
+                    || signed_headers.iter().any(|(signed, _)| signed == name)
+            })?;
+            crypto::CanonicalRequestV2 {
+                timestamp,
+                method,
+                host: &request_host(parts, uri)?,
+                path_and_query: &path_and_query,
+                content_type: parts
+                    .headers
+                    .get(header::CONTENT_TYPE)
+                    .and_then(|value| value.to_str().ok())
+                    .unwrap_or_default(),
+                nonce: &nonce,
+                key_id: &key_id,
+                signed_headers: &signed_headers,
+                body: body_bytes,
+            }
+            .canonical_string()
+        }
+    };
+
+    crypto::verify_signature(
+        client.key_algorithm,
+        &client.public_key,
+        &canonical,
+        &signature,
+    )?;
+    check_source(&client, source)?;
     state
+        .nonces
+        .register(&client_id, &nonce, now_timestamp)
+        .await?;
+
+    Ok(AuthenticatedClient {
+        id: client.id,
+        is_admin: client.is_admin,
+        certificate: None,
+        session: None,
+        otp: None,
+        source: None,
+    })
+}
+
+/// RFC 9421: `keyid` names the client, `created` goes through the clock
+/// drift check and `nonce` through replay protection. An `expires` must come
+/// after `created` and at most the allowed drift later. With
+/// `MIN_SIGNATURE_VERSION=2` the content type must be covered too.
+async fn authenticate_http_signature(
+    state: &AppState,
+    parts: &Parts,
+    body_bytes: &[u8],
+    source: Option<IpAddr>,
+) -> AppResult<AuthenticatedClient> {
+    let signature = HttpSignature::from_headers(&parts.headers)?;
+    let client_id = Uuid::parse_str(&signature.key_id)
+        .map_err(|_| AppError::Unauthorized(String::from("keyid must be a client id")))?;
+    let nonce = signature
+        .nonce
+        .as_deref()
+        .ok_or_else(|| AppError::Unauthorized(String::from("missing nonce parameter")))?;
+    if nonce.is_empty() || nonce.len() > 128 {
+        return Err(AppError::Unauthorized(String::from(
+            "invalid nonce length (must be 1..=128)",
+        )));
+    }
+    let now_timestamp =
+        validate_timestamp(signature.created, state.config.max_clock_drift_seconds)?;
+    if let Some(expires) = signature.expires {
+        if expires <= signature.created {
+            return Err(AppError::Unauthorized(String::from(
+                "expires must be after created",
+            )));
+        }
+        // `created` already limits acceptance to the drift window; a longer
+        // `expires` would promise a lifetime the server does not honour.
+        if expires.saturating_sub(signature.created) > state.config.max_clock_drift_seconds {
+            return Err(AppError::Unauthorized(String::from(
+                "signature lifetime exceeds the allowed clock drift",
+            )));
+        }
+        if expires <= now_timestamp {
+            return Err(AppError::Unauthorized(String::from(
+                "signature has expired",
+            )));
+        }
+    }
+
+    let uri = original_uri(parts);
+    let message = Message {
+        method: parts.method.as_str(),
+        scheme: if state.config.tls_enabled() {
+            "https"
+        } else {
+            "http"
+        },
+        authority: &request_host(parts, uri)?,
+        path: uri.path(),
+        query: uri.query(),
+        headers: &parts.headers,
+    };
+    signature.check_coverage(&message, !body_bytes.is_empty())?;
+    // `check_coverage` already demands the authority.
+    let covered = |name: &str| name == "host" || signature.components.iter().any(|c| c == name);
+    check_signed_headers(&state.config.signed_headers, parts, covered)?;
+    if state.config.min_signature_version >= SignatureVersion::V2 {
+        // What version 2 binds on top of the target.
+        check_signed_headers(&[String::from("content-type")], parts, covered)?;
+    }
+    if signature.components.iter().any(|c| c == "content-digest") {
+        http_signature::verify_content_digest(&parts.headers, body_bytes)?;
+    }
+    let base = signature.signature_base(&message)?;
+
+    let client = state
         .db
-        .register_nonce(&client_id, &nonce, now_timestamp)
+        .get_client_by_id(&client_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized(String::from("invalid client credentials")))?;
+    if signature
+        .alg
+        .as_deref()
+        .is_some_and(|alg| alg != client.key_algorithm.http_signature_alg())
+    {
+        return Err(AppError::Unauthorized(String::from(
+            "signature algorithm does not match the client key",
+        )));
+    }
+    crypto::verify_signature(
+        client.key_algorithm,
+        &client.public_key,
+        &base,
+        &STANDARD.encode(&signature.signature),
+    )?;
+    check_source(&client, source)?;
+    state
+        .nonces
+        .register(&client_id, nonce, now_timestamp)
         .await?;
 
-    let mut request = Request::from_parts(parts, Body::from(body_bytes));
-    request.extensions_mut().insert(AuthenticatedClient {
+    Ok(AuthenticatedClient {
+        id: client.id,
+        is_admin: client.is_admin,
+        certificate: None,
+        session: None,
+        otp: None,
+        source: None,
+    })
+}
+
+/// Routers under `nest` see a prefix-stripped URI; clients sign the full path.
+pub fn original_uri(parts: &Parts) -> &Uri {
+    parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(&parts.uri, |original| &original.0)
+}
+
+/// The handshake already proved the certificate chains to the internal CA;
+/// this checks it is one we issued and still honour.
+async fn authenticate_certificate(
+    state: &AppState,
+    fingerprint: &str,
+    source: Option<IpAddr>,
+) -> AppResult<AuthenticatedClient> {
+    let certificate = state
+        .db
+        .get_client_certificate(fingerprint)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized(String::from("unknown client certificate")))?;
+    if certificate.revoked_at.is_some() {
+        return Err(AppError::Unauthorized(String::from(
+            "client certificate has been revoked",
+        )));
+    }
+    if certificate.expires_at <= current_unix_timestamp()? {
+        return Err(AppError::Unauthorized(String::from(
+            "client certificate has expired",
+        )));
+    }
+
+    let client = state
+        .db
+        .get_client_by_id(&certificate.client_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized(String::from("invalid client credentials")))?;
+    check_source(&client, source)?;
+    Ok(AuthenticatedClient {
         id: client.id,
         is_admin: client.is_admin,
+        certificate: Some(certificate.serial),
+        session: None,
+        otp: None,
+        source: None,
+    })
+}
+
+/// A session only reaches what its scopes allow; an admin client without the
+/// `admin` scope is treated as a regular client.
+async fn authenticate_session(
+    state: &AppState,
+    parts: &Parts,
+    token: &str,
+    source: Option<IpAddr>,
+) -> AppResult<AuthenticatedClient> {
+    let session = state
+        .db
+        .get_session_by_token(&crypto::sha256_hex(token.as_bytes()))
+        .await?
+        .ok_or_else(|| AppError::Unauthorized(String::from("invalid session token")))?;
+    if session.revoked_at.is_some() {
+        return Err(AppError::Unauthorized(String::from(
+            "session has been revoked",
+        )));
+    }
+    if session.expires_at <= current_unix_timestamp()? {
+        return Err(AppError::Unauthorized(String::from("session has expired")));
+    }
+    if !session::allows(
+        &session.scopes,
+        parts.method.as_str(),
+        original_uri(parts).path(),
+    ) {
+        return Err(AppError::Forbidden(String::from(
+            "session scopes do not allow this request",
+        )));
+    }
+
+    let client = state
+        .db
+        .get_client_by_id(&session.client_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized(String::from("invalid client credentials")))?;
+    check_source(&client, source)?;
+    Ok(AuthenticatedClient {
+        id: client.id,
+        is_admin: client.is_admin && session.scopes.contains(&SessionScope::Admin),
+        certificate: None,
+        session: Some(session.id),
+        otp: None,
+        source: None,
+    })
+}
+
+/// The peer address, or the address a trusted proxy forwarded the request
+/// for. `None` without connection info.
+pub fn request_source(state: &AppState, parts: &Parts) -> Option<IpAddr> {
+    let peer = parts.extensions.get::<ConnectInfo<PeerInfo>>()?;
+    Some(network::client_address(
+        peer.0.addr.ip(),
+        parts
+            .headers
+            .get(network::FORWARDED_FOR_HEADER)
+            .and_then(|value| value.to_str().ok()),
+        &state.config.trusted_proxies,
+    ))
+}
+
+/// Refuses a client outside its allowed networks. A request whose source is
+/// unknown is refused too, since the ranges cannot be checked.
+pub fn check_source(client: &Client, source: Option<IpAddr>) -> AppResult<()> {
+    if client.allowed_networks.is_empty() {
+        return Ok(());
+    }
+
+    let allowed = source.is_some_and(|ip| {
+        client
+            .allowed_networks
+            .iter()
+            .filter_map(|range| range.parse::<IpNetwork>().ok())
+            .any(|network| network.contains(ip))
     });
+    if !allowed {
+        tracing::warn!(
+            "client {} ({}) denied: source address {} is outside its allowed networks {}",
+            client.id,
+            client.name,
+            source.map_or_else(|| String::from("unknown"), |ip| ip.to_string()),
+            client.allowed_networks.join(", ")
+        );
+        return Err(AppError::Forbidden(String::from(
+            "source address is not allowed for this client",
+        )));
+    }
 
-    Ok(next.run(request).await)
+    Ok(())
+}
+
+async fn read_body(body: Body, limit: usize) -> AppResult<axum::body::Bytes> {
+    to_bytes(body, limit)
+        .await
+        .map_err(|_| AppError::BadRequest(String::from("request body exceeds allowed size")))
 }
 
 pub fn require_admin(client: &AuthenticatedClient) -> AppResult<()> {
@@ -74,11 +421,86 @@ pub fn require_admin(client: &AuthenticatedClient) -> AppResult<()> {
     Ok(())
 }
 
+/// [`require_admin`] for destructive operations: admins that enabled one-time
+/// codes must also send a valid `X-OTP`.
+pub async fn require_admin_otp(state: &AppState, client: &AuthenticatedClient) -> AppResult<()> {
+    require_admin(client)?;
+    otp::verify(&state.db, &client.id, client.otp.as_deref()).await
+}
+
 fn parse_client_id(parts: &Parts) -> AppResult<Uuid> {
     let value = parse_header_value(parts, "X-Client-Id")?;
     Uuid::parse_str(&value).map_err(|_| AppError::Unauthorized(String::from("invalid client id")))
 }
 
+fn parse_signature_version(
+    parts: &Parts,
+    minimum: SignatureVersion,
+) -> AppResult<SignatureVersion> {
+    let version = match parts.headers.get("X-Signature-Version") {
+        None => SignatureVersion::V1,
+        Some(value) => value
+            .to_str()
+            .ok()
+            .and_then(SignatureVersion::parse)
+            .ok_or_else(|| AppError::Unauthorized(String::from("unsupported signature version")))?,
+    };
+    if version < minimum {
+        return Err(AppError::Unauthorized(format!(
+            "signature version {version} is no longer accepted; sign with version {minimum}"
+        )));
+    }
+
+    Ok(version)
+}
+
+/// The headers named in `X-Signed-Headers`, lowercased, with their values.
+fn parse_signed_headers(parts: &Parts) -> AppResult<Vec<(String, String)>> {
+    if !parts.headers.contains_key("X-Signed-Headers") {
+        return Ok(Vec::new());
+    }
+
+    parse_header_value(parts, "X-Signed-Headers")?
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            let name = name.to_ascii_lowercase();
+            let value = parse_header_value(parts, &name)
+                .map_err(|_| AppError::Unauthorized(format!("signed header is missing: {name}")))?;
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// Every `SIGNED_HEADERS` header the request carries must be `covered`.
+fn check_signed_headers(
+    required: &[String],
+    parts: &Parts,
+    covered: impl Fn(&str) -> bool,
+) -> AppResult<()> {
+    for name in required {
+        if parts.headers.contains_key(name.as_str()) && !covered(name) {
+            return Err(AppError::Unauthorized(format!(
+                "signature must cover the {name} header"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// `Host` for HTTP/1.1, the URI authority for HTTP/2.
+pub fn request_host(parts: &Parts, uri: &Uri) -> AppResult<String> {
+    parts
+        .headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned)
+        .or_else(|| uri.authority().map(ToString::to_string))
+        .ok_or_else(|| AppError::Unauthorized(String::from("missing header: Host")))
+}
+
 fn parse_timestamp(parts: &Parts) -> AppResult<i64> {
     let raw = parse_header_value(parts, "X-Timestamp")?;
     raw.parse::<i64>()
@@ -120,7 +542,179 @@ fn validate_timestamp(timestamp: i64, max_drift_seconds: i64) -> AppResult<i64>
     Ok(now)
 }
 
-fn current_unix_timestamp() -> AppResult<i64> {
+pub fn current_unix_timestamp() -> AppResult<i64> {
     let secs = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
     i64::try_from(secs).map_err(|_| AppError::Internal(String::from("unix timestamp overflow")))
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        Extension, Router,
+        http::{HeaderValue, StatusCode},
+        middleware,
+        routing::post,
+    };
+    use sha2::{Digest, Sha256};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        config::AppConfig,
+        db::{Database, MemoryStore},
+        keys::KeyAlgorithm,
+        nonce::Nonces,
+    };
+
+    struct Signer {
+        router: Router,
+        client_id: Uuid,
+        private_key_pem: String,
+    }
+
+    impl Signer {
+        async fn new(config: AppConfig) -> Self {
+            let db = Database::new(MemoryStore::new());
+            let keypair = crypto::generate_ed25519_keypair().unwrap();
+            let client = db
+                .create_client("svc", &keypair.public_key_b64, KeyAlgorithm::Ed25519, false)
+                .await
+                .unwrap();
+            let state = AppState {
+                nonces: Nonces::from_config(&config, &db),
+                db,
+                config,
+                ca: None,
+                server_key: None,
+            };
+            let router = Router::new()
+                .route(
+                    "/api/echo",
+                    post(
+                        |Extension(client): Extension<AuthenticatedClient>| async move {
+                            client.id.to_string()
+                        },
+                    ),
+                )
+                .layer(middleware::from_fn_with_state(
+                    state.clone(),
+                    require_client_signature,
+                ))
+                .with_state(state);
+
+            Self {
+                router,
+                client_id: client.id,
+                private_key_pem: keypair.private_key_pem,
+            }
+        }
+
+        /// Signs a POST of `body` over `components` and returns the status.
+        async fn send(&self, components: &str, params: &str, body: &'static str) -> StatusCode {
+            let digest = STANDARD.encode(Sha256::digest(body));
+            let input = format!("sig1=({components});{params};keyid=\"{}\"", self.client_id);
+            let mut request = Request::post("/api/echo")
+                .header(header::HOST, "config.example")
+                .header(header::CONTENT_TYPE, "application/json")
+                .header("Content-Digest", format!("sha-256=:{digest}:"))
+                .header("Signature-Input", &input)
+                .header("Signature", "sig1=:AA==:")
+                .body(Body::from(body))
+                .unwrap();
+
+            let headers = request.headers().clone();
+            let message = Message {
+                method: "POST",
+                scheme: "http",
+                authority: "config.example",
+                path: "/api/echo",
+                query: None,
+                headers: &headers,
+            };
+            let base = HttpSignature::from_headers(&headers)
+                .unwrap()
+                .signature_base(&message)
+                .unwrap();
+            let signature = crypto::sign_canonical(&self.private_key_pem, &base).unwrap();
+            request.headers_mut().insert(
+                "Signature",
+                HeaderValue::from_str(&format!("sig1=:{signature}:")).unwrap(),
+            );
+
+            self.router.clone().oneshot(request).await.unwrap().status()
+        }
+    }
+
+    #[tokio::test]
+    async fn http_signatures_pass_the_middleware_only_when_complete_and_fresh() {
+        let signer = Signer::new(AppConfig::for_tests("memory")).await;
+        let now = current_unix_timestamp().unwrap();
+        let full = r#""@method" "@authority" "@path" "content-digest""#;
+        let params = |nonce: &str| format!("created={now};nonce=\"{nonce}\"");
+
+        assert_eq!(signer.send(full, &params("a"), "{}").await, StatusCode::OK);
+        // The same nonce again is a replay.
+        assert_eq!(
+            signer.send(full, &params("a"), "{}").await,
+            StatusCode::UNAUTHORIZED
+        );
+
+        // Leaving the method, target or body unsigned is refused.
+        for components in [
+            r#""@authority" "@path" "content-digest""#,
+            r#""@method" "@path" "content-digest""#,
+            r#""@method" "@authority" "@path""#,
+        ] {
+            assert_eq!(
+                signer.send(components, &params(components), "{}").await,
+                StatusCode::UNAUTHORIZED,
+                "{components}"
+            );
+        }
+
+        let expiring = |nonce: &str, expires: i64| format!("{};expires={expires}", params(nonce));
+        assert_eq!(
+            signer.send(full, &expiring("b", now + 60), "{}").await,
+            StatusCode::OK
+        );
+        for (nonce, expires) in [("c", now), ("d", now - 1), ("e", now + 301)] {
+            assert_eq!(
+                signer.send(full, &expiring(nonce, expires), "{}").await,
+                StatusCode::UNAUTHORIZED,
+                "expires={expires}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn minimum_signature_version_2_needs_the_content_type_covered() {
+        let signer = Signer::new(AppConfig {
+            min_signature_version: SignatureVersion::V2,
+            ..AppConfig::for_tests("memory")
+        })
+        .await;
+        let now = current_unix_timestamp().unwrap();
+        let params = |nonce: &str| format!("created={now};nonce=\"{nonce}\"");
+
+        assert_eq!(
+            signer
+                .send(
+                    r#""@method" "@authority" "@path" "content-digest""#,
+                    &params("a"),
+                    "{}"
+                )
+                .await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            signer
+                .send(
+                    r#""@method" "@authority" "@path" "content-type" "content-digest""#,
+                    &params("b"),
+                    "{}"
+                )
+                .await,
+            StatusCode::OK
+        );
+    }
+}
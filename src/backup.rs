@@ -0,0 +1,334 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+use age::secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db::Database,
+    error::{AppError, AppResult},
+    models::{Client, ClientPermission, ConfigItem, Project},
+};
+
+pub const BACKUP_FORMAT: &str = "cloudconfig-backup";
+pub const BACKUP_VERSION: u32 = 1;
+
+/// Portable snapshot of everything `cloudconfig restore` can bring back.
+/// Configs keep their `version` and `updated_at`, so clients that track
+/// versions see the same history after a restore. The change feed log is
+/// left out: its revisions only mean something to the database that wrote
+/// them, so a restore starts the feed over with `reset` instead.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backup {
+    pub format: String,
+    pub version: u32,
+    /// Unix timestamp.
+    pub created_at: i64,
+    pub schema_version: i64,
+    pub clients: Vec<Client>,
+    pub projects: Vec<Project>,
+    pub permissions: Vec<ClientPermission>,
+    pub configs: Vec<ConfigItem>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum RestoreMode {
+    /// Upsert archived rows by id and keep everything else. A config only
+    /// overwrites an existing one whose version is not newer.
+    Merge,
+    /// Delete all clients, projects, configs and permissions first.
+    Replace,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RestoreSummary {
+    pub clients: u64,
+    pub projects: u64,
+    pub permissions: u64,
+    pub configs: u64,
+}
+
+/// How an archive is encrypted, or how to decrypt one.
+#[derive(Debug, Default)]
+pub struct Encryption {
+    pub passphrase: Option<SecretString>,
+    /// `age1...` recipients when encrypting.
+    pub recipients: Vec<String>,
+    /// Contents of an age identity file when decrypting.
+    pub identities: Option<String>,
+}
+
+pub async fn create(db: &Database) -> AppResult<Backup> {
+    let created_at = crate::auth::current_unix_timestamp()?;
+    let schema_version = db.schema_status().await?.current_version;
+
+    let mut clients = db.list_clients().await?;
+    clients.sort_by_key(|client| client.id);
+    let projects = db.list_projects().await?;
+
+    let mut permissions = Vec::new();
+    for client in &clients {
+        permissions.extend(db.list_permissions_for_client(&client.id).await?);
+    }
+
+    let mut configs = Vec::new();
+    for project in &projects {
+        configs.extend(db.list_configs_for_project(&project.id).await?);
+    }
+
+    Ok(Backup {
+        format: String::from(BACKUP_FORMAT),
+        version: BACKUP_VERSION,
+        created_at,
+        schema_version,
+        clients,
+        projects,
+        permissions,
+        configs,
+    })
+}
+
+/// Checks the archive and the target database, then writes the archive in
+/// one transaction.
+pub async fn restore(
+    db: &Database,
+    backup: &Backup,
+    mode: RestoreMode,
+) -> AppResult<RestoreSummary> {
+    validate(backup)?;
+
+    if mode == RestoreMode::Merge {
+        let archived_admin = backup.clients.iter().find(|client| client.is_admin);
+        if let (Some(archived), Some(current)) = (archived_admin, db.get_admin_client().await?)
+            && archived.id != current.id
+        {
+            return Err(AppError::Conflict(String::from(
+                "archive has a different admin client; restore with --mode replace",
+            )));
+        }
+
+        for project in &backup.projects {
+            if let Some(existing) = db.get_project_by_name(&project.name).await?
+                && existing.id != project.id
+            {
+                return Err(AppError::Conflict(format!(
+                    "project name {} already exists with a different id",
+                    project.name
+                )));
+            }
+        }
+    }
+
+    db.restore_backup(backup, mode).await
+}
+
+fn validate(backup: &Backup) -> AppResult<()> {
+    if backup.format != BACKUP_FORMAT {
+        return Err(AppError::BadRequest(String::from(
+            "not a cloudconfig backup archive",
+        )));
+    }
+    if backup.version != BACKUP_VERSION {
+        return Err(AppError::BadRequest(format!(
+            "unsupported backup format version {} (this build reads version {BACKUP_VERSION})",
+            backup.version
+        )));
+    }
+
+    let client_ids = unique(backup.clients.iter().map(|client| client.id), "client id")?;
+    let project_ids = unique(
+        backup.projects.iter().map(|project| project.id),
+        "project id",
+    )?;
+    unique(
+        backup.projects.iter().map(|project| project.name.as_str()),
+        "project name",
+    )?;
+    unique(
+        backup
+            .permissions
+            .iter()
+            .map(|p| (p.client_id, p.project_id)),
+        "permission",
+    )?;
+    unique(
+        backup
+            .configs
+            .iter()
+            .map(|c| (c.project_id, c.key.as_str())),
+        "config key",
+    )?;
+
+    for permission in &backup.permissions {
+        if !client_ids.contains(&permission.client_id)
+            || !project_ids.contains(&permission.project_id)
+        {
+            return Err(AppError::BadRequest(format!(
+                "permission for client {} references a missing client or project",
+                permission.client_id
+            )));
+        }
+    }
+    if let Some(config) = backup
+        .configs
+        .iter()
+        .find(|config| !project_ids.contains(&config.project_id))
+    {
+        return Err(AppError::BadRequest(format!(
+            "config {} references missing project {}",
+            config.key, config.project_id
+        )));
+    }
+
+    Ok(())
+}
+
+fn unique<T: Eq + std::hash::Hash + std::fmt::Debug>(
+    values: impl Iterator<Item = T>,
+    kind: &str,
+) -> AppResult<HashSet<T>> {
+    let mut seen = HashSet::new();
+    for value in values {
+        if seen.contains(&value) {
+            return Err(AppError::BadRequest(format!(
+                "archive contains duplicate {kind} {value:?}"
+            )));
+        }
+        seen.insert(value);
+    }
+
+    Ok(seen)
+}
+
+/// Serializes the archive, age-encrypting it when a passphrase or recipients
+/// are given.
+pub fn encode(backup: &Backup, encryption: &Encryption) -> AppResult<Vec<u8>> {
+    let json = serde_json::to_vec_pretty(backup)
+        .map_err(|e| AppError::Internal(format!("failed to serialize backup: {e}")))?;
+
+    let encryptor = if let Some(passphrase) = &encryption.passphrase {
+        age::Encryptor::with_user_passphrase(passphrase.clone())
+    } else if encryption.recipients.is_empty() {
+        return Ok(json);
+    } else {
+        let recipients = encryption
+            .recipients
+            .iter()
+            .map(|raw| {
+                age::x25519::Recipient::from_str(raw.trim())
+                    .map_err(|e| AppError::BadRequest(format!("invalid age recipient {raw}: {e}")))
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+        age::Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn age::Recipient))
+            .map_err(|e| AppError::Crypto(e.to_string()))?
+    };
+
+    let mut encrypted = Vec::new();
+    let mut writer = encryptor
+        .wrap_output(&mut encrypted)
+        .map_err(|e| AppError::Crypto(e.to_string()))?;
+    writer
+        .write_all(&json)
+        .and_then(|()| writer.finish().map(drop))
+        .map_err(|e| AppError::Crypto(e.to_string()))?;
+
+    Ok(encrypted)
+}
+
+/// Parses an archive, decrypting it first if it is an age file.
+pub fn decode(bytes: &[u8], encryption: &Encryption) -> AppResult<Backup> {
+    let reader = age::armor::ArmoredReader::new(bytes);
+    let json = match age::Decryptor::new_buffered(std::io::BufReader::new(reader)) {
+        Ok(decryptor) => decrypt(decryptor, encryption)?,
+        Err(age::DecryptError::InvalidHeader) => bytes.to_vec(),
+        Err(error) => return Err(AppError::Crypto(format!("invalid age archive: {error}"))),
+    };
+
+    serde_json::from_slice(&json)
+        .map_err(|e| AppError::BadRequest(format!("invalid backup archive: {e}")))
+}
+
+fn decrypt<R: std::io::BufRead>(
+    decryptor: age::Decryptor<R>,
+    encryption: &Encryption,
+) -> AppResult<Vec<u8>> {
+    let identities: Vec<Box<dyn age::Identity>> = if decryptor.is_scrypt() {
+        let passphrase = encryption.passphrase.clone().ok_or_else(|| {
+            AppError::BadRequest(String::from(
+                "archive is passphrase-encrypted; pass --passphrase-file",
+            ))
+        })?;
+        vec![Box::new(age::scrypt::Identity::new(passphrase))]
+    } else {
+        let raw = encryption.identities.as_deref().ok_or_else(|| {
+            AppError::BadRequest(String::from(
+                "archive is encrypted to age recipients; pass --identity",
+            ))
+        })?;
+        age::IdentityFile::from_buffer(raw.as_bytes())
+            .map_err(|e| AppError::BadRequest(format!("invalid age identity file: {e}")))?
+            .into_identities()
+            .map_err(|e| AppError::BadRequest(format!("invalid age identity file: {e}")))?
+    };
+
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(AsRef::as_ref))
+        .map_err(|e| AppError::Crypto(format!("failed to decrypt archive: {e}")))?;
+    let mut json = Vec::new();
+    reader
+        .read_to_end(&mut json)
+        .map_err(|e| AppError::Crypto(format!("failed to decrypt archive: {e}")))?;
+
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Backup, Encryption, decode, encode};
+    use age::secrecy::{ExposeSecret, SecretString};
+
+    fn sample() -> Backup {
+        serde_json::from_str(
+            r#"{"format":"cloudconfig-backup","version":1,"created_at":1700000000,"schema_version":2,
+                "clients":[],"projects":[],"permissions":[],"configs":[]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn round_trips_plain_passphrase_and_recipient_archives() {
+        let plain = encode(&sample(), &Encryption::default()).unwrap();
+        assert!(plain.starts_with(b"{"));
+        assert_eq!(
+            decode(&plain, &Encryption::default())
+                .unwrap()
+                .schema_version,
+            2
+        );
+
+        let with_passphrase = Encryption {
+            passphrase: Some(SecretString::from("correct horse")),
+            ..Encryption::default()
+        };
+        let sealed = encode(&sample(), &with_passphrase).unwrap();
+        assert!(decode(&sealed, &Encryption::default()).is_err());
+        assert!(decode(&sealed, &with_passphrase).is_ok());
+
+        let identity = age::x25519::Identity::generate();
+        let recipient = identity.to_public().to_string();
+        let sealed = encode(
+            &sample(),
+            &Encryption {
+                recipients: vec![recipient],
+                ..Encryption::default()
+            },
+        )
+        .unwrap();
+        let with_identity = Encryption {
+            identities: Some(identity.to_string().expose_secret().to_owned()),
+            ..Encryption::default()
+        };
+        assert!(decode(&sealed, &with_identity).is_ok());
+    }
+}
@@ -0,0 +1,330 @@
+use std::path::Path;
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+};
+use rcgen::{
+    BasicConstraints, CertificateParams, CertificateRevocationListParams,
+    CertificateSigningRequestParams, DnType, ExtendedKeyUsagePurpose, IsCa, Issuer, KeyIdMethod,
+    KeyPair, KeyUsagePurpose, RevokedCertParams, SanType, SerialNumber,
+};
+use ring::rand::{SecureRandom, SystemRandom};
+use rustls::pki_types::{CertificateDer, pem::PemObject};
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::{
+    AppState, auth, crypto,
+    error::{AppError, AppResult},
+    models::{ClientCertificate, IssueCertificateRequest, IssuedCertificate},
+    tls,
+};
+
+const CA_COMMON_NAME: &str = "CloudConfig Client CA";
+const CA_LIFETIME: Duration = Duration::from_secs(10 * 365 * 24 * 60 * 60);
+/// Issued certificates start slightly in the past to tolerate clock skew.
+const BACKDATE_SECONDS: i64 = 60;
+/// How long a CRL is valid; verifiers should fetch a fresh one before then.
+const CRL_LIFETIME_SECONDS: i64 = 24 * 60 * 60;
+
+/// The internal CA that signs mTLS client certificates. Each certificate
+/// names its client in the subject CN and a `urn:uuid:` SAN, but the server
+/// identifies a presented certificate by fingerprint against the
+/// `client_certificates` table, so revocation takes effect immediately.
+pub struct CertificateAuthority {
+    issuer: Issuer<'static, KeyPair>,
+    cert_pem: String,
+    cert_der: CertificateDer<'static>,
+}
+
+impl std::fmt::Debug for CertificateAuthority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertificateAuthority")
+            .field("fingerprint", &self.fingerprint())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CertificateAuthority {
+    /// Loads the CA from the given files, creating a new one there if the
+    /// certificate does not exist yet. The flag is `true` when it was created.
+    pub fn load_or_create(cert_path: &str, key_path: &str) -> AppResult<(Self, bool)> {
+        if Path::new(cert_path).exists() {
+            let cert_pem = read(cert_path)?;
+            warn_if_readable_by_others(key_path);
+            let key = KeyPair::from_pem(&read(key_path)?)
+                .map_err(|e| AppError::BadRequest(format!("invalid CA key {key_path}: {e}")))?;
+            let issuer = Issuer::from_ca_cert_pem(&cert_pem, key).map_err(|e| {
+                AppError::BadRequest(format!("invalid CA certificate {cert_path}: {e}"))
+            })?;
+            return Ok((Self::new(issuer, cert_pem)?, false));
+        }
+
+        let key = KeyPair::generate()?;
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, CA_COMMON_NAME);
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        let now = OffsetDateTime::now_utc();
+        params.not_before = now - time::Duration::seconds(BACKDATE_SECONDS);
+        params.not_after = now + CA_LIFETIME;
+        let cert_pem = params.self_signed(&key)?.pem();
+
+        crypto::write_secret_file(Path::new(key_path), key.serialize_pem().as_bytes())
+            .map_err(|e| AppError::Internal(format!("failed to write {key_path}: {e}")))?;
+        std::fs::write(cert_path, &cert_pem)
+            .map_err(|e| AppError::Internal(format!("failed to write {cert_path}: {e}")))?;
+
+        Ok((Self::new(Issuer::new(params, key), cert_pem)?, true))
+    }
+
+    fn new(issuer: Issuer<'static, KeyPair>, cert_pem: String) -> AppResult<Self> {
+        let cert_der = CertificateDer::from_pem_slice(cert_pem.as_bytes())
+            .map_err(|e| AppError::BadRequest(format!("invalid CA certificate: {e}")))?;
+        Ok(Self {
+            issuer,
+            cert_pem,
+            cert_der,
+        })
+    }
+
+    /// Trust anchor for verifying presented client certificates.
+    pub fn root(&self) -> CertificateDer<'static> {
+        self.cert_der.clone()
+    }
+
+    pub fn fingerprint(&self) -> String {
+        tls::fingerprint(&self.cert_der)
+    }
+
+    /// Signs a client certificate for `client_id`. With a CSR only its public
+    /// key is used; subject, SANs and usages are always set by the CA.
+    pub fn issue(
+        &self,
+        client_id: &Uuid,
+        csr: Option<&str>,
+        ttl: Duration,
+    ) -> AppResult<IssuedCertificate> {
+        let now = auth::current_unix_timestamp()?;
+        let ttl_seconds = i64::try_from(ttl.as_secs())
+            .map_err(|_| AppError::BadRequest(String::from("certificate lifetime is too long")))?;
+        let expires_at = now.saturating_add(ttl_seconds);
+
+        let mut serial = [0_u8; 16];
+        SystemRandom::new().fill(&mut serial)?;
+        // Positive and always 16 bytes long once DER-encoded.
+        serial[0] = (serial[0] & 0x7f) | 0x40;
+
+        let mut params = CertificateParams::default();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, client_id.to_string());
+        params.subject_alt_names = vec![SanType::URI(
+            format!("urn:uuid:{client_id}")
+                .try_into()
+                .map_err(|e| AppError::Internal(format!("invalid client SAN: {e}")))?,
+        )];
+        params.serial_number = Some(SerialNumber::from_slice(&serial));
+        params.is_ca = IsCa::ExplicitNoCa;
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.use_authority_key_identifier_extension = true;
+        params.not_before = unix_to_offset(now - BACKDATE_SECONDS)?;
+        params.not_after = unix_to_offset(expires_at)?;
+
+        let (cert, private_key_pem) = if let Some(pem) = csr {
+            let request = CertificateSigningRequestParams::from_pem(pem).map_err(|e| {
+                AppError::BadRequest(format!("invalid certificate signing request: {e}"))
+            })?;
+            (params.signed_by(&request.public_key, &self.issuer)?, None)
+        } else {
+            let key = KeyPair::generate()?;
+            let cert = params.signed_by(&key, &self.issuer)?;
+            (cert, Some(key.serialize_pem()))
+        };
+
+        Ok(IssuedCertificate {
+            certificate: ClientCertificate {
+                serial: hex::encode(serial),
+                client_id: *client_id,
+                fingerprint: tls::fingerprint(cert.der()),
+                issued_at: now,
+                expires_at,
+                revoked_at: None,
+            },
+            certificate_pem: cert.pem(),
+            private_key_pem,
+            ca_certificate_pem: self.cert_pem.clone(),
+        })
+    }
+
+    /// A CRL signed by the CA, listing the revoked certificates among
+    /// `certificates` that have not expired yet. The Unix time serves as the
+    /// CRL number, so later lists always have higher numbers.
+    pub fn crl(&self, certificates: &[ClientCertificate], now: i64) -> AppResult<String> {
+        let revoked_certs = certificates
+            .iter()
+            .filter(|certificate| certificate.expires_at > now)
+            .filter_map(|certificate| Some((certificate, certificate.revoked_at?)))
+            .map(|(certificate, revoked_at)| {
+                let serial = hex::decode(&certificate.serial).map_err(|_| {
+                    AppError::Internal(format!("invalid stored serial {}", certificate.serial))
+                })?;
+                Ok(RevokedCertParams {
+                    serial_number: SerialNumber::from_slice(&serial),
+                    revocation_time: unix_to_offset(revoked_at)?,
+                    reason_code: None,
+                    invalidity_date: None,
+                })
+            })
+            .collect::<AppResult<Vec<_>>>()?;
+
+        let params = CertificateRevocationListParams {
+            this_update: unix_to_offset(now - BACKDATE_SECONDS)?,
+            next_update: unix_to_offset(now.saturating_add(CRL_LIFETIME_SECONDS))?,
+            crl_number: SerialNumber::from(u64::try_from(now).unwrap_or_default()),
+            issuing_distribution_point: None,
+            revoked_certs,
+            key_identifier_method: KeyIdMethod::Sha256,
+        };
+        Ok(params.signed_by(&self.issuer)?.pem()?)
+    }
+}
+
+/// `GET /crl`: the client CA's current revocation list as PEM, for TLS
+/// terminators and other verifiers in front of the server.
+pub async fn crl(State(state): State<AppState>) -> AppResult<Response> {
+    let ca = state.ca.as_ref().ok_or_else(|| {
+        AppError::NotFound(String::from(
+            "client certificate authority is not configured",
+        ))
+    })?;
+    let certificates = state.db.list_client_certificates(None).await?;
+    let pem = ca.crl(&certificates, auth::current_unix_timestamp()?)?;
+    Ok(([(header::CONTENT_TYPE, "application/x-pem-file")], pem).into_response())
+}
+
+/// Issues a certificate to an existing client and records it so the server
+/// accepts it. The lifetime is capped at `MTLS_CERT_TTL_HOURS`.
+pub async fn issue_for_client(
+    state: &AppState,
+    client_id: &Uuid,
+    request: &IssueCertificateRequest,
+) -> AppResult<IssuedCertificate> {
+    let ca = state.ca.as_ref().ok_or_else(|| {
+        AppError::Conflict(String::from(
+            "client certificate authority is not configured",
+        ))
+    })?;
+    if state.db.get_client_by_id(client_id).await?.is_none() {
+        return Err(AppError::NotFound(String::from("client not found")));
+    }
+
+    let max_hours = state.config.mtls_cert_ttl_hours;
+    let hours = request.ttl_hours.unwrap_or(max_hours).min(max_hours);
+    if hours == 0 {
+        return Err(AppError::BadRequest(String::from("ttl_hours must be > 0")));
+    }
+
+    let issued = ca.issue(
+        client_id,
+        request.csr.as_deref(),
+        Duration::from_secs(hours * 60 * 60),
+    )?;
+    state
+        .db
+        .create_client_certificate(&issued.certificate)
+        .await?;
+    Ok(issued)
+}
+
+fn unix_to_offset(timestamp: i64) -> AppResult<OffsetDateTime> {
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .map_err(|e| AppError::BadRequest(format!("certificate validity out of range: {e}")))
+}
+
+/// Anyone who can read the CA key can mint trusted client certificates.
+fn warn_if_readable_by_others(key_path: &str) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if let Ok(metadata) = std::fs::metadata(key_path)
+            && metadata.permissions().mode() & 0o077 != 0
+        {
+            tracing::warn!(
+                "client CA key {key_path} is readable by other users; run chmod 600 on it"
+            );
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = key_path;
+}
+
+fn read(path: &str) -> AppResult<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| AppError::BadRequest(format!("failed to read {path}: {e}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rustls::{
+        crypto::ring::default_provider,
+        pki_types::{CertificateRevocationListDer, UnixTime, pem::PemObject},
+        server::WebPkiClientVerifier,
+    };
+
+    use super::*;
+
+    #[test]
+    fn crl_revokes_for_webpki_verifiers() {
+        let dir = std::env::temp_dir().join(format!("cloudconfig-ca-{}", Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("ca.pem"), dir.join("ca.key"));
+        let (ca, created) = CertificateAuthority::load_or_create(
+            cert_path.to_str().unwrap(),
+            key_path.to_str().unwrap(),
+        )
+        .unwrap();
+        assert!(created);
+
+        let issued = ca
+            .issue(&Uuid::new_v4(), None, Duration::from_secs(3600))
+            .unwrap();
+        let now = issued.certificate.issued_at;
+        let client_cert = CertificateDer::from_pem_slice(issued.certificate_pem.as_bytes())
+            .unwrap()
+            .into_owned();
+        let verify = |revoked_at: Option<i64>| {
+            let certificate = ClientCertificate {
+                revoked_at,
+                ..issued.certificate.clone()
+            };
+            let crl = ca.crl(&[certificate], now).unwrap();
+            let mut roots = rustls::RootCertStore::empty();
+            roots.add(ca.root()).unwrap();
+            let verifier = WebPkiClientVerifier::builder_with_provider(
+                Arc::new(roots),
+                Arc::new(default_provider()),
+            )
+            .with_crls([CertificateRevocationListDer::from_pem_slice(crl.as_bytes()).unwrap()])
+            .build()
+            .unwrap();
+            verifier.verify_client_cert(&client_cert, &[], UnixTime::now())
+        };
+
+        assert!(verify(None).is_ok());
+        assert!(verify(Some(now)).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,4 +1,8 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand};
+
+use crate::{backup::RestoreMode, crypto::SignatureVersion, keys::KeyAlgorithm};
 
 #[derive(Debug, Parser)]
 #[command(
@@ -15,6 +19,143 @@ pub struct Cli {
 pub enum Command {
     Init,
     Start,
-    Reset,
+    Reset(ResetArgs),
+    Status,
+    /// Generate a typed Rust struct from a project's config keys
+    Codegen(CodegenArgs),
+    /// Generate an Ed25519 or ECDSA P-256 client keypair (see --algorithm)
+    Keygen(KeygenArgs),
+    /// Print signed request headers for a call to the API
+    Sign(SignArgs),
+    /// Diagnose configuration, database, server and client key problems
+    Doctor(DoctorArgs),
+    /// Write clients, projects, permissions and configs to an archive
+    Backup(BackupArgs),
+    /// Load an archive written by `cloudconfig backup`
+    Restore(RestoreArgs),
+    /// Inspect or apply database schema migrations
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MigrateAction {
+    /// Show applied and pending migrations
     Status,
+    /// Apply all pending migrations
+    Up,
+}
+
+#[derive(Debug, Args)]
+pub struct ResetArgs {
+    /// Current one-time or recovery code, if the admin enabled one-time codes
+    #[arg(long)]
+    pub otp: Option<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct BackupArgs {
+    /// Archive file to write
+    #[arg(long, short)]
+    pub out: PathBuf,
+    /// Encrypt with the passphrase in this file (age scrypt)
+    #[arg(long, conflicts_with = "recipient")]
+    pub passphrase_file: Option<PathBuf>,
+    /// Encrypt to an age recipient (`age1...`); may be repeated
+    #[arg(long)]
+    pub recipient: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct RestoreArgs {
+    /// Archive file to read
+    pub input: PathBuf,
+    /// Merge into the existing data or replace it entirely
+    #[arg(long, value_enum, default_value = "merge")]
+    pub mode: RestoreMode,
+    /// Passphrase file for an archive encrypted with --passphrase-file
+    #[arg(long)]
+    pub passphrase_file: Option<PathBuf>,
+    /// age identity file for an archive encrypted to a recipient
+    #[arg(long)]
+    pub identity: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct CodegenArgs {
+    /// Project name or id to read from the configured database
+    #[arg(long, required_unless_present = "input")]
+    pub project: Option<String>,
+    /// Read configs from a JSON file exported from `GET /api/projects/{id}/configs`
+    #[arg(long)]
+    pub input: Option<PathBuf>,
+    /// Write the generated code to this file instead of stdout
+    #[arg(long, short)]
+    pub output: Option<PathBuf>,
+    /// Name of the generated struct
+    #[arg(long, default_value = "ProjectConfig")]
+    pub struct_name: String,
+}
+
+#[derive(Debug, Args)]
+pub struct KeygenArgs {
+    /// Write the PKCS#8 PEM here and the base64 public key to `<out>.pub`
+    #[arg(long, short)]
+    pub out: Option<PathBuf>,
+    /// Key type to generate
+    #[arg(long, value_enum, default_value_t = KeyAlgorithm::Ed25519)]
+    pub algorithm: KeyAlgorithm,
+}
+
+#[derive(Debug, Args)]
+pub struct SignArgs {
+    /// HTTP method, e.g. GET or PUT
+    #[arg(long, short = 'X', default_value = "GET")]
+    pub method: String,
+    /// Request path including any query string, e.g. /api/projects
+    #[arg(long)]
+    pub path: String,
+    /// Request body sent verbatim
+    #[arg(long, short = 'd', conflicts_with = "body_file")]
+    pub body: Option<String>,
+    /// Read the request body from a file
+    #[arg(long)]
+    pub body_file: Option<PathBuf>,
+    /// Client id sent as `X-Client-Id`
+    #[arg(long)]
+    pub client_id: uuid::Uuid,
+    /// Path to the client's PKCS#8 or unencrypted OpenSSH private key
+    #[arg(long)]
+    pub key: PathBuf,
+    /// Print a ready-to-run curl command against this base URL instead of headers
+    #[arg(long)]
+    pub curl: Option<String>,
+    /// Signature scheme; version 2 also signs the host, content type and key id.
+    /// Defaults to 2 when the host is known from --host or --curl, 1 otherwise
+    #[arg(long, value_enum)]
+    pub signature_version: Option<SignatureVersion>,
+    /// Host the request is sent to, for version 2 (defaults to the --curl host)
+    #[arg(long)]
+    pub host: Option<String>,
+    /// Extra header to send as `Name: value`, e.g. X-OTP; version 2 signs it too
+    #[arg(long = "header", short = 'H', value_name = "NAME: VALUE")]
+    pub headers: Vec<String>,
+}
+
+#[derive(Debug, Args)]
+pub struct DoctorArgs {
+    /// Server address to probe (defaults to `LISTEN_ADDR`)
+    #[arg(long)]
+    pub server: Option<String>,
+    /// Client id to verify against the server
+    #[arg(long, requires = "key")]
+    pub client_id: Option<uuid::Uuid>,
+    /// Path to the client's PKCS#8 private key PEM
+    #[arg(long, requires = "client_id")]
+    pub key: Option<PathBuf>,
+    /// Print results as JSON
+    #[arg(long)]
+    pub json: bool,
 }
@@ -0,0 +1,264 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+
+use serde_json::Value;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::ConfigItem,
+};
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "false", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+    "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type",
+    "unsafe", "use", "where", "while", "abstract", "become", "box", "do", "final", "macro",
+    "override", "priv", "try", "typeof", "unsized", "virtual", "yield",
+];
+
+/// Keywords that are not allowed as raw identifiers either (`r#self` is an
+/// error), so they get a trailing underscore instead.
+const NON_RAW_KEYWORDS: &[&str] = &["crate", "self", "Self", "super"];
+
+/// Renders a `serde` struct with one field per config key. Field types are
+/// inferred from each item's JSON `value`; the original key is kept through
+/// `#[serde(rename)]` so the struct deserializes from a `key -> value` object.
+pub fn generate_struct(
+    struct_name: &str,
+    project_label: &str,
+    items: &[ConfigItem],
+) -> AppResult<String> {
+    validate_struct_name(struct_name)?;
+
+    let mut items = items.to_vec();
+    items.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let mut seen_fields = HashSet::new();
+    let mut fields = Vec::with_capacity(items.len());
+    for item in &items {
+        let value = serde_json::from_str::<Value>(&item.value).map_err(|e| {
+            AppError::BadRequest(format!("config `{}` is not valid JSON: {e}", item.key))
+        })?;
+
+        let field = field_name(&item.key);
+        if !seen_fields.insert(field.clone()) {
+            return Err(AppError::Conflict(format!(
+                "config key `{}` maps to duplicate field name `{field}`",
+                item.key
+            )));
+        }
+
+        fields.push((item, field, rust_type(&value)));
+    }
+
+    let project_label = comment_text(project_label);
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "// @generated by `cloudconfig codegen` from project `{project_label}`. Do not edit."
+    );
+    out.push('\n');
+    out.push_str("use serde::{Deserialize, Serialize};\n\n");
+    let _ = writeln!(
+        out,
+        "/// Typed configuration for the `{project_label}` CloudConfig project."
+    );
+    out.push_str("#[derive(Debug, Clone, Serialize, Deserialize)]\n");
+    let _ = writeln!(out, "pub struct {struct_name} {{");
+    for (item, field, ty) in &fields {
+        let _ = writeln!(
+            out,
+            "    /// `{}` (version {}, updated {})",
+            comment_text(&item.key),
+            item.version,
+            comment_text(&item.updated_at)
+        );
+        if field.trim_start_matches("r#") != item.key {
+            let _ = writeln!(out, "    #[serde(rename = {:?})]", item.key);
+        }
+        let _ = writeln!(out, "    pub {field}: {ty},");
+    }
+    out.push_str("}\n\n");
+
+    let _ = writeln!(out, "impl {struct_name} {{");
+    out.push_str("    /// Every config key this struct was generated from.\n");
+    out.push_str("    pub const KEYS: &'static [&'static str] = &[\n");
+    for (item, _, _) in &fields {
+        let _ = writeln!(out, "        {:?},", item.key);
+    }
+    out.push_str("    ];\n}\n");
+
+    Ok(out)
+}
+
+/// Escapes control characters, so a key or project name with a line break
+/// cannot end the comment it is written into and inject code.
+fn comment_text(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_control() {
+                c.escape_default().to_string()
+            } else {
+                c.to_string()
+            }
+        })
+        .collect()
+}
+
+fn validate_struct_name(name: &str) -> AppResult<()> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(AppError::BadRequest(format!(
+            "invalid struct name `{name}`: must be an UpperCamelCase identifier"
+        )));
+    }
+
+    Ok(())
+}
+
+fn field_name(key: &str) -> String {
+    let mut name = String::with_capacity(key.len());
+    let mut prev_lower = false;
+    for c in key.chars() {
+        if c.is_ascii_alphanumeric() {
+            if c.is_ascii_uppercase() && prev_lower {
+                name.push('_');
+            }
+            name.push(c.to_ascii_lowercase());
+            prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        } else {
+            if !name.is_empty() && !name.ends_with('_') {
+                name.push('_');
+            }
+            prev_lower = false;
+        }
+    }
+
+    let name = name.trim_end_matches('_');
+    if name.is_empty() {
+        return String::from("key_");
+    }
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        return format!("key_{name}");
+    }
+    if NON_RAW_KEYWORDS.contains(&name) {
+        return format!("{name}_");
+    }
+    if RUST_KEYWORDS.contains(&name) {
+        return format!("r#{name}");
+    }
+
+    name.to_owned()
+}
+
+fn rust_type(value: &Value) -> String {
+    match value {
+        Value::Null => String::from("Option<serde_json::Value>"),
+        Value::Bool(_) => String::from("bool"),
+        Value::Number(number) if number.is_i64() => String::from("i64"),
+        Value::Number(number) if number.is_u64() => String::from("u64"),
+        Value::Number(_) => String::from("f64"),
+        Value::String(_) => String::from("String"),
+        Value::Array(values) => {
+            let mut element_types = values.iter().map(rust_type);
+            match element_types.next() {
+                Some(first)
+                    if !first.contains("serde_json") && element_types.all(|ty| ty == first) =>
+                {
+                    format!("Vec<{first}>")
+                }
+                _ => String::from("Vec<serde_json::Value>"),
+            }
+        }
+        Value::Object(_) => String::from("serde_json::Value"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{field_name, generate_struct};
+    use crate::models::ConfigItem;
+    use uuid::Uuid;
+
+    fn item(key: &str, value: &str) -> ConfigItem {
+        ConfigItem {
+            id: Uuid::new_v4(),
+            project_id: Uuid::nil(),
+            key: key.to_owned(),
+            value: value.to_owned(),
+            version: 1,
+            updated_at: String::from("2026-01-01 00:00:00"),
+        }
+    }
+
+    #[test]
+    fn maps_keys_to_rust_identifiers() {
+        assert_eq!(field_name("database.host"), "database_host");
+        assert_eq!(field_name("maxConnections"), "max_connections");
+        assert_eq!(field_name("3rd-party"), "key_3rd_party");
+        assert_eq!(field_name("type"), "r#type");
+        assert_eq!(field_name("self"), "self_");
+        assert_eq!(field_name("Self"), "self_");
+        assert_eq!(field_name("crate"), "crate_");
+        assert_eq!(field_name("super"), "super_");
+    }
+
+    #[test]
+    fn renames_keywords_that_cannot_be_raw() {
+        let generated =
+            generate_struct("Cfg", "p", &[item("crate", "1"), item("match", "2")]).unwrap();
+
+        assert!(generated.contains("#[serde(rename = \"crate\")]\n    pub crate_: i64,"));
+        assert!(generated.contains("    pub r#match: i64,"));
+        assert!(!generated.contains("rename = \"match\""));
+        assert!(!generated.contains("r#crate"));
+    }
+
+    #[test]
+    fn infers_field_types_and_keeps_original_keys() {
+        let generated = generate_struct(
+            "AppSettings",
+            "billing",
+            &[
+                item("database.port", "5432"),
+                item("feature_flags", r#"["a","b"]"#),
+                item("ratio", "0.5"),
+            ],
+        )
+        .unwrap();
+
+        assert!(generated.contains("#[serde(rename = \"database.port\")]"));
+        assert!(generated.contains("pub database_port: i64,"));
+        assert!(generated.contains("pub feature_flags: Vec<String>,"));
+        assert!(generated.contains("pub ratio: f64,"));
+        assert!(!generated.contains("rename = \"ratio\""));
+    }
+
+    #[test]
+    fn escapes_line_breaks_in_comments() {
+        let generated = generate_struct(
+            "Cfg",
+            "p\npub fn injected() {}",
+            &[item("a\nfn evil() {}", "1")],
+        )
+        .unwrap();
+        assert!(generated.contains("project `p\\npub fn injected() {}`"));
+        assert!(generated.contains("/// `a\\nfn evil() {}` (version"));
+        // The key itself stays intact in the code, as a string literal.
+        assert!(generated.contains(r#"#[serde(rename = "a\nfn evil() {}")]"#));
+        assert!(
+            generated
+                .lines()
+                .all(|line| !line.starts_with("pub fn injected") && !line.starts_with("fn evil"))
+        );
+    }
+
+    #[test]
+    fn rejects_colliding_field_names() {
+        let result = generate_struct("Cfg", "p", &[item("a.b", "1"), item("a_b", "2")]);
+        assert!(result.is_err());
+    }
+}
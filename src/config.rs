@@ -1,28 +1,271 @@
-use crate::error::{AppError, AppResult};
+use axum::http::HeaderName;
+
+use crate::{
+    crypto::SignatureVersion,
+    error::{AppError, AppResult},
+    models::ApprovalAction,
+    network::IpNetwork,
+};
 
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     pub listen_addr: String,
-    pub turso_url: String,
+    pub database_url: String,
+    pub database_pool_size: usize,
     pub turso_auth_token: Option<String>,
+    pub turso_replica_path: Option<String>,
+    pub turso_sync_interval_seconds: u64,
+    /// CA for a Postgres server's certificate; public web roots without it.
+    pub postgres_ca_cert_path: Option<String>,
     pub max_clock_drift_seconds: i64,
     pub max_body_size_bytes: usize,
+    /// Oldest `X-Signature-Version` still accepted.
+    pub min_signature_version: SignatureVersion,
+    /// Lowercase names of headers a signature must cover whenever the
+    /// request carries them.
+    pub signed_headers: Vec<String>,
+    pub nonce_store: NonceStoreKind,
+    pub cache_ttl_seconds: u64,
+    pub cache_sync_interval_seconds: u64,
+    /// Revisions of change history kept per project; 0 keeps all of it.
+    pub change_history_limit: u64,
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    pub tls_self_signed: bool,
+    pub tls_reload_interval_seconds: u64,
+    pub mtls_ca_cert_path: Option<String>,
+    pub mtls_ca_key_path: Option<String>,
+    pub mtls_cert_ttl_hours: u64,
+    pub server_key_path: Option<String>,
+    /// Default and maximum lifetime of console session tokens.
+    pub session_ttl_seconds: u64,
+    pub approvals: ApprovalPolicy,
+    /// Reverse proxies whose `X-Forwarded-For` is believed when checking a
+    /// client's allowed networks.
+    pub trusted_proxies: Vec<IpNetwork>,
+}
+
+/// Which admin actions wait for other admins before they run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApprovalPolicy {
+    /// Distinct admins, the requester included, that must approve a gated
+    /// action. 1 turns approvals off.
+    pub required: u32,
+    pub actions: Vec<ApprovalAction>,
+    /// Names of the projects whose permission changes are gated.
+    pub production_projects: Vec<String>,
+    pub ttl_seconds: u64,
+}
+
+impl Default for ApprovalPolicy {
+    fn default() -> Self {
+        Self {
+            required: 1,
+            actions: ApprovalAction::ALL.to_vec(),
+            production_projects: Vec::new(),
+            ttl_seconds: 86_400,
+        }
+    }
+}
+
+impl ApprovalPolicy {
+    fn from_env() -> AppResult<Self> {
+        let defaults = Self::default();
+        let required = match std::env::var("APPROVALS_REQUIRED") {
+            Ok(raw) => raw
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| AppError::BadRequest(format!("invalid APPROVALS_REQUIRED: {e}")))?,
+            Err(_) => defaults.required,
+        };
+        if required == 0 {
+            return Err(AppError::BadRequest(String::from(
+                "APPROVALS_REQUIRED must be > 0",
+            )));
+        }
+
+        let actions = match std::env::var("APPROVAL_ACTIONS") {
+            Ok(raw) => parse_list(&raw)
+                .iter()
+                .map(|name| {
+                    ApprovalAction::parse(name).ok_or_else(|| {
+                        AppError::BadRequest(format!(
+                            "invalid APPROVAL_ACTIONS entry: {name} (expected create-admin, delete-project or production-permissions)"
+                        ))
+                    })
+                })
+                .collect::<AppResult<Vec<_>>>()?,
+            Err(_) => defaults.actions,
+        };
+        let production_projects = std::env::var("PRODUCTION_PROJECTS")
+            .map(|raw| parse_list(&raw))
+            .unwrap_or_default();
+        let ttl_seconds = parse_u64("APPROVAL_TTL_SECONDS", defaults.ttl_seconds)?;
+        if ttl_seconds == 0 {
+            return Err(AppError::BadRequest(String::from(
+                "APPROVAL_TTL_SECONDS must be > 0",
+            )));
+        }
+
+        Ok(Self {
+            required,
+            actions,
+            production_projects,
+            ttl_seconds,
+        })
+    }
+
+    /// Whether `action` has to be approved by other admins.
+    pub fn gates(&self, action: ApprovalAction) -> bool {
+        self.required > 1 && self.actions.contains(&action)
+    }
+}
+
+/// Where replay-protection nonces are remembered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceStoreKind {
+    /// In-process map; each instance only sees its own requests.
+    Memory,
+    /// The `used_nonces` table, shared by every instance on the database.
+    Database,
+}
+
+#[cfg(test)]
+impl AppConfig {
+    /// A config for tests: local defaults on `database_url`, nothing optional
+    /// turned on.
+    pub fn for_tests(database_url: &str) -> Self {
+        Self {
+            listen_addr: String::from("127.0.0.1:0"),
+            database_url: database_url.to_owned(),
+            database_pool_size: 4,
+            turso_auth_token: None,
+            turso_replica_path: None,
+            turso_sync_interval_seconds: 0,
+            postgres_ca_cert_path: None,
+            max_clock_drift_seconds: 300,
+            max_body_size_bytes: 1024,
+            min_signature_version: SignatureVersion::V1,
+            signed_headers: Vec::new(),
+            nonce_store: NonceStoreKind::Database,
+            cache_ttl_seconds: 0,
+            cache_sync_interval_seconds: 0,
+            change_history_limit: 0,
+            tls_cert_path: None,
+            tls_key_path: None,
+            tls_self_signed: false,
+            tls_reload_interval_seconds: 0,
+            mtls_ca_cert_path: None,
+            mtls_ca_key_path: None,
+            mtls_cert_ttl_hours: 24,
+            server_key_path: None,
+            session_ttl_seconds: 900,
+            approvals: ApprovalPolicy::default(),
+            trusted_proxies: Vec::new(),
+        }
+    }
 }
 
 impl AppConfig {
+    /// True for libSQL URLs served over the network (Turso or a libSQL server).
+    pub fn is_remote_database(&self) -> bool {
+        ["libsql://", "https://", "http://"]
+            .iter()
+            .any(|scheme| self.database_url.starts_with(scheme))
+    }
+
+    /// How long a nonce must be remembered: a timestamp stays acceptable for
+    /// `max_clock_drift_seconds` either side of the server clock.
+    pub fn nonce_ttl_seconds(&self) -> i64 {
+        self.max_clock_drift_seconds.saturating_mul(2)
+    }
+
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_cert_path.is_some()
+    }
+
+    fn validate_tls(&self) -> AppResult<()> {
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            return Err(AppError::BadRequest(String::from(
+                "TLS_CERT_PATH and TLS_KEY_PATH must be set together",
+            )));
+        }
+        if self.tls_self_signed && !self.tls_enabled() {
+            return Err(AppError::BadRequest(String::from(
+                "TLS_SELF_SIGNED needs TLS_CERT_PATH and TLS_KEY_PATH to write the certificate to",
+            )));
+        }
+        if self.mtls_ca_cert_path.is_some() != self.mtls_ca_key_path.is_some() {
+            return Err(AppError::BadRequest(String::from(
+                "MTLS_CA_CERT_PATH and MTLS_CA_KEY_PATH must be set together",
+            )));
+        }
+        if self.mtls_ca_cert_path.is_some() && !self.tls_enabled() {
+            return Err(AppError::BadRequest(String::from(
+                "mTLS needs TLS: set TLS_CERT_PATH and TLS_KEY_PATH as well",
+            )));
+        }
+        if self.mtls_cert_ttl_hours == 0 {
+            return Err(AppError::BadRequest(String::from(
+                "MTLS_CERT_TTL_HOURS must be > 0",
+            )));
+        }
+
+        Ok(())
+    }
+
     pub fn from_env() -> AppResult<Self> {
         dotenvy::dotenv().ok();
 
         let listen_addr =
             std::env::var("LISTEN_ADDR").unwrap_or_else(|_| String::from("0.0.0.0:8080"));
-        let turso_url = std::env::var("TURSO_URL").unwrap_or_else(|_| String::from(":memory:"));
+        let database_url = std::env::var("DATABASE_URL")
+            .or_else(|_| std::env::var("TURSO_URL"))
+            .unwrap_or_else(|_| String::from(":memory:"));
+        let database_pool_size = parse_usize("DATABASE_POOL_SIZE", 8)?;
         let turso_auth_token = std::env::var("TURSO_AUTH_TOKEN")
             .ok()
             .map(|value| value.trim().to_owned())
             .filter(|value| !value.is_empty());
 
+        let turso_replica_path = parse_path("TURSO_REPLICA_PATH");
+        let turso_sync_interval_seconds = parse_u64("TURSO_SYNC_INTERVAL_SECONDS", 60)?;
+
         let max_clock_drift_seconds = parse_i64("MAX_CLOCK_DRIFT_SECONDS", 300)?;
         let max_body_size_bytes = parse_usize("MAX_BODY_SIZE_BYTES", 1024 * 1024)?;
+        let min_signature_version = match std::env::var("MIN_SIGNATURE_VERSION") {
+            Err(_) => SignatureVersion::V1,
+            Ok(raw) if raw.trim().is_empty() => SignatureVersion::V1,
+            Ok(raw) => SignatureVersion::parse(&raw).ok_or_else(|| {
+                AppError::BadRequest(format!(
+                    "invalid MIN_SIGNATURE_VERSION: {raw} (expected 1 or 2)"
+                ))
+            })?,
+        };
+        let nonce_store = match std::env::var("NONCE_STORE").as_deref().map(str::trim) {
+            Err(_) | Ok("" | "database") => NonceStoreKind::Database,
+            Ok("memory") => NonceStoreKind::Memory,
+            Ok(other) => {
+                return Err(AppError::BadRequest(format!(
+                    "invalid NONCE_STORE: {other} (expected database or memory)"
+                )));
+            }
+        };
+
+        let cache_ttl_seconds = parse_u64("CACHE_TTL_SECONDS", 0)?;
+        let cache_sync_interval_seconds = parse_u64("CACHE_SYNC_INTERVAL_SECONDS", 1)?;
+
+        let tls_cert_path = parse_path("TLS_CERT_PATH");
+        let tls_key_path = parse_path("TLS_KEY_PATH");
+        let tls_self_signed = parse_bool("TLS_SELF_SIGNED", false)?;
+        let tls_reload_interval_seconds = parse_u64("TLS_RELOAD_INTERVAL_SECONDS", 30)?;
+
+        let mtls_ca_cert_path = parse_path("MTLS_CA_CERT_PATH");
+        let mtls_ca_key_path = parse_path("MTLS_CA_KEY_PATH");
+        let mtls_cert_ttl_hours = parse_u64("MTLS_CERT_TTL_HOURS", 24)?;
+        let server_key_path = parse_path("SERVER_KEY_PATH");
+        let session_ttl_seconds = parse_u64("SESSION_TTL_SECONDS", 900)?;
+        let approvals = ApprovalPolicy::from_env()?;
 
         if max_clock_drift_seconds < 0 {
             return Err(AppError::BadRequest(String::from(
@@ -30,19 +273,61 @@ impl AppConfig {
             )));
         }
 
+        if database_pool_size == 0 {
+            return Err(AppError::BadRequest(String::from(
+                "DATABASE_POOL_SIZE must be > 0",
+            )));
+        }
+
         if max_body_size_bytes == 0 {
             return Err(AppError::BadRequest(String::from(
                 "MAX_BODY_SIZE_BYTES must be > 0",
             )));
         }
 
-        Ok(Self {
+        if session_ttl_seconds == 0 {
+            return Err(AppError::BadRequest(String::from(
+                "SESSION_TTL_SECONDS must be > 0",
+            )));
+        }
+
+        let config = Self {
             listen_addr,
-            turso_url,
+            database_url,
+            database_pool_size,
             turso_auth_token,
+            turso_replica_path,
+            turso_sync_interval_seconds,
+            postgres_ca_cert_path: parse_path("POSTGRES_CA_CERT_PATH"),
             max_clock_drift_seconds,
             max_body_size_bytes,
-        })
+            min_signature_version,
+            signed_headers: parse_header_names("SIGNED_HEADERS")?,
+            nonce_store,
+            cache_ttl_seconds,
+            cache_sync_interval_seconds,
+            change_history_limit: parse_u64("CHANGE_HISTORY_LIMIT", 10_000)?,
+            tls_cert_path,
+            tls_key_path,
+            tls_self_signed,
+            tls_reload_interval_seconds,
+            mtls_ca_cert_path,
+            mtls_ca_key_path,
+            mtls_cert_ttl_hours,
+            server_key_path,
+            session_ttl_seconds,
+            approvals,
+            trusted_proxies: parse_networks("TRUSTED_PROXIES")?,
+        };
+        config.validate_tls()?;
+
+        if config.turso_replica_path.is_some() && !config.is_remote_database() {
+            return Err(AppError::BadRequest(String::from(
+                "TURSO_REPLICA_PATH requires a remote libSQL DATABASE_URL to replicate from",
+            )));
+        }
+
+        Ok(config)
     }
 }
 
@@ -55,6 +340,15 @@ fn parse_i64(var: &str, default_value: i64) -> AppResult<i64> {
     }
 }
 
+fn parse_u64(var: &str, default_value: u64) -> AppResult<u64> {
+    match std::env::var(var) {
+        Ok(raw) => raw
+            .parse::<u64>()
+            .map_err(|e| AppError::BadRequest(format!("invalid {var}: {e}"))),
+        Err(_) => Ok(default_value),
+    }
+}
+
 fn parse_usize(var: &str, default_value: usize) -> AppResult<usize> {
     match std::env::var(var) {
         Ok(raw) => raw
@@ -63,3 +357,54 @@ fn parse_usize(var: &str, default_value: usize) -> AppResult<usize> {
         Err(_) => Ok(default_value),
     }
 }
+
+/// Comma-separated values, trimmed, without empty entries.
+fn parse_list(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Comma-separated CIDR ranges.
+fn parse_networks(var: &str) -> AppResult<Vec<IpNetwork>> {
+    parse_list(&std::env::var(var).unwrap_or_default())
+        .iter()
+        .map(|range| {
+            range
+                .parse::<IpNetwork>()
+                .map_err(|_| AppError::BadRequest(format!("invalid {var} entry: {range}")))
+        })
+        .collect()
+}
+
+/// Comma-separated header names, lowercased.
+fn parse_header_names(var: &str) -> AppResult<Vec<String>> {
+    parse_list(&std::env::var(var).unwrap_or_default())
+        .iter()
+        .map(|name| {
+            HeaderName::from_bytes(name.as_bytes())
+                .map(|name| name.as_str().to_owned())
+                .map_err(|_| AppError::BadRequest(format!("invalid {var} entry: {name}")))
+        })
+        .collect()
+}
+
+fn parse_path(var: &str) -> Option<String> {
+    std::env::var(var)
+        .ok()
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+}
+
+fn parse_bool(var: &str, default_value: bool) -> AppResult<bool> {
+    match std::env::var(var).as_deref().map(str::trim) {
+        Err(_) | Ok("") => Ok(default_value),
+        Ok("1" | "true" | "yes") => Ok(true),
+        Ok("0" | "false" | "no") => Ok(false),
+        Ok(other) => Err(AppError::BadRequest(format!(
+            "invalid {var}: {other} (expected true or false)"
+        ))),
+    }
+}
@@ -1,31 +1,38 @@
+use std::fmt::Write as _;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
 use base64::Engine;
 use base64::engine::general_purpose::STANDARD;
-use rcgen::{KeyPair, PKCS_ED25519};
-use ring::signature;
-use ring::signature::KeyPair as RingKeyPair;
 use sha2::{Digest, Sha256};
 
-use crate::error::{AppError, AppResult};
+use crate::{
+    error::{AppError, AppResult},
+    keys::{self, KeyAlgorithm, SigningKey},
+    models::SessionScope,
+};
 
 #[derive(Debug, Clone)]
 pub struct GeneratedKeypair {
+    pub algorithm: KeyAlgorithm,
     pub private_key_pem: String,
     pub public_key_b64: String,
 }
 
-pub fn generate_ed25519_keypair() -> AppResult<GeneratedKeypair> {
-    let generated = KeyPair::generate_for(&PKCS_ED25519)?;
-    let private_key_der = generated.serialize_der();
-    let private_key_pem = generated.serialize_pem();
-    let ring_key = signature::Ed25519KeyPair::from_pkcs8(private_key_der.as_ref())?;
-    let public_key_b64 = STANDARD.encode(ring_key.public_key().as_ref());
-
+pub fn generate_keypair(algorithm: KeyAlgorithm) -> AppResult<GeneratedKeypair> {
+    let (private_key_pem, public_key) = keys::generate(algorithm)?;
     Ok(GeneratedKeypair {
+        algorithm,
         private_key_pem,
-        public_key_b64,
+        public_key_b64: public_key.public_key_b64,
     })
 }
 
+pub fn generate_ed25519_keypair() -> AppResult<GeneratedKeypair> {
+    generate_keypair(KeyAlgorithm::Ed25519)
+}
+
 pub fn sha256_hex(bytes: &[u8]) -> String {
     let mut hasher = Sha256::new();
     hasher.update(bytes);
@@ -33,6 +40,32 @@ pub fn sha256_hex(bytes: &[u8]) -> String {
     hex::encode(digest)
 }
 
+/// Which canonical string a request was signed over, from
+/// `X-Signature-Version`. Requests without the header are version 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum SignatureVersion {
+    #[value(name = "1")]
+    V1 = 1,
+    #[value(name = "2")]
+    V2 = 2,
+}
+
+impl SignatureVersion {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim() {
+            "1" => Some(Self::V1),
+            "2" => Some(Self::V2),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for SignatureVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", *self as u8)
+    }
+}
+
 pub fn canonical_string(
     timestamp: i64,
     method: &str,
@@ -44,7 +77,115 @@ pub fn canonical_string(
     format!("{timestamp}\n{method}\n{path_and_query}\n{nonce}\n{body_hash}")
 }
 
+/// Version 2 inputs. On top of version 1 they bind the `Host` and
+/// `Content-Type` headers, the id of the key that signed and any headers
+/// listed in `X-Signed-Headers`.
+#[derive(Debug, Clone, Copy)]
+pub struct CanonicalRequestV2<'a> {
+    pub timestamp: i64,
+    pub method: &'a str,
+    pub host: &'a str,
+    pub path_and_query: &'a str,
+    pub content_type: &'a str,
+    pub nonce: &'a str,
+    pub key_id: &'a str,
+    /// Names and values of the `X-Signed-Headers` headers, in listed order.
+    pub signed_headers: &'a [(String, String)],
+    pub body: &'a [u8],
+}
+
+impl CanonicalRequestV2<'_> {
+    /// The host is lowercased; an absent `Content-Type` is an empty line.
+    /// Each signed header adds a `name:value` line, name lowercased and
+    /// value trimmed, before the body hash.
+    pub fn canonical_string(&self) -> String {
+        let mut canonical = format!(
+            "v2\n{}\n{}\n{}\n{}\n{}\n{}\n{}\n",
+            self.timestamp,
+            self.method,
+            self.host.to_ascii_lowercase(),
+            self.path_and_query,
+            self.content_type,
+            self.nonce,
+            self.key_id,
+        );
+        for (name, value) in self.signed_headers {
+            let _ = writeln!(canonical, "{}:{}", name.to_ascii_lowercase(), value.trim());
+        }
+        canonical.push_str(&sha256_hex(self.body));
+        canonical
+    }
+}
+
+/// Short id of a base64 public key, sent as `X-Key-Id`: the first 16 hex
+/// digits of the SHA-256 of the key bytes.
+pub fn key_id(public_key_b64: &str) -> AppResult<String> {
+    let public_key = STANDARD
+        .decode(public_key_b64)
+        .map_err(|_| AppError::Unauthorized(String::from("invalid public key encoding")))?;
+    let mut id = sha256_hex(&public_key);
+    id.truncate(16);
+    Ok(id)
+}
+
+/// The base64 public key for a PKCS#8 or OpenSSH private key PEM.
+pub fn public_key_from_pem(private_key_pem: &str) -> AppResult<String> {
+    Ok(SigningKey::from_pem(private_key_pem)?
+        .public_key()
+        .public_key_b64)
+}
+
+/// What the server signs in `X-Server-Signature`: the request it answers and
+/// the response itself.
+#[derive(Debug, Clone, Copy)]
+pub struct CanonicalResponse<'a> {
+    /// The request's `X-Nonce` or RFC 9421 `nonce`, empty if it had neither.
+    pub nonce: &'a str,
+    pub method: &'a str,
+    pub path_and_query: &'a str,
+    /// When the response was signed, sent as `X-Server-Timestamp`.
+    pub timestamp: i64,
+    pub status: u16,
+    pub body: &'a [u8],
+}
+
+impl CanonicalResponse<'_> {
+    pub fn canonical_string(&self) -> String {
+        let body_hash = sha256_hex(self.body);
+        format!(
+            "{}\n{}\n{}\n{}\n{}\n{body_hash}",
+            self.nonce, self.method, self.path_and_query, self.timestamp, self.status,
+        )
+    }
+}
+
+/// What a client signs to open a console session: the server's challenge,
+/// bound to the host that issued it and to the scopes asked for, in the order
+/// they are sent.
+pub fn session_canonical_string(
+    challenge: &str,
+    host: &str,
+    client_id: &str,
+    scopes: &[SessionScope],
+) -> String {
+    let scopes = scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "session\n{challenge}\n{}\n{client_id}\n{scopes}",
+        host.to_ascii_lowercase()
+    )
+}
+
+pub fn sign_canonical(private_key_pem: &str, canonical: &str) -> AppResult<String> {
+    let key = SigningKey::from_pem(private_key_pem)?;
+    Ok(STANDARD.encode(key.sign(canonical.as_bytes())?))
+}
+
 pub fn verify_signature(
+    algorithm: KeyAlgorithm,
     public_key_b64: &str,
     canonical: &str,
     signature_b64: &str,
@@ -57,10 +198,153 @@ pub fn verify_signature(
         .decode(signature_b64)
         .map_err(|_| AppError::Unauthorized(String::from("invalid signature encoding")))?;
 
-    let verifier = signature::UnparsedPublicKey::new(&signature::ED25519, public_key);
-    verifier
-        .verify(canonical.as_bytes(), &signature)
-        .map_err(|_| AppError::Unauthorized(String::from("signature verification failed")))?;
+    keys::verify(algorithm, &public_key, canonical.as_bytes(), &signature)
+}
+
+/// Writes private key material readable by its owner only. An existing
+/// file is truncated and has its permissions tightened first.
+pub fn write_secret_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        CanonicalRequestV2, CanonicalResponse, canonical_string, generate_ed25519_keypair, key_id,
+        public_key_from_pem, sign_canonical, verify_signature, write_secret_file,
+    };
+
+    #[test]
+    fn signed_canonical_verifies_against_public_key() {
+        let keypair = generate_ed25519_keypair().unwrap();
+        let canonical = canonical_string(1_700_000_000, "GET", "/api/projects", "n-1", b"");
+        let signature = sign_canonical(&keypair.private_key_pem, &canonical).unwrap();
+
+        verify_signature(
+            keypair.algorithm,
+            &keypair.public_key_b64,
+            &canonical,
+            &signature,
+        )
+        .unwrap();
+        let tampered = canonical_string(1_700_000_000, "GET", "/api/projects", "n-2", b"");
+        assert!(
+            verify_signature(
+                keypair.algorithm,
+                &keypair.public_key_b64,
+                &tampered,
+                &signature
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn v2_canonical_binds_host_content_type_and_key() {
+        let keypair = generate_ed25519_keypair().unwrap();
+        assert_eq!(
+            public_key_from_pem(&keypair.private_key_pem).unwrap(),
+            keypair.public_key_b64
+        );
+        let key_id = key_id(&keypair.public_key_b64).unwrap();
+        assert_eq!(key_id.len(), 16);
+
+        let request = CanonicalRequestV2 {
+            timestamp: 1_700_000_000,
+            method: "PUT",
+            host: "Config.Example.com",
+            path_and_query: "/api/projects",
+            content_type: "application/json",
+            nonce: "n-1",
+            key_id: &key_id,
+            signed_headers: &[],
+            body: b"{}",
+        };
+        let canonical = request.canonical_string();
+        assert!(canonical.starts_with("v2\n1700000000\nPUT\nconfig.example.com\n"));
+        assert_eq!(canonical.lines().count(), 9);
+        let signature = sign_canonical(&keypair.private_key_pem, &canonical).unwrap();
+        verify_signature(
+            keypair.algorithm,
+            &keypair.public_key_b64,
+            &canonical,
+            &signature,
+        )
+        .unwrap();
+
+        let other_host = CanonicalRequestV2 {
+            host: "evil.example.com",
+            ..request
+        };
+        assert!(
+            verify_signature(
+                keypair.algorithm,
+                &keypair.public_key_b64,
+                &other_host.canonical_string(),
+                &signature
+            )
+            .is_err()
+        );
+
+        let otp = [(String::from("X-OTP"), String::from(" 123456 "))];
+        let with_otp = CanonicalRequestV2 {
+            signed_headers: &otp,
+            ..request
+        }
+        .canonical_string();
+        assert!(with_otp.contains(&format!("\n{key_id}\nx-otp:123456\n")));
+    }
+
+    #[test]
+    fn response_canonical_binds_request_status_and_body() {
+        let response = CanonicalResponse {
+            nonce: "n-1",
+            method: "GET",
+            path_and_query: "/api/projects",
+            timestamp: 1_700_000_000,
+            status: 200,
+            body: b"",
+        };
+        assert_eq!(
+            response.canonical_string(),
+            "n-1\nGET\n/api/projects\n1700000000\n200\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_ne!(
+            response.canonical_string(),
+            CanonicalResponse {
+                status: 403,
+                ..response
+            }
+            .canonical_string()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn secret_files_are_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = std::env::temp_dir().join(format!("cloudconfig-key-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "old contents that are longer").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
 
-    Ok(())
+        write_secret_file(&path, b"secret").unwrap();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "secret");
+        std::fs::remove_file(&path).unwrap();
+    }
 }
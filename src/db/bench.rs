@@ -0,0 +1,148 @@
+//! Load test for the storage calls behind every signed `/api` read. Ignored by
+//! default; run it with
+//!
+//! ```text
+//! cargo test --release auth_path_throughput -- --ignored --nocapture
+//! ```
+//!
+//! Set `CLOUDCONFIG_TEST_POSTGRES_URL` to include Postgres.
+
+use std::time::{Duration, Instant};
+
+use uuid::Uuid;
+
+use super::{Database, LibsqlStore, PostgresStore};
+use crate::{config::AppConfig, keys::KeyAlgorithm};
+
+const TASKS: usize = 32;
+const REQUESTS_PER_TASK: usize = 250;
+const CONFIGS_PER_PROJECT: usize = 20;
+
+#[derive(Clone, Copy)]
+enum ReadPath {
+    /// Permission lookup, project lookup and config list as separate calls.
+    Separate,
+    /// Permission check and config read through `read_configs`.
+    Collapsed,
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+#[ignore = "benchmark"]
+async fn auth_path_throughput() {
+    let dir = std::env::temp_dir().join(format!("cloudconfig-bench-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    println!(
+        "{:<10} {:>5} {:<10} {:>10} {:>9} {:>9}",
+        "backend", "pool", "reads", "req/s", "p50", "p99"
+    );
+    for pool_size in [1, 8] {
+        for path in [ReadPath::Separate, ReadPath::Collapsed] {
+            let file = dir.join(format!("bench-{pool_size}-{}.db", Uuid::new_v4()));
+            let config = AppConfig {
+                database_pool_size: pool_size,
+                ..AppConfig::for_tests(&file.display().to_string())
+            };
+            let db = Database::new(LibsqlStore::connect(&config).await.unwrap());
+            run("libsql", pool_size, path, db).await;
+        }
+    }
+
+    if let Ok(url) = std::env::var("CLOUDCONFIG_TEST_POSTGRES_URL") {
+        for pool_size in [1, 8] {
+            for path in [ReadPath::Separate, ReadPath::Collapsed] {
+                let url = super::conformance::reset_postgres(&url, "bench").await;
+                let db =
+                    Database::new(PostgresStore::connect(&url, pool_size, None).await.unwrap());
+                run("postgres", pool_size, path, db).await;
+            }
+        }
+    }
+
+    std::fs::remove_dir_all(&dir).ok();
+}
+
+async fn run(backend: &str, pool_size: usize, path: ReadPath, db: Database) {
+    db.migrate().await.unwrap();
+    let client = db
+        .create_client("bench", "pk", KeyAlgorithm::Ed25519, false)
+        .await
+        .unwrap();
+    let project = db.create_project("bench", "").await.unwrap();
+    db.set_permission(&client.id, &project.id, true, false)
+        .await
+        .unwrap();
+    for i in 0..CONFIGS_PER_PROJECT {
+        db.upsert_config(&project.id, &format!("key.{i}"), "\"value\"")
+            .await
+            .unwrap();
+    }
+
+    let started = Instant::now();
+    let mut tasks = Vec::with_capacity(TASKS);
+    for _ in 0..TASKS {
+        let db = db.clone();
+        tasks.push(tokio::spawn(async move {
+            let mut latencies = Vec::with_capacity(REQUESTS_PER_TASK);
+            for _ in 0..REQUESTS_PER_TASK {
+                let request_started = Instant::now();
+                signed_read(&db, client.id, project.id, path).await;
+                latencies.push(request_started.elapsed());
+            }
+            latencies
+        }));
+    }
+
+    let mut latencies = Vec::with_capacity(TASKS * REQUESTS_PER_TASK);
+    for task in tasks {
+        latencies.extend(task.await.unwrap());
+    }
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+
+    #[allow(clippy::cast_precision_loss)]
+    let throughput = latencies.len() as f64 / elapsed.as_secs_f64();
+    let label = match path {
+        ReadPath::Separate => "separate",
+        ReadPath::Collapsed => "collapsed",
+    };
+    println!(
+        "{backend:<10} {pool_size:>5} {label:<10} {throughput:>10.0} {:>9} {:>9}",
+        format_latency(percentile(&latencies, 50)),
+        format_latency(percentile(&latencies, 99)),
+    );
+}
+
+/// The storage work of one authenticated `GET /api/projects/{id}/configs`.
+async fn signed_read(db: &Database, client_id: Uuid, project_id: Uuid, path: ReadPath) {
+    let client = db.get_client_by_id(&client_id).await.unwrap().unwrap();
+    let now = crate::auth::current_unix_timestamp().unwrap();
+    db.register_nonce(&client.id, &Uuid::new_v4().to_string(), now, 600)
+        .await
+        .unwrap();
+
+    let configs = match path {
+        ReadPath::Separate => {
+            let permission = db.get_permission(&client_id, &project_id).await.unwrap();
+            assert!(permission.is_some_and(|p| p.can_read));
+            db.list_configs_for_project(&project_id).await.unwrap()
+        }
+        ReadPath::Collapsed => {
+            let read = db
+                .read_configs(&client_id, &project_id, None)
+                .await
+                .unwrap();
+            read.unwrap().configs
+        }
+    };
+    assert_eq!(configs.len(), CONFIGS_PER_PROJECT);
+}
+
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let index = (sorted.len() * percent / 100).min(sorted.len() - 1);
+    sorted[index]
+}
+
+fn format_latency(latency: Duration) -> String {
+    format!("{:.2}ms", latency.as_secs_f64() * 1000.0)
+}
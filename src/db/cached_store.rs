@@ -0,0 +1,737 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use super::{ConfigSnapshot, ProjectConfigs, Storage};
+use crate::{
+    backup::{Backup, RestoreMode, RestoreSummary},
+    error::{AppError, AppResult},
+    keys::KeyAlgorithm,
+    migrations::SchemaStatus,
+    models::{
+        BatchWriteResponse, CacheStats, ChangeFeed, Client, ClientCertificate, ClientPermission,
+        ConfigItem, ConfigOperation, OtpEnrollment, PendingAction, PendingActionStatus, Project,
+        ReplicaStatus, Session,
+    },
+};
+
+/// Read-through cache in front of another [`Storage`] for the lookups every
+/// signed request makes: clients, permissions, a project's configs and the
+/// certificates mTLS requests present.
+///
+/// Writes made through this store drop the affected entries directly. Writes
+/// made by other instances are picked up by
+/// [`CachedStore::check_for_changes`], which compares the database's change
+/// sequence, and in the worst case by entry expiry after `ttl`.
+#[derive(Debug)]
+pub struct CachedStore {
+    inner: Arc<dyn Storage>,
+    ttl: Duration,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    clients: HashMap<Uuid, Entry<Client>>,
+    permissions: HashMap<(Uuid, Uuid), Entry<Option<ClientPermission>>>,
+    configs: HashMap<Uuid, Entry<ConfigSnapshot>>,
+    /// Issued certificates by fingerprint, for mTLS requests.
+    certificates: HashMap<String, Entry<ClientCertificate>>,
+    /// Bumped by every invalidation. A miss only fills the cache if no
+    /// invalidation happened while it was reading from the inner store.
+    generation: u64,
+    change_seq: Option<i64>,
+}
+
+#[derive(Debug)]
+struct Entry<T> {
+    value: T,
+    expires_at: Instant,
+}
+
+impl CacheState {
+    fn clear(&mut self) {
+        self.clients.clear();
+        self.permissions.clear();
+        self.configs.clear();
+        self.certificates.clear();
+    }
+
+    fn evict_expired(&mut self) {
+        let now = Instant::now();
+        self.clients.retain(|_, entry| entry.expires_at > now);
+        self.permissions.retain(|_, entry| entry.expires_at > now);
+        self.configs.retain(|_, entry| entry.expires_at > now);
+        self.certificates.retain(|_, entry| entry.expires_at > now);
+    }
+}
+
+fn fresh<K: Eq + Hash, T: Clone>(map: &HashMap<K, Entry<T>>, key: &K) -> Option<T> {
+    map.get(key)
+        .filter(|entry| entry.expires_at > Instant::now())
+        .map(|entry| entry.value.clone())
+}
+
+impl CachedStore {
+    pub fn new(inner: Arc<dyn Storage>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            state: Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            invalidations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        let hits = self.hits.load(Ordering::Relaxed);
+        let misses = self.misses.load(Ordering::Relaxed);
+        let (entries, change_seq) = self.state.lock().map_or((0, None), |state| {
+            (
+                state.clients.len()
+                    + state.permissions.len()
+                    + state.configs.len()
+                    + state.certificates.len(),
+                state.change_seq,
+            )
+        });
+
+        #[allow(clippy::cast_precision_loss)]
+        let hit_rate = if hits + misses == 0 {
+            0.0
+        } else {
+            hits as f64 / (hits + misses) as f64
+        };
+
+        CacheStats {
+            hits,
+            misses,
+            hit_rate,
+            entries,
+            invalidations: self.invalidations.load(Ordering::Relaxed),
+            ttl_seconds: self.ttl.as_secs(),
+            change_seq,
+        }
+    }
+
+    /// Drops every entry if the database changed since the last check, and
+    /// expired entries otherwise. Returns whether the cache was cleared.
+    pub async fn check_for_changes(&self) -> AppResult<bool> {
+        let seq = self.inner.change_seq().await?;
+        let mut state = self.lock()?;
+        if state.change_seq.replace(seq) == Some(seq) {
+            state.evict_expired();
+            return Ok(false);
+        }
+
+        state.clear();
+        state.generation += 1;
+        self.invalidations.fetch_add(1, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    fn lock(&self) -> AppResult<MutexGuard<'_, CacheState>> {
+        self.state
+            .lock()
+            .map_err(|_| AppError::Internal(String::from("cache lock poisoned")))
+    }
+
+    /// `Ok(value)` on a hit, otherwise the generation to pass to [`Self::fill`].
+    fn lookup<T>(&self, read: impl FnOnce(&CacheState) -> Option<T>) -> AppResult<Result<T, u64>> {
+        let state = self.lock()?;
+        if let Some(value) = read(&state) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(Ok(value));
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        Ok(Err(state.generation))
+    }
+
+    fn fill(&self, generation: u64, write: impl FnOnce(&mut CacheState, Instant)) -> AppResult<()> {
+        let mut state = self.lock()?;
+        if state.generation == generation {
+            write(&mut state, Instant::now() + self.ttl);
+        }
+
+        Ok(())
+    }
+
+    fn invalidate(&self, write: impl FnOnce(&mut CacheState)) -> AppResult<()> {
+        let mut state = self.lock()?;
+        write(&mut state);
+        state.generation += 1;
+        self.invalidations.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn cached_snapshot(&self, project_id: &Uuid) -> AppResult<ConfigSnapshot> {
+        let generation = match self.lookup(|state| fresh(&state.configs, project_id))? {
+            Ok(snapshot) => return Ok(snapshot),
+            Err(generation) => generation,
+        };
+
+        let snapshot = self.inner.config_snapshot(project_id).await?;
+        self.fill(generation, |state, expires_at| {
+            state.configs.insert(
+                *project_id,
+                Entry {
+                    value: snapshot.clone(),
+                    expires_at,
+                },
+            );
+        })?;
+        Ok(snapshot)
+    }
+}
+
+#[async_trait]
+impl Storage for CachedStore {
+    async fn migrate(&self) -> AppResult<Vec<i64>> {
+        self.inner.migrate().await
+    }
+
+    async fn schema_status(&self) -> AppResult<SchemaStatus> {
+        self.inner.schema_status().await
+    }
+
+    async fn ping(&self) -> AppResult<()> {
+        self.inner.ping().await
+    }
+
+    async fn change_seq(&self) -> AppResult<i64> {
+        self.inner.change_seq().await
+    }
+
+    async fn sync(&self) -> AppResult<Option<ReplicaStatus>> {
+        self.inner.sync().await
+    }
+
+    fn replica_status(&self) -> Option<ReplicaStatus> {
+        self.inner.replica_status()
+    }
+
+    async fn get_client_by_id(&self, client_id: &Uuid) -> AppResult<Option<Client>> {
+        let generation = match self.lookup(|state| fresh(&state.clients, client_id))? {
+            Ok(client) => return Ok(Some(client)),
+            Err(generation) => generation,
+        };
+
+        // Unknown ids are not cached, so probing random ids cannot grow the map.
+        let client = self.inner.get_client_by_id(client_id).await?;
+        if let Some(client) = &client {
+            self.fill(generation, |state, expires_at| {
+                state.clients.insert(
+                    *client_id,
+                    Entry {
+                        value: client.clone(),
+                        expires_at,
+                    },
+                );
+            })?;
+        }
+
+        Ok(client)
+    }
+
+    async fn get_admin_client(&self) -> AppResult<Option<Client>> {
+        self.inner.get_admin_client().await
+    }
+
+    async fn create_client(
+        &self,
+        name: &str,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        is_admin: bool,
+    ) -> AppResult<Client> {
+        self.inner
+            .create_client(name, public_key, key_algorithm, is_admin)
+            .await
+    }
+
+    async fn set_client_public_key(
+        &self,
+        client_id: &Uuid,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        rotated_at: i64,
+    ) -> AppResult<bool> {
+        let updated = self
+            .inner
+            .set_client_public_key(client_id, public_key, key_algorithm, rotated_at)
+            .await?;
+        self.invalidate(|state| {
+            state.clients.remove(client_id);
+            state
+                .certificates
+                .retain(|_, entry| entry.value.client_id != *client_id);
+        })?;
+        Ok(updated)
+    }
+
+    async fn set_client_networks(&self, client_id: &Uuid, networks: &[String]) -> AppResult<bool> {
+        let updated = self.inner.set_client_networks(client_id, networks).await?;
+        self.invalidate(|state| {
+            state.clients.remove(client_id);
+        })?;
+        Ok(updated)
+    }
+
+    async fn list_clients(&self) -> AppResult<Vec<Client>> {
+        self.inner.list_clients().await
+    }
+
+    async fn delete_client(&self, client_id: &Uuid) -> AppResult<bool> {
+        let deleted = self.inner.delete_client(client_id).await?;
+        self.invalidate(|state| {
+            state.clients.remove(client_id);
+            state
+                .permissions
+                .retain(|(client, _), _| client != client_id);
+            state
+                .certificates
+                .retain(|_, entry| entry.value.client_id != *client_id);
+        })?;
+        Ok(deleted)
+    }
+
+    async fn create_project(&self, name: &str, description: &str) -> AppResult<Project> {
+        self.inner.create_project(name, description).await
+    }
+
+    async fn get_project_by_id(&self, project_id: &Uuid) -> AppResult<Option<Project>> {
+        self.inner.get_project_by_id(project_id).await
+    }
+
+    async fn get_project_by_name(&self, name: &str) -> AppResult<Option<Project>> {
+        self.inner.get_project_by_name(name).await
+    }
+
+    async fn list_projects(&self) -> AppResult<Vec<Project>> {
+        self.inner.list_projects().await
+    }
+
+    async fn list_projects_for_client(&self, client_id: &Uuid) -> AppResult<Vec<Project>> {
+        self.inner.list_projects_for_client(client_id).await
+    }
+
+    async fn delete_project(&self, project_id: &Uuid) -> AppResult<bool> {
+        let deleted = self.inner.delete_project(project_id).await?;
+        self.invalidate(|state| {
+            state.configs.remove(project_id);
+            state
+                .permissions
+                .retain(|(_, project), _| project != project_id);
+        })?;
+        Ok(deleted)
+    }
+
+    async fn set_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+        can_read: bool,
+        can_write: bool,
+    ) -> AppResult<ClientPermission> {
+        let permission = self
+            .inner
+            .set_permission(client_id, project_id, can_read, can_write)
+            .await?;
+        self.invalidate(|state| {
+            state.permissions.remove(&(*client_id, *project_id));
+        })?;
+        Ok(permission)
+    }
+
+    async fn get_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+    ) -> AppResult<Option<ClientPermission>> {
+        let key = (*client_id, *project_id);
+        let generation = match self.lookup(|state| fresh(&state.permissions, &key))? {
+            Ok(permission) => return Ok(permission),
+            Err(generation) => generation,
+        };
+
+        // A missing grant is cached too; it is what a `Forbidden` answer reads.
+        let permission = self.inner.get_permission(client_id, project_id).await?;
+        self.fill(generation, |state, expires_at| {
+            state.permissions.insert(
+                key,
+                Entry {
+                    value: permission.clone(),
+                    expires_at,
+                },
+            );
+        })?;
+        Ok(permission)
+    }
+
+    async fn list_permissions_for_client(
+        &self,
+        client_id: &Uuid,
+    ) -> AppResult<Vec<ClientPermission>> {
+        self.inner.list_permissions_for_client(client_id).await
+    }
+
+    async fn delete_permission(&self, client_id: &Uuid, project_id: &Uuid) -> AppResult<bool> {
+        let deleted = self.inner.delete_permission(client_id, project_id).await?;
+        self.invalidate(|state| {
+            state.permissions.remove(&(*client_id, *project_id));
+        })?;
+        Ok(deleted)
+    }
+
+    async fn register_nonce(
+        &self,
+        client_id: &Uuid,
+        nonce: &str,
+        now_timestamp: i64,
+        ttl_seconds: i64,
+    ) -> AppResult<()> {
+        self.inner
+            .register_nonce(client_id, nonce, now_timestamp, ttl_seconds)
+            .await
+    }
+
+    async fn purge_expired_nonces(&self, cutoff: i64) -> AppResult<u64> {
+        self.inner.purge_expired_nonces(cutoff).await
+    }
+
+    async fn create_client_certificate(&self, certificate: &ClientCertificate) -> AppResult<()> {
+        self.inner.create_client_certificate(certificate).await
+    }
+
+    async fn get_client_certificate(
+        &self,
+        fingerprint: &str,
+    ) -> AppResult<Option<ClientCertificate>> {
+        let key = fingerprint.to_owned();
+        let generation = match self.lookup(|state| fresh(&state.certificates, &key))? {
+            Ok(certificate) => return Ok(Some(certificate)),
+            Err(generation) => generation,
+        };
+
+        let certificate = self.inner.get_client_certificate(fingerprint).await?;
+        if let Some(certificate) = &certificate {
+            self.fill(generation, |state, expires_at| {
+                state.certificates.insert(
+                    key,
+                    Entry {
+                        value: certificate.clone(),
+                        expires_at,
+                    },
+                );
+            })?;
+        }
+
+        Ok(certificate)
+    }
+
+    async fn list_client_certificates(
+        &self,
+        client_id: Option<&Uuid>,
+    ) -> AppResult<Vec<ClientCertificate>> {
+        self.inner.list_client_certificates(client_id).await
+    }
+
+    async fn revoke_client_certificate(&self, serial: &str, revoked_at: i64) -> AppResult<bool> {
+        let revoked = self
+            .inner
+            .revoke_client_certificate(serial, revoked_at)
+            .await?;
+        self.invalidate(|state| {
+            state
+                .certificates
+                .retain(|_, entry| entry.value.serial != serial);
+        })?;
+        Ok(revoked)
+    }
+
+    async fn create_session_challenge(
+        &self,
+        challenge: &str,
+        client_id: &Uuid,
+        expires_at: i64,
+        now_timestamp: i64,
+    ) -> AppResult<()> {
+        self.inner
+            .create_session_challenge(challenge, client_id, expires_at, now_timestamp)
+            .await
+    }
+
+    async fn take_session_challenge(
+        &self,
+        challenge: &str,
+        now_timestamp: i64,
+    ) -> AppResult<Option<Uuid>> {
+        self.inner
+            .take_session_challenge(challenge, now_timestamp)
+            .await
+    }
+
+    async fn create_session(&self, session: &Session, token_hash: &str) -> AppResult<()> {
+        self.inner.create_session(session, token_hash).await
+    }
+
+    // Not cached, so a revoked session stops working on every instance at once.
+    async fn get_session_by_token(&self, token_hash: &str) -> AppResult<Option<Session>> {
+        self.inner.get_session_by_token(token_hash).await
+    }
+
+    async fn list_sessions(&self, client_id: Option<&Uuid>) -> AppResult<Vec<Session>> {
+        self.inner.list_sessions(client_id).await
+    }
+
+    async fn revoke_session(&self, session_id: &Uuid, revoked_at: i64) -> AppResult<bool> {
+        self.inner.revoke_session(session_id, revoked_at).await
+    }
+
+    async fn set_otp_secret(&self, client_id: &Uuid, secret: &str) -> AppResult<()> {
+        self.inner.set_otp_secret(client_id, secret).await
+    }
+
+    async fn get_otp(&self, client_id: &Uuid) -> AppResult<Option<OtpEnrollment>> {
+        self.inner.get_otp(client_id).await
+    }
+
+    async fn enable_otp(&self, client_id: &Uuid, enabled_at: i64, step: i64) -> AppResult<bool> {
+        self.inner.enable_otp(client_id, enabled_at, step).await
+    }
+
+    async fn accept_otp_step(&self, client_id: &Uuid, step: i64) -> AppResult<bool> {
+        self.inner.accept_otp_step(client_id, step).await
+    }
+
+    async fn record_otp_failure(&self, client_id: &Uuid) -> AppResult<i64> {
+        self.inner.record_otp_failure(client_id).await
+    }
+
+    async fn delete_otp(&self, client_id: &Uuid) -> AppResult<bool> {
+        self.inner.delete_otp(client_id).await
+    }
+
+    async fn replace_recovery_codes(
+        &self,
+        client_id: &Uuid,
+        code_hashes: &[String],
+    ) -> AppResult<()> {
+        self.inner
+            .replace_recovery_codes(client_id, code_hashes)
+            .await
+    }
+
+    async fn use_recovery_code(
+        &self,
+        client_id: &Uuid,
+        code_hash: &str,
+        used_at: i64,
+    ) -> AppResult<bool> {
+        self.inner
+            .use_recovery_code(client_id, code_hash, used_at)
+            .await
+    }
+
+    async fn create_pending_action(&self, action: &PendingAction) -> AppResult<()> {
+        self.inner.create_pending_action(action).await
+    }
+
+    async fn get_pending_action(&self, action_id: &Uuid) -> AppResult<Option<PendingAction>> {
+        self.inner.get_pending_action(action_id).await
+    }
+
+    async fn list_pending_actions(&self) -> AppResult<Vec<PendingAction>> {
+        self.inner.list_pending_actions().await
+    }
+
+    async fn approve_pending_action(
+        &self,
+        action_id: &Uuid,
+        client_id: &Uuid,
+        approved_at: i64,
+    ) -> AppResult<bool> {
+        self.inner
+            .approve_pending_action(action_id, client_id, approved_at)
+            .await
+    }
+
+    async fn resolve_pending_action(
+        &self,
+        action_id: &Uuid,
+        from: PendingActionStatus,
+        to: PendingActionStatus,
+        resolved_at: i64,
+        resolved_by: Option<&Uuid>,
+    ) -> AppResult<bool> {
+        self.inner
+            .resolve_pending_action(action_id, from, to, resolved_at, resolved_by)
+            .await
+    }
+
+    async fn expire_pending_actions(&self, now_timestamp: i64) -> AppResult<u64> {
+        self.inner.expire_pending_actions(now_timestamp).await
+    }
+
+    async fn upsert_config(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+        value: &str,
+    ) -> AppResult<ConfigItem> {
+        let item = self.inner.upsert_config(project_id, key, value).await?;
+        self.invalidate(|state| {
+            state.configs.remove(project_id);
+        })?;
+        Ok(item)
+    }
+
+    async fn apply_config_batch(
+        &self,
+        project_id: &Uuid,
+        operations: &[ConfigOperation],
+    ) -> AppResult<BatchWriteResponse> {
+        let response = self.inner.apply_config_batch(project_id, operations).await;
+        self.invalidate(|state| {
+            state.configs.remove(project_id);
+        })?;
+        response
+    }
+
+    async fn restore_backup(
+        &self,
+        backup: &Backup,
+        mode: RestoreMode,
+    ) -> AppResult<RestoreSummary> {
+        let summary = self.inner.restore_backup(backup, mode).await?;
+        self.invalidate(CacheState::clear)?;
+        Ok(summary)
+    }
+
+    async fn list_configs_for_project(&self, project_id: &Uuid) -> AppResult<Vec<ConfigItem>> {
+        Ok(self.cached_snapshot(project_id).await?.configs)
+    }
+
+    async fn config_snapshot(&self, project_id: &Uuid) -> AppResult<ConfigSnapshot> {
+        self.cached_snapshot(project_id).await
+    }
+
+    async fn config_changes(
+        &self,
+        project_id: &Uuid,
+        since: i64,
+        limit: usize,
+    ) -> AppResult<ChangeFeed> {
+        self.inner.config_changes(project_id, since, limit).await
+    }
+
+    async fn prune_config_changes(&self, keep: u64) -> AppResult<u64> {
+        self.inner.prune_config_changes(keep).await
+    }
+
+    async fn get_config_by_key(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+    ) -> AppResult<Option<ConfigItem>> {
+        self.inner.get_config_by_key(project_id, key).await
+    }
+
+    async fn read_configs(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+        key: Option<&str>,
+    ) -> AppResult<Option<ProjectConfigs>> {
+        let Some(permission) = self.get_permission(client_id, project_id).await? else {
+            return Ok(None);
+        };
+
+        let mut snapshot = self.cached_snapshot(project_id).await?;
+        if let Some(key) = key {
+            snapshot.configs.retain(|item| item.key == key);
+        }
+
+        Ok(Some(ProjectConfigs {
+            permission,
+            revision: snapshot.revision,
+            configs: snapshot.configs,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::CachedStore;
+    use crate::db::{MemoryStore, Storage};
+    use crate::keys::KeyAlgorithm;
+    use crate::models::ClientCertificate;
+
+    #[tokio::test]
+    async fn serves_hits_and_sees_writes_from_other_instances() {
+        let shared = Arc::new(MemoryStore::new());
+        let cache = CachedStore::new(shared.clone(), Duration::from_secs(60));
+        cache.check_for_changes().await.unwrap();
+
+        let client = cache
+            .create_client("svc", "pk", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+        let project = cache.create_project("billing", "").await.unwrap();
+        cache
+            .set_permission(&client.id, &project.id, true, false)
+            .await
+            .unwrap();
+        cache.upsert_config(&project.id, "a", "1").await.unwrap();
+
+        for _ in 0..3 {
+            let read = cache.read_configs(&client.id, &project.id, None).await;
+            assert_eq!(read.unwrap().unwrap().configs[0].value, "1");
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (4, 2));
+        assert_eq!(stats.entries, 2);
+
+        let certificate = ClientCertificate {
+            serial: String::from("01"),
+            client_id: client.id,
+            fingerprint: String::from("AB:CD"),
+            issued_at: 0,
+            expires_at: i64::MAX,
+            revoked_at: None,
+        };
+        cache.create_client_certificate(&certificate).await.unwrap();
+        cache
+            .get_client_certificate("AB:CD")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(cache.stats().entries, 3);
+
+        // A write through this cache is visible immediately.
+        cache.upsert_config(&project.id, "a", "2").await.unwrap();
+        let read = cache.read_configs(&client.id, &project.id, Some("a")).await;
+        assert_eq!(read.unwrap().unwrap().configs[0].value, "2");
+
+        // A write by another instance is only visible after the change check.
+        shared.upsert_config(&project.id, "a", "3").await.unwrap();
+        let read = cache.read_configs(&client.id, &project.id, Some("a")).await;
+        assert_eq!(read.unwrap().unwrap().configs[0].value, "2");
+        assert!(cache.check_for_changes().await.unwrap());
+        let read = cache.read_configs(&client.id, &project.id, Some("a")).await;
+        assert_eq!(read.unwrap().unwrap().configs[0].value, "3");
+    }
+}
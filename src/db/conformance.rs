@@ -0,0 +1,1061 @@
+//! Behaviour every [`Storage`] backend must share. Each test runs against the
+//! in-memory and libSQL stores, and the libSQL store behind the read cache;
+//! set `CLOUDCONFIG_TEST_POSTGRES_URL` to a
+//! disposable database to include Postgres.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use super::{CachedStore, Database, LibsqlStore, MemoryStore, PostgresStore};
+use crate::{
+    backup::{self, RestoreMode},
+    config::AppConfig,
+    error::AppError,
+    keys::KeyAlgorithm,
+    models::{
+        AdminOperation, ChangeOp, ClientCertificate, ConfigOperation, PendingAction,
+        PendingActionStatus, Session, SessionScope,
+    },
+};
+
+/// `schema` isolates each test's Postgres tables, since tests run in
+/// parallel against the same database.
+async fn backends(schema: &str) -> Vec<(&'static str, Database)> {
+    let mut backends = vec![
+        ("memory", Database::new(MemoryStore::new())),
+        (
+            "libsql",
+            Database::new(
+                LibsqlStore::connect(&AppConfig::for_tests(":memory:"))
+                    .await
+                    .unwrap(),
+            ),
+        ),
+        (
+            "cached",
+            Database::new(CachedStore::new(
+                Arc::new(
+                    LibsqlStore::connect(&AppConfig::for_tests(":memory:"))
+                        .await
+                        .unwrap(),
+                ),
+                Duration::from_secs(60),
+            )),
+        ),
+    ];
+
+    if let Ok(url) = std::env::var("CLOUDCONFIG_TEST_POSTGRES_URL") {
+        let url = reset_postgres(&url, schema).await;
+        let store = PostgresStore::connect(&url, 4, None).await.unwrap();
+        backends.push(("postgres", Database::new(store)));
+    }
+
+    for (name, db) in &backends {
+        db.migrate()
+            .await
+            .unwrap_or_else(|e| panic!("{name}: migrate failed: {e}"));
+    }
+
+    backends
+}
+
+pub(super) async fn reset_postgres(url: &str, schema: &str) -> String {
+    let (client, connection) = tokio_postgres::connect(url, tokio_postgres::NoTls)
+        .await
+        .unwrap();
+    tokio::spawn(connection);
+    client
+        .batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema};"
+        ))
+        .await
+        .unwrap();
+
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{url}{separator}options=-csearch_path%3D{schema}")
+}
+
+#[tokio::test]
+async fn migrations_are_idempotent_and_up_to_date() {
+    for (name, db) in backends("conformance_migrations").await {
+        assert!(db.migrate().await.unwrap().is_empty(), "{name}");
+        let status = db.schema_status().await.unwrap();
+        assert_eq!(status.current_version, status.latest_version, "{name}");
+        assert_eq!(status.pending().count(), 0, "{name}");
+        db.ping().await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn client_lifecycle() {
+    for (name, db) in backends("conformance_clients").await {
+        assert!(db.get_admin_client().await.unwrap().is_none(), "{name}");
+        let bootstrap = db.bootstrap_admin_if_missing("admin").await.unwrap();
+        let admin = bootstrap.expect("admin created").client;
+        assert!(admin.is_admin, "{name}");
+        assert!(
+            db.bootstrap_admin_if_missing("admin")
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        let reset = db.reset_admin().await.unwrap();
+        assert_eq!(reset.client.id, admin.id, "{name}");
+        assert_ne!(reset.client.public_key, admin.public_key, "{name}");
+
+        let client = db
+            .create_client("  svc  ", "pk", KeyAlgorithm::EcdsaP256, false)
+            .await
+            .unwrap();
+        assert_eq!(client.name, "svc", "{name}");
+        assert_eq!(client.key_algorithm, KeyAlgorithm::EcdsaP256, "{name}");
+        assert!(matches!(
+            db.create_client(" ", "pk", KeyAlgorithm::Ed25519, false)
+                .await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        let listed = db.list_clients().await.unwrap();
+        assert_eq!(listed.len(), 2, "{name}");
+        assert_eq!(
+            db.get_client_by_id(&client.id).await.unwrap().unwrap().name,
+            "svc"
+        );
+
+        assert!(
+            db.set_client_public_key(&client.id, "pk2", KeyAlgorithm::Ed25519, 100)
+                .await
+                .unwrap()
+        );
+        let rotated = db.get_client_by_id(&client.id).await.unwrap().unwrap();
+        assert_eq!(rotated.public_key, "pk2", "{name}");
+        assert_eq!(rotated.key_algorithm, KeyAlgorithm::Ed25519, "{name}");
+
+        let networks = [String::from("10.0.0.0/8"), String::from("2001:db8::/32")];
+        assert!(db.set_client_networks(&client.id, &networks).await.unwrap());
+        let restricted = db.get_client_by_id(&client.id).await.unwrap().unwrap();
+        assert_eq!(restricted.allowed_networks, networks, "{name}");
+        assert!(db.set_client_networks(&client.id, &[]).await.unwrap());
+        let unrestricted = db.get_client_by_id(&client.id).await.unwrap().unwrap();
+        assert!(unrestricted.allowed_networks.is_empty(), "{name}");
+        assert!(!db.set_client_networks(&Uuid::new_v4(), &[]).await.unwrap());
+
+        assert!(db.delete_client(&client.id).await.unwrap(), "{name}");
+        assert!(!db.delete_client(&client.id).await.unwrap(), "{name}");
+        assert!(db.get_client_by_id(&client.id).await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn projects_are_unique_and_sorted() {
+    for (name, db) in backends("conformance_projects").await {
+        let beta = db.create_project("beta", "").await.unwrap();
+        db.create_project("alpha", "first").await.unwrap();
+        assert!(
+            matches!(
+                db.create_project("beta", "").await,
+                Err(AppError::Conflict(_))
+            ),
+            "{name}"
+        );
+
+        let names: Vec<_> = db
+            .list_projects()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|p| p.name)
+            .collect();
+        assert_eq!(names, ["alpha", "beta"], "{name}");
+        assert_eq!(
+            db.get_project_by_name("beta").await.unwrap().unwrap().id,
+            beta.id
+        );
+        assert!(
+            db.get_project_by_id(&Uuid::new_v4())
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
+
+#[tokio::test]
+async fn permissions_grant_and_cascade() {
+    for (name, db) in backends("conformance_permissions").await {
+        let client = db
+            .create_client("svc", "pk", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+        let project = db.create_project("billing", "").await.unwrap();
+        db.create_project("hidden", "").await.unwrap();
+
+        assert!(matches!(
+            db.set_permission(&Uuid::new_v4(), &project.id, true, false)
+                .await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            db.set_permission(&client.id, &Uuid::new_v4(), true, false)
+                .await,
+            Err(AppError::NotFound(_))
+        ));
+
+        db.set_permission(&client.id, &project.id, true, false)
+            .await
+            .unwrap();
+        let updated = db
+            .set_permission(&client.id, &project.id, true, true)
+            .await
+            .unwrap();
+        assert!(updated.can_write, "{name}");
+
+        let visible = db.list_projects_for_client(&client.id).await.unwrap();
+        assert_eq!(visible.len(), 1, "{name}");
+        assert_eq!(visible[0].id, project.id, "{name}");
+        assert_eq!(
+            db.list_permissions_for_client(&client.id)
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+
+        assert!(db.delete_permission(&client.id, &project.id).await.unwrap());
+        assert!(
+            db.get_permission(&client.id, &project.id)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        db.set_permission(&client.id, &project.id, true, false)
+            .await
+            .unwrap();
+        db.delete_client(&client.id).await.unwrap();
+        assert!(
+            db.get_permission(&client.id, &project.id)
+                .await
+                .unwrap()
+                .is_none(),
+            "{name}: permissions must cascade with their client"
+        );
+    }
+}
+
+#[tokio::test]
+async fn configs_upsert_and_version() {
+    for (name, db) in backends("conformance_configs").await {
+        let project = db.create_project("billing", "").await.unwrap();
+
+        let first = db
+            .upsert_config(&project.id, " db.host ", "\"a\"")
+            .await
+            .unwrap();
+        assert_eq!(first.key, "db.host", "{name}");
+        assert_eq!(first.version, 1, "{name}");
+
+        let second = db
+            .upsert_config(&project.id, "db.host", "\"b\"")
+            .await
+            .unwrap();
+        assert_eq!(second.id, first.id, "{name}");
+        assert_eq!(second.version, 2, "{name}");
+        assert_eq!(second.value, "\"b\"", "{name}");
+
+        db.upsert_config(&project.id, "api.port", "8080")
+            .await
+            .unwrap();
+        let keys: Vec<_> = db
+            .list_configs_for_project(&project.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.key)
+            .collect();
+        assert_eq!(keys, ["api.port", "db.host"], "{name}");
+
+        assert!(
+            db.get_config_by_key(&project.id, "missing")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(matches!(
+            db.upsert_config(&Uuid::new_v4(), "k", "1").await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            db.list_configs_for_project(&Uuid::new_v4()).await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            db.upsert_config(&project.id, "  ", "1").await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn nonces_reject_replays() {
+    for (name, db) in backends("conformance_nonces").await {
+        let client = db
+            .create_client("svc", "pk", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+        let (now, ttl) = (1_700_000_000, 600);
+
+        db.register_nonce(&client.id, "n-1", now, ttl)
+            .await
+            .unwrap();
+        assert!(
+            matches!(
+                db.register_nonce(&client.id, "n-1", now + ttl, ttl).await,
+                Err(AppError::Unauthorized(_))
+            ),
+            "{name}"
+        );
+        db.register_nonce(&client.id, "n-2", now, ttl)
+            .await
+            .unwrap();
+        assert!(matches!(
+            db.register_nonce(&client.id, "  ", now, ttl).await,
+            Err(AppError::Unauthorized(_))
+        ));
+
+        // Past the TTL the row is reclaimed even before it has been purged.
+        db.register_nonce(&client.id, "n-1", now + ttl + 1, ttl)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.purge_expired_nonces(now + 1).await.unwrap(),
+            1,
+            "{name}: only n-2 is older than the cutoff"
+        );
+    }
+}
+
+#[tokio::test]
+async fn read_configs_checks_grant_in_one_call() {
+    for (name, db) in backends("conformance_read_configs").await {
+        let client = db
+            .create_client("svc", "pk", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+        let project = db.create_project("billing", "").await.unwrap();
+
+        assert!(
+            db.read_configs(&client.id, &project.id, None)
+                .await
+                .unwrap()
+                .is_none(),
+            "{name}"
+        );
+
+        db.set_permission(&client.id, &project.id, true, false)
+            .await
+            .unwrap();
+        let empty = db
+            .read_configs(&client.id, &project.id, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(empty.permission.can_read && !empty.permission.can_write);
+        assert!(empty.configs.is_empty(), "{name}");
+
+        db.upsert_config(&project.id, "b", "2").await.unwrap();
+        db.upsert_config(&project.id, "a", "1").await.unwrap();
+        let all = db
+            .read_configs(&client.id, &project.id, None)
+            .await
+            .unwrap()
+            .unwrap();
+        let keys: Vec<_> = all.configs.iter().map(|c| c.key.as_str()).collect();
+        assert_eq!(keys, ["a", "b"], "{name}");
+
+        let one = db
+            .read_configs(&client.id, &project.id, Some("b"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(one.configs.len(), 1, "{name}");
+        assert_eq!(one.configs[0].value, "2", "{name}");
+
+        let missing = db
+            .read_configs(&client.id, &project.id, Some("zzz"))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(missing.configs.is_empty(), "{name}");
+    }
+}
+
+async fn revision(db: &Database, project_id: &Uuid) -> i64 {
+    db.get_project_by_id(project_id)
+        .await
+        .unwrap()
+        .unwrap()
+        .revision
+}
+
+/// A restore always moves the revision on and sends feed clients that were
+/// up to date back to a full reload.
+async fn assert_restored_revision(db: &Database, project_id: &Uuid, before: i64, name: &str) {
+    assert!(revision(db, project_id).await > before, "{name}");
+    let feed = db.config_changes(project_id, before, 100).await.unwrap();
+    assert!(feed.reset, "{name}");
+}
+
+#[tokio::test]
+async fn backup_restores_in_merge_and_replace_modes() {
+    for (name, db) in backends("conformance_backup").await {
+        let admin = db
+            .bootstrap_admin_if_missing("admin")
+            .await
+            .unwrap()
+            .unwrap();
+        let client = db
+            .create_client("svc", "pk", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+        let project = db.create_project("billing", "").await.unwrap();
+        db.set_permission(&client.id, &project.id, true, true)
+            .await
+            .unwrap();
+        db.upsert_config(&project.id, "a", "1").await.unwrap();
+        db.upsert_config(&project.id, "b", "1").await.unwrap();
+        let archive = backup::create(&db).await.unwrap();
+
+        // Merge keeps newer configs and rows the archive does not know about.
+        db.upsert_config(&project.id, "a", "2").await.unwrap();
+        db.delete_permission(&client.id, &project.id).await.unwrap();
+        let extra = db.create_project("extra", "").await.unwrap();
+        let before = revision(&db, &project.id).await;
+        let summary = backup::restore(&db, &archive, RestoreMode::Merge)
+            .await
+            .unwrap();
+        assert_eq!(summary.configs, 1, "{name}");
+        assert_restored_revision(&db, &project.id, before, name).await;
+        let a = db
+            .get_config_by_key(&project.id, "a")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((a.value.as_str(), a.version), ("2", 2), "{name}");
+        assert!(
+            db.get_permission(&client.id, &project.id)
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert!(db.get_project_by_id(&extra.id).await.unwrap().is_some());
+
+        // Replace brings back exactly the archived rows.
+        let before = revision(&db, &project.id).await;
+        backup::restore(&db, &archive, RestoreMode::Replace)
+            .await
+            .unwrap();
+        assert_restored_revision(&db, &project.id, before, name).await;
+        assert!(db.get_project_by_id(&extra.id).await.unwrap().is_none());
+        let a = db
+            .get_config_by_key(&project.id, "a")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((a.value.as_str(), a.version), ("1", 1), "{name}");
+        assert_eq!(
+            db.get_admin_client().await.unwrap().unwrap().id,
+            admin.client.id,
+            "{name}"
+        );
+
+        let other = backup::Backup {
+            clients: vec![crate::models::Client {
+                id: Uuid::new_v4(),
+                ..admin.client.clone()
+            }],
+            permissions: Vec::new(),
+            ..archive.clone()
+        };
+        assert!(matches!(
+            backup::restore(&db, &other, RestoreMode::Merge).await,
+            Err(AppError::Conflict(_))
+        ));
+        let future = backup::Backup {
+            version: backup::BACKUP_VERSION + 1,
+            ..archive
+        };
+        assert!(matches!(
+            backup::restore(&db, &future, RestoreMode::Replace).await,
+            Err(AppError::BadRequest(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn config_batches_apply_atomically() {
+    for (name, db) in backends("conformance_batches").await {
+        let project = db.create_project("billing", "").await.unwrap();
+        db.upsert_config(&project.id, "db.host", "\"a\"")
+            .await
+            .unwrap();
+        db.upsert_config(&project.id, "old", "1").await.unwrap();
+
+        let set = |key: &str, value: &str, expected_version| ConfigOperation::Set {
+            key: key.to_owned(),
+            value: value.to_owned(),
+            expected_version,
+        };
+        let delete = |key: &str| ConfigOperation::Delete {
+            key: key.to_owned(),
+            expected_version: None,
+        };
+
+        // A stale expected version fails the batch without applying anything.
+        let stale = [
+            set("db.password", "\"p\"", Some(0)),
+            set("db.host", "\"b\"", Some(2)),
+        ];
+        assert!(matches!(
+            db.apply_config_batch(&project.id, &stale).await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(
+            db.get_config_by_key(&project.id, "db.password")
+                .await
+                .unwrap()
+                .is_none(),
+            "{name}"
+        );
+        assert!(matches!(
+            db.apply_config_batch(&project.id, &[delete("missing"), delete("old")])
+                .await,
+            Err(AppError::NotFound(_))
+        ));
+        assert!(matches!(
+            db.apply_config_batch(&project.id, &[delete("old"), delete(" old ")])
+                .await,
+            Err(AppError::BadRequest(_))
+        ));
+
+        let applied = db
+            .apply_config_batch(
+                &project.id,
+                &[
+                    set("db.host", "\"b\"", Some(1)),
+                    set("db.password", "\"p\"", Some(0)),
+                    delete("old"),
+                ],
+            )
+            .await
+            .unwrap();
+        let versions: Vec<_> = applied
+            .configs
+            .iter()
+            .map(|c| (c.key.as_str(), c.version))
+            .collect();
+        assert_eq!(versions, [("db.host", 2), ("db.password", 1)], "{name}");
+        assert_eq!(applied.deleted, ["old"], "{name}");
+        let keys: Vec<_> = db
+            .list_configs_for_project(&project.id)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.key)
+            .collect();
+        assert_eq!(keys, ["db.host", "db.password"], "{name}");
+    }
+}
+
+#[tokio::test]
+async fn change_feed_follows_project_revisions() {
+    for (name, db) in backends("conformance_changes").await {
+        let project = db.create_project("billing", "").await.unwrap();
+        assert_eq!(db.config_snapshot(&project.id).await.unwrap().revision, 0);
+
+        db.upsert_config(&project.id, "a", "1").await.unwrap();
+        db.upsert_config(&project.id, "a", "2").await.unwrap();
+        db.apply_config_batch(
+            &project.id,
+            &[
+                ConfigOperation::Set {
+                    key: String::from("b"),
+                    value: String::from("1"),
+                    expected_version: None,
+                },
+                ConfigOperation::Delete {
+                    key: String::from("a"),
+                    expected_version: Some(2),
+                },
+            ],
+        )
+        .await
+        .unwrap();
+
+        let snapshot = db.config_snapshot(&project.id).await.unwrap();
+        assert_eq!(snapshot.revision, 4, "{name}");
+        assert_eq!(snapshot.configs.len(), 1, "{name}");
+
+        let feed = db.config_changes(&project.id, 1, 100).await.unwrap();
+        assert!(!feed.reset, "{name}");
+        let changes: Vec<_> = feed
+            .changes
+            .iter()
+            .map(|c| (c.revision, c.key.as_str(), c.op, c.value.as_deref()))
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                (2, "a", ChangeOp::Set, Some("2")),
+                (3, "b", ChangeOp::Set, Some("1")),
+                (4, "a", ChangeOp::Delete, None),
+            ],
+            "{name}"
+        );
+        assert!(
+            db.config_changes(&project.id, 4, 100)
+                .await
+                .unwrap()
+                .changes
+                .is_empty()
+        );
+        assert!(db.config_changes(&project.id, 9, 100).await.unwrap().reset);
+
+        // Pages end at the last change they carry.
+        let page = db.config_changes(&project.id, 1, 2).await.unwrap();
+        assert_eq!(page.changes.len(), 2, "{name}");
+        assert_eq!((page.revision, page.has_more), (3, true), "{name}");
+        let page = db
+            .config_changes(&project.id, page.revision, 2)
+            .await
+            .unwrap();
+        assert_eq!(page.changes.len(), 1, "{name}");
+        assert_eq!((page.revision, page.has_more), (4, false), "{name}");
+
+        // Pruning keeps the newest revisions; older cursors must resync.
+        assert_eq!(db.prune_config_changes(1).await.unwrap(), 3, "{name}");
+        assert_eq!(db.prune_config_changes(1).await.unwrap(), 0, "{name}");
+        assert!(db.config_changes(&project.id, 2, 100).await.unwrap().reset);
+        let feed = db.config_changes(&project.id, 3, 100).await.unwrap();
+        assert!(!feed.reset, "{name}");
+        assert_eq!(feed.changes.len(), 1, "{name}");
+
+        // A restore rewrites history, so readers from before it must resync.
+        let archive = backup::create(&db).await.unwrap();
+        backup::restore(&db, &archive, RestoreMode::Replace)
+            .await
+            .unwrap();
+        let feed = db.config_changes(&project.id, 4, 100).await.unwrap();
+        assert!(feed.reset, "{name}");
+        assert!(feed.revision >= 4, "{name}");
+        assert!(
+            !db.config_changes(&project.id, feed.revision, 100)
+                .await
+                .unwrap()
+                .reset
+        );
+        assert!(matches!(
+            db.config_snapshot(&Uuid::new_v4()).await,
+            Err(AppError::NotFound(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn key_rotation_revokes_certificates() {
+    for (name, db) in backends("conformance_rotation_certificates").await {
+        let client = db
+            .create_client("svc", "pk", KeyAlgorithm::Ed25519, true)
+            .await
+            .unwrap();
+        let other = db
+            .create_client("other", "pk", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+        for (serial, client_id, revoked_at) in [
+            ("01", client.id, None),
+            ("02", client.id, Some(300)),
+            ("03", other.id, None),
+        ] {
+            db.create_client_certificate(&ClientCertificate {
+                serial: String::from(serial),
+                client_id,
+                fingerprint: format!("FP:{serial}"),
+                issued_at: 100,
+                expires_at: 3_700,
+                revoked_at,
+            })
+            .await
+            .unwrap();
+        }
+        // Warms the cache, if there is one, with the soon revoked certificate.
+        db.get_client_certificate("FP:01").await.unwrap();
+
+        db.set_client_public_key(&client.id, "pk2", KeyAlgorithm::Ed25519, 500)
+            .await
+            .unwrap();
+        let revoked_at = |fingerprint: &'static str| {
+            let db = db.clone();
+            async move {
+                db.get_client_certificate(fingerprint)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .revoked_at
+            }
+        };
+        assert_eq!(revoked_at("FP:01").await, Some(500), "{name}");
+        assert_eq!(revoked_at("FP:02").await, Some(300), "{name}");
+        assert_eq!(revoked_at("FP:03").await, None, "{name}");
+    }
+}
+
+#[tokio::test]
+async fn client_certificates_issue_and_revoke() {
+    for (name, db) in backends("conformance_certificates").await {
+        let client = db
+            .create_client("svc", "pk", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+        let other = db
+            .create_client("other", "pk2", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+        let certificate = |serial: &str, client_id: Uuid, issued_at: i64| ClientCertificate {
+            serial: String::from(serial),
+            client_id,
+            fingerprint: format!("FP:{serial}"),
+            issued_at,
+            expires_at: issued_at + 3_600,
+            revoked_at: None,
+        };
+
+        db.create_client_certificate(&certificate("01", client.id, 100))
+            .await
+            .unwrap();
+        db.create_client_certificate(&certificate("02", client.id, 200))
+            .await
+            .unwrap();
+        db.create_client_certificate(&certificate("03", other.id, 300))
+            .await
+            .unwrap();
+        assert!(matches!(
+            db.create_client_certificate(&certificate("01", client.id, 400))
+                .await,
+            Err(AppError::Conflict(_))
+        ));
+        assert!(
+            db.create_client_certificate(&certificate("04", Uuid::new_v4(), 400))
+                .await
+                .is_err()
+        );
+
+        let serials =
+            |list: Vec<ClientCertificate>| list.into_iter().map(|c| c.serial).collect::<Vec<_>>();
+        assert_eq!(
+            serials(db.list_client_certificates(Some(&client.id)).await.unwrap()),
+            ["02", "01"],
+            "{name}"
+        );
+        assert_eq!(
+            serials(db.list_client_certificates(None).await.unwrap()),
+            ["03", "02", "01"],
+            "{name}"
+        );
+
+        // Load it first so a cached copy has to be invalidated.
+        let found = db.get_client_certificate("FP:01").await.unwrap();
+        assert_eq!(found, Some(certificate("01", client.id, 100)), "{name}");
+        assert!(db.revoke_client_certificate("01", 500).await.unwrap());
+        assert!(!db.revoke_client_certificate("01", 600).await.unwrap());
+        assert!(!db.revoke_client_certificate("ff", 600).await.unwrap());
+        let revoked = db.get_client_certificate("FP:01").await.unwrap().unwrap();
+        assert_eq!(revoked.revoked_at, Some(500), "{name}");
+
+        db.delete_client(&client.id).await.unwrap();
+        assert!(db.get_client_certificate("FP:02").await.unwrap().is_none());
+        assert_eq!(
+            serials(db.list_client_certificates(None).await.unwrap()),
+            ["03"],
+            "{name}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn sessions_challenge_and_revoke() {
+    for (name, db) in backends("conformance_sessions").await {
+        let client = db
+            .create_client("svc", "pk", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+
+        db.create_session_challenge("c-1", &client.id, 200, 100)
+            .await
+            .unwrap();
+        db.create_session_challenge("c-2", &client.id, 150, 100)
+            .await
+            .unwrap();
+        assert_eq!(
+            db.take_session_challenge("c-1", 120).await.unwrap(),
+            Some(client.id),
+            "{name}"
+        );
+        // One use only.
+        assert_eq!(db.take_session_challenge("c-1", 120).await.unwrap(), None);
+        assert_eq!(db.take_session_challenge("c-2", 150).await.unwrap(), None);
+
+        let session = |id: Uuid, created_at: i64| Session {
+            id,
+            client_id: client.id,
+            scopes: vec![SessionScope::Read, SessionScope::Write],
+            created_at,
+            expires_at: created_at + 900,
+            revoked_at: None,
+        };
+        let first = session(Uuid::new_v4(), 100);
+        let second = session(Uuid::new_v4(), 200);
+        db.create_session(&first, "hash-1").await.unwrap();
+        db.create_session(&second, "hash-2").await.unwrap();
+
+        assert_eq!(
+            db.get_session_by_token("hash-1").await.unwrap(),
+            Some(first.clone()),
+            "{name}"
+        );
+        assert!(db.get_session_by_token("hash-3").await.unwrap().is_none());
+        let ids = db
+            .list_sessions(Some(&client.id))
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|s| s.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [second.id, first.id], "{name}");
+
+        assert!(db.revoke_session(&first.id, 300).await.unwrap());
+        assert!(!db.revoke_session(&first.id, 400).await.unwrap());
+        let revoked = db.get_session_by_token("hash-1").await.unwrap().unwrap();
+        assert_eq!(revoked.revoked_at, Some(300), "{name}");
+
+        // Rotating the key ends the sessions opened with the old one.
+        db.set_client_public_key(&client.id, "pk2", KeyAlgorithm::Ed25519, 500)
+            .await
+            .unwrap();
+        let rotated = db.get_session_by_token("hash-2").await.unwrap().unwrap();
+        assert_eq!(rotated.revoked_at, Some(500), "{name}");
+        let revoked = db.get_session_by_token("hash-1").await.unwrap().unwrap();
+        assert_eq!(revoked.revoked_at, Some(300), "{name}");
+
+        db.delete_client(&client.id).await.unwrap();
+        assert!(db.list_sessions(None).await.unwrap().is_empty(), "{name}");
+    }
+}
+
+#[tokio::test]
+async fn otp_enrollment_and_recovery_codes() {
+    for (name, db) in backends("conformance_otp").await {
+        let admin = db
+            .create_client("admin", "pk", KeyAlgorithm::Ed25519, true)
+            .await
+            .unwrap();
+        assert!(db.get_otp(&admin.id).await.unwrap().is_none(), "{name}");
+
+        db.set_otp_secret(&admin.id, "SECRET").await.unwrap();
+        let pending = db.get_otp(&admin.id).await.unwrap().unwrap();
+        assert_eq!(pending.secret, "SECRET", "{name}");
+        assert_eq!(pending.enabled_at, None, "{name}");
+
+        assert!(db.enable_otp(&admin.id, 100, 3).await.unwrap(), "{name}");
+        assert!(!db.enable_otp(&admin.id, 100, 3).await.unwrap(), "{name}");
+        // A step is accepted once, and never one before it.
+        assert!(!db.accept_otp_step(&admin.id, 3).await.unwrap(), "{name}");
+        assert_eq!(db.record_otp_failure(&admin.id).await.unwrap(), 1);
+        assert_eq!(db.record_otp_failure(&admin.id).await.unwrap(), 2);
+        assert!(db.accept_otp_step(&admin.id, 4).await.unwrap(), "{name}");
+        let enabled = db.get_otp(&admin.id).await.unwrap().unwrap();
+        assert_eq!(
+            (enabled.enabled_at, enabled.last_step, enabled.failures),
+            (Some(100), 4, 0),
+            "{name}"
+        );
+
+        let hashes = [String::from("h-1"), String::from("h-2")];
+        db.replace_recovery_codes(&admin.id, &hashes).await.unwrap();
+        db.record_otp_failure(&admin.id).await.unwrap();
+        assert!(db.use_recovery_code(&admin.id, "h-1", 200).await.unwrap());
+        assert!(!db.use_recovery_code(&admin.id, "h-1", 200).await.unwrap());
+        let reset = db.get_otp(&admin.id).await.unwrap().unwrap();
+        assert_eq!(reset.failures, 0, "{name}");
+        db.replace_recovery_codes(&admin.id, &hashes[..1])
+            .await
+            .unwrap();
+        assert!(db.use_recovery_code(&admin.id, "h-1", 300).await.unwrap());
+        assert!(!db.use_recovery_code(&admin.id, "h-2", 300).await.unwrap());
+
+        assert!(db.delete_otp(&admin.id).await.unwrap(), "{name}");
+        assert!(!db.delete_otp(&admin.id).await.unwrap(), "{name}");
+
+        db.set_otp_secret(&admin.id, "SECRET").await.unwrap();
+        db.delete_client(&admin.id).await.unwrap();
+        assert!(db.get_otp(&admin.id).await.unwrap().is_none(), "{name}");
+    }
+}
+
+#[tokio::test]
+async fn delete_project_removes_configs_and_grants() {
+    for (name, db) in backends("conformance_delete_project").await {
+        let client = db
+            .create_client("svc", "pk", KeyAlgorithm::Ed25519, false)
+            .await
+            .unwrap();
+        let project = db.create_project("billing", "").await.unwrap();
+        let kept = db.create_project("search", "").await.unwrap();
+        db.upsert_config(&project.id, "db.host", "\"a\"")
+            .await
+            .unwrap();
+        db.upsert_config(&kept.id, "db.host", "\"b\"")
+            .await
+            .unwrap();
+        db.set_permission(&client.id, &project.id, true, true)
+            .await
+            .unwrap();
+
+        assert!(db.delete_project(&project.id).await.unwrap(), "{name}");
+        assert!(!db.delete_project(&project.id).await.unwrap(), "{name}");
+        assert!(db.get_project_by_id(&project.id).await.unwrap().is_none());
+        assert!(
+            db.get_permission(&client.id, &project.id)
+                .await
+                .unwrap()
+                .is_none(),
+            "{name}"
+        );
+        assert!(
+            db.get_config_by_key(&project.id, "db.host")
+                .await
+                .unwrap()
+                .is_none(),
+            "{name}"
+        );
+        assert_eq!(
+            db.list_configs_for_project(&kept.id).await.unwrap().len(),
+            1
+        );
+    }
+}
+
+#[tokio::test]
+async fn pending_actions_approve_and_expire() {
+    for (name, db) in backends("conformance_pending_actions").await {
+        let alice = db
+            .create_client("alice", "pk-a", KeyAlgorithm::Ed25519, true)
+            .await
+            .unwrap();
+        let bob = db
+            .create_client("bob", "pk-b", KeyAlgorithm::Ed25519, true)
+            .await
+            .unwrap();
+
+        let pending = |created_at: i64, operation: AdminOperation| PendingAction {
+            id: Uuid::new_v4(),
+            action: operation.action(),
+            operation,
+            requested_by: alice.id,
+            approvals: vec![alice.id],
+            required_approvals: 2,
+            status: PendingActionStatus::Pending,
+            created_at,
+            expires_at: created_at + 100,
+            resolved_at: None,
+            resolved_by: None,
+        };
+        let first = pending(
+            100,
+            AdminOperation::DeleteProject {
+                project_id: Uuid::new_v4(),
+            },
+        );
+        let second = pending(
+            200,
+            AdminOperation::CreateAdmin {
+                name: String::from("carol"),
+                public_key: String::from("pk-c"),
+                key_algorithm: KeyAlgorithm::Ed25519,
+            },
+        );
+        db.create_pending_action(&first).await.unwrap();
+        db.create_pending_action(&second).await.unwrap();
+
+        assert_eq!(
+            db.get_pending_action(&second.id).await.unwrap(),
+            Some(second.clone()),
+            "{name}"
+        );
+        let ids: Vec<_> = db
+            .list_pending_actions()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|action| action.id)
+            .collect();
+        assert_eq!(ids, [second.id, first.id], "{name}");
+
+        assert!(
+            !db.approve_pending_action(&first.id, &alice.id, 110)
+                .await
+                .unwrap(),
+            "{name}"
+        );
+        assert!(
+            db.approve_pending_action(&first.id, &bob.id, 110)
+                .await
+                .unwrap(),
+            "{name}"
+        );
+
+        // Only one caller moves an action out of pending.
+        let resolve = |from, to| db.resolve_pending_action(&first.id, from, to, 120, Some(&bob.id));
+        assert!(
+            resolve(PendingActionStatus::Pending, PendingActionStatus::Executed)
+                .await
+                .unwrap()
+        );
+        assert!(
+            !resolve(PendingActionStatus::Pending, PendingActionStatus::Rejected)
+                .await
+                .unwrap()
+        );
+        let executed = db.get_pending_action(&first.id).await.unwrap().unwrap();
+        assert_eq!(executed.approvals, [alice.id, bob.id], "{name}");
+        assert_eq!(
+            (executed.status, executed.resolved_at, executed.resolved_by),
+            (PendingActionStatus::Executed, Some(120), Some(bob.id)),
+            "{name}"
+        );
+
+        // Expired actions take no more approvals.
+        assert_eq!(db.expire_pending_actions(300).await.unwrap(), 1, "{name}");
+        let expired = db.get_pending_action(&second.id).await.unwrap().unwrap();
+        assert_eq!(expired.status, PendingActionStatus::Expired, "{name}");
+        assert!(
+            !db.approve_pending_action(&second.id, &bob.id, 310)
+                .await
+                .unwrap(),
+            "{name}"
+        );
+
+        db.delete_client(&bob.id).await.unwrap();
+        let scrubbed = db.get_pending_action(&first.id).await.unwrap().unwrap();
+        assert_eq!(scrubbed.approvals, [alice.id], "{name}");
+        assert_eq!(scrubbed.resolved_by, None, "{name}");
+        db.delete_client(&alice.id).await.unwrap();
+        assert!(
+            db.list_pending_actions().await.unwrap().is_empty(),
+            "{name}"
+        );
+    }
+}
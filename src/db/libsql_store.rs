@@ -0,0 +1,1878 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use libsql::{Builder, Connection, Row, Statement, params};
+use uuid::Uuid;
+
+use super::{
+    ConfigSnapshot, ProjectConfigs, Storage, certificate_conflict, check_expected_version,
+    config_not_found, encode_admin_operation, group_approvals, join_client_networks,
+    join_session_scopes, limit_change_feed, parse_admin_operation, parse_change_op,
+    parse_client_networks, parse_key_algorithm, parse_pending_status, parse_required_approvals,
+    parse_session_scopes, parse_uuid,
+    pool::{Pool, PoolGuard, Pooled},
+    project_name_conflict, replayed_request, validate_config_batch, validate_config_key,
+    validate_name, validate_nonce,
+};
+use crate::{
+    backup::{Backup, RestoreMode, RestoreSummary},
+    config::AppConfig,
+    error::{AppError, AppResult},
+    keys::KeyAlgorithm,
+    migrations::{AppliedMigration, MIGRATIONS, SchemaStatus},
+    models::{
+        BatchWriteResponse, ChangeFeed, Client, ClientCertificate, ClientPermission, ConfigChange,
+        ConfigItem, ConfigOperation, OtpEnrollment, PendingAction, PendingActionStatus, Project,
+        ReplicaStatus, Session,
+    },
+};
+
+const MIGRATIONS_TABLE_SQL: &str = r"
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version     INTEGER PRIMARY KEY,
+    name        TEXT NOT NULL,
+    checksum    TEXT NOT NULL,
+    applied_at  TEXT NOT NULL DEFAULT (datetime('now'))
+);
+";
+
+const CLIENT_BY_ID_SQL: &str = "SELECT id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks FROM clients WHERE id = ?1 LIMIT 1";
+
+const PERMISSION_SQL: &str = r"
+SELECT client_id, project_id, can_read, can_write
+FROM client_permissions
+WHERE client_id = ?1 AND project_id = ?2
+LIMIT 1
+";
+
+/// Reclaims an expired row for the same nonce; otherwise a conflict means replay.
+const REGISTER_NONCE_SQL: &str = r"
+INSERT INTO used_nonces (client_id, nonce, created_at) VALUES (?1, ?2, ?3)
+ON CONFLICT(client_id, nonce) DO UPDATE SET created_at = excluded.created_at
+WHERE used_nonces.created_at < ?4
+";
+
+const READ_CONFIGS_SQL: &str = r"
+SELECT cp.can_read, cp.can_write, p.revision, c.id, c.project_id, c.key, c.value, c.version, c.updated_at
+FROM client_permissions cp
+JOIN projects p ON p.id = cp.project_id
+LEFT JOIN configs c ON c.project_id = cp.project_id
+WHERE cp.client_id = ?1 AND cp.project_id = ?2
+ORDER BY c.key ASC
+";
+
+const CONFIG_SNAPSHOT_SQL: &str = r"
+SELECT p.revision, c.id, c.project_id, c.key, c.value, c.version, c.updated_at
+FROM projects p
+LEFT JOIN configs c ON c.project_id = p.id
+WHERE p.id = ?1
+ORDER BY c.key ASC
+";
+
+const READ_CONFIG_SQL: &str = r"
+SELECT cp.can_read, cp.can_write, p.revision, c.id, c.project_id, c.key, c.value, c.version, c.updated_at
+FROM client_permissions cp
+JOIN projects p ON p.id = cp.project_id
+LEFT JOIN configs c ON c.project_id = cp.project_id AND c.key = ?3
+WHERE cp.client_id = ?1 AND cp.project_id = ?2
+";
+
+#[derive(Debug)]
+pub struct LibsqlStore {
+    pool: Pool<LibsqlConnection>,
+    /// Database files allow one writer at a time. Funnelling writes through a
+    /// dedicated connection queues them here instead of in the sqlite busy
+    /// handler, whose sleep-and-retry backoff shows up as tail latency.
+    writer: Option<Pool<LibsqlConnection>>,
+    replica: Option<Replica>,
+}
+
+/// A pooled connection with the prepared statements it has already compiled.
+struct LibsqlConnection {
+    conn: Connection,
+    statements: HashMap<&'static str, Statement>,
+}
+
+impl LibsqlConnection {
+    async fn prepared(&mut self, sql: &'static str) -> AppResult<&Statement> {
+        if !self.statements.contains_key(sql) {
+            let statement = self.conn.prepare(sql).await?;
+            self.statements.insert(sql, statement);
+        }
+
+        let statement = &self.statements[sql];
+        statement.reset();
+        Ok(statement)
+    }
+}
+
+/// Opens connections on a shared [`libsql::Database`].
+#[derive(Debug)]
+struct LibsqlConnector {
+    db: Arc<libsql::Database>,
+    local_file: bool,
+    replica: bool,
+}
+
+#[async_trait]
+impl Pooled for LibsqlConnection {
+    type Connector = LibsqlConnector;
+
+    async fn connect(connector: &LibsqlConnector) -> AppResult<Self> {
+        let conn = connector.db.connect()?;
+        // A replica forwards the pragma to the primary like any write, which
+        // would keep it from starting while the primary is down. Writes run on
+        // the primary anyway.
+        if !connector.replica {
+            conn.execute("PRAGMA foreign_keys = ON", ()).await?;
+        }
+        if connector.local_file {
+            // WAL lets readers proceed while the writer holds its lock.
+            conn.busy_timeout(Duration::from_secs(5))?;
+            conn.query("PRAGMA journal_mode = WAL", ()).await?;
+        }
+
+        Ok(Self {
+            conn,
+            statements: HashMap::new(),
+        })
+    }
+
+    /// A cached statement that was not stepped to completion keeps its read
+    /// snapshot open, which makes later writes on this connection fail.
+    fn release(&mut self) {
+        for statement in self.statements.values() {
+            statement.reset();
+        }
+    }
+}
+
+impl Deref for LibsqlConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        &self.conn
+    }
+}
+
+impl std::fmt::Debug for LibsqlConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LibsqlConnection")
+            .field("cached_statements", &self.statements.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// An embedded replica: reads are served from a local file kept in sync with
+/// the remote primary, and writes are forwarded to the primary by libsql.
+#[derive(Debug)]
+struct Replica {
+    db: Arc<libsql::Database>,
+    status: Mutex<ReplicaStatus>,
+}
+
+impl LibsqlStore {
+    pub async fn connect(config: &AppConfig) -> AppResult<Self> {
+        let (db, replica_path) = if let Some(replica_path) = &config.turso_replica_path {
+            let db = Builder::new_remote_replica(
+                replica_path,
+                config.database_url.clone(),
+                remote_auth_token(config)?,
+            )
+            .read_your_writes(true)
+            .build()
+            .await?;
+            (db, Some(replica_path.clone()))
+        } else if config.is_remote_database() {
+            let db = Builder::new_remote(config.database_url.clone(), remote_auth_token(config)?)
+                .build()
+                .await?;
+            (db, None)
+        } else {
+            let db = Builder::new_local(config.database_url.clone())
+                .build()
+                .await?;
+            (db, None)
+        };
+
+        let db = Arc::new(db);
+        let local_file = replica_path.is_none() && !config.is_remote_database();
+        // Every connection to `:memory:` opens its own empty database.
+        let pool_size = if config.database_url == ":memory:" {
+            1
+        } else {
+            config.database_pool_size
+        };
+
+        let connector = LibsqlConnector {
+            db: Arc::clone(&db),
+            local_file,
+            replica: replica_path.is_some(),
+        };
+        let pool = Pool::connect(connector, pool_size).await?;
+        let writer = if local_file && pool_size > 1 {
+            let connector = LibsqlConnector {
+                db: Arc::clone(&db),
+                local_file,
+                replica: false,
+            };
+            Some(Pool::connect(connector, 1).await?)
+        } else {
+            None
+        };
+
+        let replica = replica_path.map(|replica_path| Replica {
+            db,
+            status: Mutex::new(ReplicaStatus {
+                replica_path,
+                ..ReplicaStatus::default()
+            }),
+        });
+        let store = Self {
+            pool,
+            writer,
+            replica,
+        };
+
+        if store.replica.is_some()
+            && let Err(error) = store.sync().await
+        {
+            tracing::warn!("initial replica sync failed, serving local data: {error}");
+        }
+
+        Ok(store)
+    }
+
+    async fn writer(&self) -> AppResult<PoolGuard<'_, LibsqlConnection>> {
+        self.writer.as_ref().unwrap_or(&self.pool).get().await
+    }
+}
+
+#[async_trait]
+impl Storage for LibsqlStore {
+    async fn sync(&self) -> AppResult<Option<ReplicaStatus>> {
+        let Some(replica) = &self.replica else {
+            return Ok(None);
+        };
+
+        let result = replica.db.sync().await;
+        let now = crate::auth::current_unix_timestamp()?;
+        let mut status = replica
+            .status
+            .lock()
+            .map_err(|_| AppError::Internal(String::from("replica status lock poisoned")))?;
+        status.last_sync_at = Some(now);
+        status.sync_count += 1;
+
+        match result {
+            Ok(replicated) => {
+                status.last_success_at = Some(now);
+                status.frame_no = replicated.frame_no();
+                status.frames_synced_total += replicated.frames_synced() as u64;
+                status.last_error = None;
+                Ok(Some(status.clone()))
+            }
+            Err(error) => {
+                status.last_error = Some(error.to_string());
+                Err(error.into())
+            }
+        }
+    }
+
+    fn replica_status(&self) -> Option<ReplicaStatus> {
+        let replica = self.replica.as_ref()?;
+        replica.status.lock().ok().map(|status| status.clone())
+    }
+
+    async fn migrate(&self) -> AppResult<Vec<i64>> {
+        let status = self.schema_status().await?;
+        status.ensure_supported()?;
+
+        let conn = self.writer().await?;
+        let mut applied = Vec::new();
+        for migration in MIGRATIONS {
+            if status.is_applied(migration.version) {
+                continue;
+            }
+
+            let tx = conn.transaction().await?;
+            tx.execute_batch(migration.sql).await?;
+            tx.execute(
+                "INSERT INTO schema_migrations (version, name, checksum) VALUES (?1, ?2, ?3)",
+                params![migration.version, migration.name, migration.checksum()],
+            )
+            .await?;
+            tx.commit().await?;
+
+            tracing::info!(
+                "applied migration {:04} {}",
+                migration.version,
+                migration.name
+            );
+            applied.push(migration.version);
+        }
+
+        Ok(applied)
+    }
+
+    async fn schema_status(&self) -> AppResult<SchemaStatus> {
+        let conn = self.writer().await?;
+        conn.execute_batch(MIGRATIONS_TABLE_SQL).await?;
+
+        let mut rows = conn
+            .query(
+                "SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version ASC",
+                (),
+            )
+            .await?;
+
+        let mut applied = Vec::new();
+        while let Some(row) = rows.next().await? {
+            applied.push(AppliedMigration {
+                version: row.get::<i64>(0)?,
+                name: row.get::<String>(1)?,
+                checksum: row.get::<String>(2)?,
+                applied_at: row.get::<String>(3)?,
+            });
+        }
+
+        SchemaStatus::from_applied(MIGRATIONS, &applied)
+    }
+
+    async fn ping(&self) -> AppResult<()> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn.query("SELECT 1", ()).await?;
+        rows.next().await?;
+        Ok(())
+    }
+
+    async fn change_seq(&self) -> AppResult<i64> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query("SELECT seq FROM change_seq WHERE id = 1", ())
+            .await?;
+
+        match rows.next().await? {
+            Some(row) => Ok(row.get::<i64>(0)?),
+            None => Ok(0),
+        }
+    }
+
+    async fn get_client_by_id(&self, client_id: &Uuid) -> AppResult<Option<Client>> {
+        let mut conn = self.pool.get().await?;
+        let mut rows = conn
+            .prepared(CLIENT_BY_ID_SQL)
+            .await?
+            .query(params![client_id.to_string()])
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            return Ok(Some(client_from_row(&row)?));
+        }
+
+        Ok(None)
+    }
+
+    async fn get_admin_client(&self) -> AppResult<Option<Client>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                "SELECT id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks FROM clients WHERE is_admin = 1 ORDER BY created_at ASC LIMIT 1",
+                (),
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            return Ok(Some(client_from_row(&row)?));
+        }
+
+        Ok(None)
+    }
+
+    async fn create_client(
+        &self,
+        name: &str,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        is_admin: bool,
+    ) -> AppResult<Client> {
+        let name = validate_name(name, "client")?;
+
+        let conn = self.writer().await?;
+        let mut rows = conn
+            .query(
+                "INSERT INTO clients (id, name, public_key, is_admin, key_algorithm) VALUES (?1, ?2, ?3, ?4, ?5) RETURNING id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks",
+                params![
+                    Uuid::new_v4().to_string(),
+                    name,
+                    public_key,
+                    i64::from(is_admin),
+                    key_algorithm.as_str()
+                ],
+            )
+            .await?;
+
+        let row = rows
+            .next()
+            .await?
+            .ok_or_else(|| AppError::Internal(String::from("failed to load created client")))?;
+        client_from_row(&row)
+    }
+
+    async fn set_client_public_key(
+        &self,
+        client_id: &Uuid,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        rotated_at: i64,
+    ) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let tx = conn.transaction().await?;
+        let client = client_id.to_string();
+        let affected = tx
+            .execute(
+                "UPDATE clients SET public_key = ?1, key_algorithm = ?2 WHERE id = ?3",
+                params![public_key, key_algorithm.as_str(), client.as_str()],
+            )
+            .await?;
+        for table in ["sessions", "client_certificates"] {
+            tx.execute(
+                &format!(
+                    "UPDATE {table} SET revoked_at = ?2 WHERE client_id = ?1 AND revoked_at IS NULL"
+                ),
+                params![client.as_str(), rotated_at],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn set_client_networks(&self, client_id: &Uuid, networks: &[String]) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                "UPDATE clients SET allowed_networks = ?1 WHERE id = ?2",
+                params![join_client_networks(networks), client_id.to_string()],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn list_clients(&self) -> AppResult<Vec<Client>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                "SELECT id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks FROM clients ORDER BY created_at DESC",
+                (),
+            )
+            .await?;
+
+        let mut clients = Vec::new();
+        while let Some(row) = rows.next().await? {
+            clients.push(client_from_row(&row)?);
+        }
+
+        Ok(clients)
+    }
+
+    async fn delete_project(&self, project_id: &Uuid) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let tx = conn.transaction().await?;
+        let project = project_id.to_string();
+        // Configs first: their delete triggers write to the project's change log.
+        tx.execute(
+            "DELETE FROM configs WHERE project_id = ?1",
+            params![project.as_str()],
+        )
+        .await?;
+        let affected = tx
+            .execute(
+                "DELETE FROM projects WHERE id = ?1",
+                params![project.as_str()],
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn delete_client(&self, client_id: &Uuid) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                "DELETE FROM clients WHERE id = ?1",
+                params![client_id.to_string()],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn create_project(&self, name: &str, description: &str) -> AppResult<Project> {
+        let name = validate_name(name, "project")?;
+
+        let conn = self.writer().await?;
+        let insert_result = conn
+            .query(
+                "INSERT INTO projects (id, name, description) VALUES (?1, ?2, ?3) RETURNING id, name, description, created_at, revision",
+                params![Uuid::new_v4().to_string(), name, description],
+            )
+            .await;
+
+        let row = match insert_result {
+            Ok(mut rows) => rows.next().await,
+            Err(error) => Err(error),
+        };
+        match row {
+            Ok(Some(row)) => project_from_row(&row),
+            Ok(None) => Err(AppError::Internal(String::from(
+                "failed to load created project",
+            ))),
+            Err(error) if is_unique_constraint_error(&error) => Err(project_name_conflict()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn get_project_by_id(&self, project_id: &Uuid) -> AppResult<Option<Project>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                "SELECT id, name, description, created_at, revision FROM projects WHERE id = ?1 LIMIT 1",
+                params![project_id.to_string()],
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            return Ok(Some(project_from_row(&row)?));
+        }
+
+        Ok(None)
+    }
+
+    async fn get_project_by_name(&self, name: &str) -> AppResult<Option<Project>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                "SELECT id, name, description, created_at, revision FROM projects WHERE name = ?1 LIMIT 1",
+                params![name.trim()],
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            return Ok(Some(project_from_row(&row)?));
+        }
+
+        Ok(None)
+    }
+
+    async fn list_projects(&self) -> AppResult<Vec<Project>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                "SELECT id, name, description, created_at, revision FROM projects ORDER BY name ASC",
+                (),
+            )
+            .await?;
+
+        let mut projects = Vec::new();
+        while let Some(row) = rows.next().await? {
+            projects.push(project_from_row(&row)?);
+        }
+
+        Ok(projects)
+    }
+
+    async fn list_projects_for_client(&self, client_id: &Uuid) -> AppResult<Vec<Project>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                r"
+                SELECT p.id, p.name, p.description, p.created_at, p.revision
+                FROM projects p
+                JOIN client_permissions cp ON cp.project_id = p.id
+                WHERE cp.client_id = ?1 AND (cp.can_read = 1 OR cp.can_write = 1)
+                ORDER BY p.name ASC
+                ",
+                params![client_id.to_string()],
+            )
+            .await?;
+
+        let mut projects = Vec::new();
+        while let Some(row) = rows.next().await? {
+            projects.push(project_from_row(&row)?);
+        }
+
+        Ok(projects)
+    }
+
+    async fn set_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+        can_read: bool,
+        can_write: bool,
+    ) -> AppResult<ClientPermission> {
+        if self.get_client_by_id(client_id).await?.is_none() {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+
+        if self.get_project_by_id(project_id).await?.is_none() {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let conn = self.writer().await?;
+        let mut rows = conn
+            .query(
+                r"
+                INSERT INTO client_permissions (client_id, project_id, can_read, can_write)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT(client_id, project_id) DO UPDATE SET
+                    can_read = excluded.can_read,
+                    can_write = excluded.can_write
+                RETURNING client_id, project_id, can_read, can_write
+                ",
+                params![
+                    client_id.to_string(),
+                    project_id.to_string(),
+                    i64::from(can_read),
+                    i64::from(can_write)
+                ],
+            )
+            .await?;
+
+        let row = rows
+            .next()
+            .await?
+            .ok_or_else(|| AppError::Internal(String::from("failed to load permission")))?;
+        permission_from_row(&row)
+    }
+
+    async fn get_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+    ) -> AppResult<Option<ClientPermission>> {
+        let mut conn = self.pool.get().await?;
+        let mut rows = conn
+            .prepared(PERMISSION_SQL)
+            .await?
+            .query(params![client_id.to_string(), project_id.to_string()])
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            return Ok(Some(permission_from_row(&row)?));
+        }
+
+        Ok(None)
+    }
+
+    async fn list_permissions_for_client(
+        &self,
+        client_id: &Uuid,
+    ) -> AppResult<Vec<ClientPermission>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                r"
+                SELECT cp.client_id, cp.project_id, cp.can_read, cp.can_write
+                FROM client_permissions cp
+                JOIN projects p ON p.id = cp.project_id
+                WHERE cp.client_id = ?1
+                ORDER BY p.name ASC
+                ",
+                params![client_id.to_string()],
+            )
+            .await?;
+
+        let mut permissions = Vec::new();
+        while let Some(row) = rows.next().await? {
+            permissions.push(permission_from_row(&row)?);
+        }
+
+        Ok(permissions)
+    }
+
+    async fn register_nonce(
+        &self,
+        client_id: &Uuid,
+        nonce: &str,
+        now_timestamp: i64,
+        ttl_seconds: i64,
+    ) -> AppResult<()> {
+        let nonce = validate_nonce(nonce)?;
+
+        let cutoff = now_timestamp - ttl_seconds;
+        let mut conn = self.writer().await?;
+        let inserted = conn
+            .prepared(REGISTER_NONCE_SQL)
+            .await?
+            .execute(params![client_id.to_string(), nonce, now_timestamp, cutoff])
+            .await?;
+
+        if inserted == 0 {
+            return Err(replayed_request());
+        }
+
+        Ok(())
+    }
+
+    async fn purge_expired_nonces(&self, cutoff: i64) -> AppResult<u64> {
+        let conn = self.writer().await?;
+        let purged = conn
+            .execute(
+                "DELETE FROM used_nonces WHERE created_at < ?1",
+                params![cutoff],
+            )
+            .await?;
+
+        Ok(purged)
+    }
+
+    async fn create_client_certificate(&self, certificate: &ClientCertificate) -> AppResult<()> {
+        let conn = self.writer().await?;
+        let result = conn
+            .execute(
+                r"
+                INSERT INTO client_certificates (serial, client_id, fingerprint, issued_at, expires_at, revoked_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                ",
+                params![
+                    certificate.serial.as_str(),
+                    certificate.client_id.to_string(),
+                    certificate.fingerprint.as_str(),
+                    certificate.issued_at,
+                    certificate.expires_at,
+                    certificate.revoked_at
+                ],
+            )
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(error) if is_unique_constraint_error(&error) => Err(certificate_conflict()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn get_client_certificate(
+        &self,
+        fingerprint: &str,
+    ) -> AppResult<Option<ClientCertificate>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                &format!(
+                    "SELECT {CERTIFICATE_COLUMNS} FROM client_certificates WHERE fingerprint = ?1"
+                ),
+                params![fingerprint],
+            )
+            .await?;
+
+        rows.next()
+            .await?
+            .map(|row| certificate_from_row(&row))
+            .transpose()
+    }
+
+    async fn list_client_certificates(
+        &self,
+        client_id: Option<&Uuid>,
+    ) -> AppResult<Vec<ClientCertificate>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                &format!(
+                    "SELECT {CERTIFICATE_COLUMNS} FROM client_certificates
+                     WHERE ?1 IS NULL OR client_id = ?1
+                     ORDER BY issued_at DESC, serial ASC"
+                ),
+                params![client_id.map(Uuid::to_string)],
+            )
+            .await?;
+
+        let mut certificates = Vec::new();
+        while let Some(row) = rows.next().await? {
+            certificates.push(certificate_from_row(&row)?);
+        }
+
+        Ok(certificates)
+    }
+
+    async fn revoke_client_certificate(&self, serial: &str, revoked_at: i64) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                "UPDATE client_certificates SET revoked_at = ?2 WHERE serial = ?1 AND revoked_at IS NULL",
+                params![serial, revoked_at],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn create_session_challenge(
+        &self,
+        challenge: &str,
+        client_id: &Uuid,
+        expires_at: i64,
+        now_timestamp: i64,
+    ) -> AppResult<()> {
+        let conn = self.writer().await?;
+        conn.execute(
+            "DELETE FROM session_challenges WHERE expires_at <= ?1",
+            params![now_timestamp],
+        )
+        .await?;
+        conn.execute(
+            "INSERT INTO session_challenges (challenge, client_id, expires_at) VALUES (?1, ?2, ?3)",
+            params![challenge, client_id.to_string(), expires_at],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn take_session_challenge(
+        &self,
+        challenge: &str,
+        now_timestamp: i64,
+    ) -> AppResult<Option<Uuid>> {
+        let conn = self.writer().await?;
+        let mut rows = conn
+            .query(
+                "DELETE FROM session_challenges WHERE challenge = ?1 RETURNING client_id, expires_at",
+                params![challenge],
+            )
+            .await?;
+
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        if row.get::<i64>(1)? <= now_timestamp {
+            return Ok(None);
+        }
+        Ok(Some(parse_uuid(&row.get::<String>(0)?)?))
+    }
+
+    async fn create_session(&self, session: &Session, token_hash: &str) -> AppResult<()> {
+        let conn = self.writer().await?;
+        conn.execute(
+            r"
+            INSERT INTO sessions (id, client_id, token_hash, scopes, created_at, expires_at, revoked_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ",
+            params![
+                session.id.to_string(),
+                session.client_id.to_string(),
+                token_hash,
+                join_session_scopes(&session.scopes),
+                session.created_at,
+                session.expires_at,
+                session.revoked_at
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_session_by_token(&self, token_hash: &str) -> AppResult<Option<Session>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                &format!("SELECT {SESSION_COLUMNS} FROM sessions WHERE token_hash = ?1"),
+                params![token_hash],
+            )
+            .await?;
+
+        rows.next()
+            .await?
+            .map(|row| session_from_row(&row))
+            .transpose()
+    }
+
+    async fn list_sessions(&self, client_id: Option<&Uuid>) -> AppResult<Vec<Session>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                &format!(
+                    "SELECT {SESSION_COLUMNS} FROM sessions
+                     WHERE ?1 IS NULL OR client_id = ?1
+                     ORDER BY created_at DESC, id ASC"
+                ),
+                params![client_id.map(Uuid::to_string)],
+            )
+            .await?;
+
+        let mut sessions = Vec::new();
+        while let Some(row) = rows.next().await? {
+            sessions.push(session_from_row(&row)?);
+        }
+
+        Ok(sessions)
+    }
+
+    async fn revoke_session(&self, session_id: &Uuid, revoked_at: i64) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                "UPDATE sessions SET revoked_at = ?2 WHERE id = ?1 AND revoked_at IS NULL",
+                params![session_id.to_string(), revoked_at],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn set_otp_secret(&self, client_id: &Uuid, secret: &str) -> AppResult<()> {
+        let conn = self.writer().await?;
+        conn.execute(
+            r"
+            INSERT INTO client_otp (client_id, secret) VALUES (?1, ?2)
+            ON CONFLICT(client_id) DO UPDATE SET
+                secret = excluded.secret, enabled_at = NULL, last_step = 0, failures = 0
+            ",
+            params![client_id.to_string(), secret],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_otp(&self, client_id: &Uuid) -> AppResult<Option<OtpEnrollment>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                "SELECT client_id, secret, enabled_at, last_step, failures FROM client_otp WHERE client_id = ?1",
+                params![client_id.to_string()],
+            )
+            .await?;
+
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        Ok(Some(OtpEnrollment {
+            client_id: parse_uuid(&row.get::<String>(0)?)?,
+            secret: row.get::<String>(1)?,
+            enabled_at: row.get::<Option<i64>>(2)?,
+            last_step: row.get::<i64>(3)?,
+            failures: row.get::<i64>(4)?,
+        }))
+    }
+
+    async fn enable_otp(&self, client_id: &Uuid, enabled_at: i64, step: i64) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                r"
+                UPDATE client_otp SET enabled_at = ?2, last_step = ?3, failures = 0
+                WHERE client_id = ?1 AND enabled_at IS NULL
+                ",
+                params![client_id.to_string(), enabled_at, step],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn accept_otp_step(&self, client_id: &Uuid, step: i64) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                "UPDATE client_otp SET last_step = ?2, failures = 0 WHERE client_id = ?1 AND last_step < ?2",
+                params![client_id.to_string(), step],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn record_otp_failure(&self, client_id: &Uuid) -> AppResult<i64> {
+        let conn = self.writer().await?;
+        let mut rows = conn
+            .query(
+                "UPDATE client_otp SET failures = failures + 1 WHERE client_id = ?1 RETURNING failures",
+                params![client_id.to_string()],
+            )
+            .await?;
+
+        match rows.next().await? {
+            Some(row) => Ok(row.get::<i64>(0)?),
+            None => Ok(0),
+        }
+    }
+
+    async fn delete_otp(&self, client_id: &Uuid) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                "DELETE FROM client_otp WHERE client_id = ?1",
+                params![client_id.to_string()],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn replace_recovery_codes(
+        &self,
+        client_id: &Uuid,
+        code_hashes: &[String],
+    ) -> AppResult<()> {
+        let conn = self.writer().await?;
+        let tx = conn.transaction().await?;
+        let client = client_id.to_string();
+        tx.execute(
+            "DELETE FROM recovery_codes WHERE client_id = ?1",
+            params![client.as_str()],
+        )
+        .await?;
+        for code_hash in code_hashes {
+            tx.execute(
+                "INSERT INTO recovery_codes (client_id, code_hash) VALUES (?1, ?2)",
+                params![client.as_str(), code_hash.as_str()],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        client_id: &Uuid,
+        code_hash: &str,
+        used_at: i64,
+    ) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let client = client_id.to_string();
+        let affected = conn
+            .execute(
+                r"
+                UPDATE recovery_codes SET used_at = ?3
+                WHERE client_id = ?1 AND code_hash = ?2 AND used_at IS NULL
+                ",
+                params![client.as_str(), code_hash, used_at],
+            )
+            .await?;
+        if affected == 0 {
+            return Ok(false);
+        }
+
+        conn.execute(
+            "UPDATE client_otp SET failures = 0 WHERE client_id = ?1",
+            params![client.as_str()],
+        )
+        .await?;
+        Ok(true)
+    }
+
+    async fn create_pending_action(&self, action: &PendingAction) -> AppResult<()> {
+        let conn = self.writer().await?;
+        let tx = conn.transaction().await?;
+        let id = action.id.to_string();
+        tx.execute(
+            r"
+            INSERT INTO pending_actions (id, operation, requested_by, required_approvals, status, created_at, expires_at, resolved_at, resolved_by)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+            ",
+            params![
+                id.as_str(),
+                encode_admin_operation(&action.operation)?,
+                action.requested_by.to_string(),
+                i64::from(action.required_approvals),
+                action.status.as_str(),
+                action.created_at,
+                action.expires_at,
+                action.resolved_at,
+                action.resolved_by.map(|id| id.to_string())
+            ],
+        )
+        .await?;
+        for client_id in &action.approvals {
+            tx.execute(
+                "INSERT INTO pending_action_approvals (action_id, client_id, approved_at) VALUES (?1, ?2, ?3)",
+                params![id.as_str(), client_id.to_string(), action.created_at],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_pending_action(&self, action_id: &Uuid) -> AppResult<Option<PendingAction>> {
+        let conn = self.pool.get().await?;
+        let id = action_id.to_string();
+        let mut rows = conn
+            .query(
+                &format!("SELECT {PENDING_ACTION_COLUMNS} FROM pending_actions WHERE id = ?1"),
+                params![id.as_str()],
+            )
+            .await?;
+
+        let Some(row) = rows.next().await? else {
+            return Ok(None);
+        };
+        let mut actions = [pending_action_from_row(&row)?];
+        group_approvals(load_approvals(&conn, Some(&id)).await?, &mut actions);
+        let [action] = actions;
+        Ok(Some(action))
+    }
+
+    async fn list_pending_actions(&self) -> AppResult<Vec<PendingAction>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                &format!(
+                    "SELECT {PENDING_ACTION_COLUMNS} FROM pending_actions ORDER BY created_at DESC, id ASC"
+                ),
+                (),
+            )
+            .await?;
+
+        let mut actions = Vec::new();
+        while let Some(row) = rows.next().await? {
+            actions.push(pending_action_from_row(&row)?);
+        }
+        group_approvals(load_approvals(&conn, None).await?, &mut actions);
+        Ok(actions)
+    }
+
+    async fn approve_pending_action(
+        &self,
+        action_id: &Uuid,
+        client_id: &Uuid,
+        approved_at: i64,
+    ) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                r"
+                INSERT INTO pending_action_approvals (action_id, client_id, approved_at)
+                SELECT id, ?2, ?3 FROM pending_actions WHERE id = ?1 AND status = 'pending'
+                ON CONFLICT(action_id, client_id) DO NOTHING
+                ",
+                params![action_id.to_string(), client_id.to_string(), approved_at],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn resolve_pending_action(
+        &self,
+        action_id: &Uuid,
+        from: PendingActionStatus,
+        to: PendingActionStatus,
+        resolved_at: i64,
+        resolved_by: Option<&Uuid>,
+    ) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                r"
+                UPDATE pending_actions SET status = ?3, resolved_at = ?4, resolved_by = ?5
+                WHERE id = ?1 AND status = ?2
+                ",
+                params![
+                    action_id.to_string(),
+                    from.as_str(),
+                    to.as_str(),
+                    resolved_at,
+                    resolved_by.map(Uuid::to_string)
+                ],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn expire_pending_actions(&self, now_timestamp: i64) -> AppResult<u64> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                r"
+                UPDATE pending_actions SET status = 'expired', resolved_at = expires_at
+                WHERE status = 'pending' AND expires_at <= ?1
+                ",
+                params![now_timestamp],
+            )
+            .await?;
+
+        Ok(affected)
+    }
+
+    async fn delete_permission(&self, client_id: &Uuid, project_id: &Uuid) -> AppResult<bool> {
+        let conn = self.writer().await?;
+        let affected = conn
+            .execute(
+                "DELETE FROM client_permissions WHERE client_id = ?1 AND project_id = ?2",
+                params![client_id.to_string(), project_id.to_string()],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn upsert_config(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+        value: &str,
+    ) -> AppResult<ConfigItem> {
+        if self.get_project_by_id(project_id).await?.is_none() {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let key = validate_config_key(key)?;
+
+        let conn = self.writer().await?;
+        let mut rows = conn
+            .query(
+                r"
+                INSERT INTO configs (id, project_id, key, value, version, updated_at)
+                VALUES (?1, ?2, ?3, ?4, 1, datetime('now'))
+                ON CONFLICT(project_id, key) DO UPDATE SET
+                    value = excluded.value,
+                    version = configs.version + 1,
+                    updated_at = datetime('now')
+                RETURNING id, project_id, key, value, version, updated_at
+                ",
+                params![
+                    Uuid::new_v4().to_string(),
+                    project_id.to_string(),
+                    key,
+                    value
+                ],
+            )
+            .await?;
+
+        let row = rows
+            .next()
+            .await?
+            .ok_or_else(|| AppError::Internal(String::from("failed to load upserted config")))?;
+        config_from_row(&row)
+    }
+
+    async fn config_snapshot(&self, project_id: &Uuid) -> AppResult<ConfigSnapshot> {
+        let mut conn = self.pool.get().await?;
+        let mut rows = conn
+            .prepared(CONFIG_SNAPSHOT_SQL)
+            .await?
+            .query(params![project_id.to_string()])
+            .await?;
+
+        let mut snapshot: Option<ConfigSnapshot> = None;
+        while let Some(row) = rows.next().await? {
+            let snapshot = snapshot.get_or_insert(ConfigSnapshot {
+                revision: row.get::<i64>(0)?,
+                configs: Vec::new(),
+            });
+            if row.get::<Option<String>>(1)?.is_some() {
+                snapshot.configs.push(config_from_row_at(&row, 1)?);
+            }
+        }
+
+        snapshot.ok_or_else(|| AppError::NotFound(String::from("project not found")))
+    }
+
+    async fn config_changes(
+        &self,
+        project_id: &Uuid,
+        since: i64,
+        limit: usize,
+    ) -> AppResult<ChangeFeed> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                "SELECT revision, history_start FROM projects WHERE id = ?1",
+                params![project_id.to_string()],
+            )
+            .await?;
+        let row = rows
+            .next()
+            .await?
+            .ok_or_else(|| AppError::NotFound(String::from("project not found")))?;
+        let (revision, history_start) = (row.get::<i64>(0)?, row.get::<i64>(1)?);
+        drop(rows);
+
+        let mut feed = ChangeFeed {
+            project_id: *project_id,
+            revision,
+            reset: since < history_start || since > revision,
+            changes: Vec::new(),
+            has_more: false,
+        };
+        if feed.reset {
+            return Ok(feed);
+        }
+
+        // Bounded by the revision read above, so a write committed in between
+        // shows up in the next poll instead of being skipped.
+        let mut rows = conn
+            .query(
+                r"
+                SELECT revision, key, op, value, version, changed_at
+                FROM config_changes
+                WHERE project_id = ?1 AND revision > ?2 AND revision <= ?3
+                ORDER BY revision ASC
+                LIMIT ?4
+                ",
+                params![
+                    project_id.to_string(),
+                    since,
+                    revision,
+                    i64::try_from(limit.saturating_add(1)).unwrap_or(i64::MAX)
+                ],
+            )
+            .await?;
+        while let Some(row) = rows.next().await? {
+            feed.changes.push(ConfigChange {
+                revision: row.get::<i64>(0)?,
+                key: row.get::<String>(1)?,
+                op: parse_change_op(&row.get::<String>(2)?)?,
+                value: row.get::<Option<String>>(3)?,
+                version: row.get::<i64>(4)?,
+                changed_at: row.get::<String>(5)?,
+            });
+        }
+
+        limit_change_feed(&mut feed, limit);
+        Ok(feed)
+    }
+
+    async fn prune_config_changes(&self, keep: u64) -> AppResult<u64> {
+        let keep = i64::try_from(keep).unwrap_or(i64::MAX);
+        let conn = self.writer().await?;
+        let tx = conn.transaction().await?;
+        tx.execute(
+            r"
+            UPDATE projects SET history_start = revision - ?1
+            WHERE history_start < revision - ?1
+            ",
+            params![keep],
+        )
+        .await?;
+        let pruned = tx
+            .execute(
+                r"
+                DELETE FROM config_changes
+                WHERE revision <= (
+                    SELECT history_start FROM projects WHERE projects.id = config_changes.project_id
+                )
+                ",
+                (),
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(pruned)
+    }
+
+    async fn apply_config_batch(
+        &self,
+        project_id: &Uuid,
+        operations: &[ConfigOperation],
+    ) -> AppResult<BatchWriteResponse> {
+        validate_config_batch(operations)?;
+
+        let conn = self.writer().await?;
+        let tx = conn.transaction().await?;
+        let project = project_id.to_string();
+        let mut rows = tx
+            .query(
+                "SELECT 1 FROM projects WHERE id = ?1",
+                params![project.as_str()],
+            )
+            .await?;
+        if rows.next().await?.is_none() {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let mut response = BatchWriteResponse::default();
+        for operation in operations {
+            let key = validate_config_key(operation.key())?;
+            let mut rows = tx
+                .query(
+                    "SELECT version FROM configs WHERE project_id = ?1 AND key = ?2",
+                    params![project.as_str(), key],
+                )
+                .await?;
+            let current = match rows.next().await? {
+                Some(row) => Some(row.get::<i64>(0)?),
+                None => None,
+            };
+            check_expected_version(key, current, operation.expected_version())?;
+
+            match operation {
+                ConfigOperation::Set { value, .. } => {
+                    let mut rows = tx
+                        .query(
+                            r"
+                            INSERT INTO configs (id, project_id, key, value, version, updated_at)
+                            VALUES (?1, ?2, ?3, ?4, 1, datetime('now'))
+                            ON CONFLICT(project_id, key) DO UPDATE SET
+                                value = excluded.value,
+                                version = configs.version + 1,
+                                updated_at = datetime('now')
+                            RETURNING id, project_id, key, value, version, updated_at
+                            ",
+                            params![
+                                Uuid::new_v4().to_string(),
+                                project.as_str(),
+                                key,
+                                value.as_str()
+                            ],
+                        )
+                        .await?;
+                    let row = rows.next().await?.ok_or_else(|| {
+                        AppError::Internal(String::from("failed to load upserted config"))
+                    })?;
+                    response.configs.push(config_from_row(&row)?);
+                }
+                ConfigOperation::Delete { .. } => {
+                    if current.is_none() {
+                        return Err(config_not_found(key));
+                    }
+                    tx.execute(
+                        "DELETE FROM configs WHERE project_id = ?1 AND key = ?2",
+                        params![project.as_str(), key],
+                    )
+                    .await?;
+                    response.deleted.push(key.to_owned());
+                }
+            }
+        }
+
+        tx.commit().await?;
+        Ok(response)
+    }
+
+    async fn restore_backup(
+        &self,
+        backup: &Backup,
+        mode: RestoreMode,
+    ) -> AppResult<RestoreSummary> {
+        let conn = self.writer().await?;
+        let tx = conn.transaction().await?;
+        let archived: HashSet<String> = backup.projects.iter().map(|p| p.id.to_string()).collect();
+        if mode == RestoreMode::Replace {
+            clear_for_replace(&tx, &archived).await?;
+        }
+
+        let mut summary = RestoreSummary {
+            clients: restore_clients(&tx, &backup.clients).await?,
+            ..RestoreSummary::default()
+        };
+        for project in &backup.projects {
+            summary.projects += tx
+                .execute(
+                    r"
+                    INSERT INTO projects (id, name, description, created_at, revision)
+                    VALUES (?1, ?2, ?3, ?4, ?5)
+                    ON CONFLICT(id) DO UPDATE SET
+                        name = excluded.name,
+                        description = excluded.description,
+                        revision = MAX(projects.revision, excluded.revision)
+                    ",
+                    params![
+                        project.id.to_string(),
+                        project.name.as_str(),
+                        project.description.as_str(),
+                        project.created_at.as_str(),
+                        project.revision
+                    ],
+                )
+                .await?;
+        }
+        for permission in &backup.permissions {
+            summary.permissions += tx
+                .execute(
+                    r"
+                    INSERT INTO client_permissions (client_id, project_id, can_read, can_write)
+                    VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT(client_id, project_id) DO UPDATE SET
+                        can_read = excluded.can_read,
+                        can_write = excluded.can_write
+                    ",
+                    params![
+                        permission.client_id.to_string(),
+                        permission.project_id.to_string(),
+                        i64::from(permission.can_read),
+                        i64::from(permission.can_write)
+                    ],
+                )
+                .await?;
+        }
+        for item in &backup.configs {
+            summary.configs += tx
+                .execute(
+                    r"
+                    INSERT INTO configs (id, project_id, key, value, version, updated_at)
+                    VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                    ON CONFLICT(project_id, key) DO UPDATE SET
+                        value = excluded.value,
+                        version = excluded.version,
+                        updated_at = excluded.updated_at
+                    WHERE excluded.version >= configs.version
+                    ",
+                    params![
+                        item.id.to_string(),
+                        item.project_id.to_string(),
+                        item.key.as_str(),
+                        item.value.as_str(),
+                        item.version,
+                        item.updated_at.as_str()
+                    ],
+                )
+                .await?;
+        }
+
+        // A fresh revision moves ETags on even when no row changed, and the
+        // log does not describe how the restored state was reached.
+        for id in &archived {
+            tx.execute(
+                "UPDATE projects SET revision = revision + 1, history_start = revision + 1 WHERE id = ?1",
+                params![id.as_str()],
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    async fn list_configs_for_project(&self, project_id: &Uuid) -> AppResult<Vec<ConfigItem>> {
+        if self.get_project_by_id(project_id).await?.is_none() {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                r"
+                SELECT id, project_id, key, value, version, updated_at
+                FROM configs
+                WHERE project_id = ?1
+                ORDER BY key ASC
+                ",
+                params![project_id.to_string()],
+            )
+            .await?;
+
+        let mut items = Vec::new();
+        while let Some(row) = rows.next().await? {
+            items.push(config_from_row(&row)?);
+        }
+
+        Ok(items)
+    }
+
+    async fn get_config_by_key(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+    ) -> AppResult<Option<ConfigItem>> {
+        let conn = self.pool.get().await?;
+        let mut rows = conn
+            .query(
+                r"
+                SELECT id, project_id, key, value, version, updated_at
+                FROM configs
+                WHERE project_id = ?1 AND key = ?2
+                LIMIT 1
+                ",
+                params![project_id.to_string(), key],
+            )
+            .await?;
+
+        if let Some(row) = rows.next().await? {
+            return Ok(Some(config_from_row(&row)?));
+        }
+
+        Ok(None)
+    }
+
+    async fn read_configs(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+        key: Option<&str>,
+    ) -> AppResult<Option<ProjectConfigs>> {
+        let mut conn = self.pool.get().await?;
+        let mut rows = match key {
+            Some(key) => {
+                conn.prepared(READ_CONFIG_SQL)
+                    .await?
+                    .query(params![client_id.to_string(), project_id.to_string(), key])
+                    .await?
+            }
+            None => {
+                conn.prepared(READ_CONFIGS_SQL)
+                    .await?
+                    .query(params![client_id.to_string(), project_id.to_string()])
+                    .await?
+            }
+        };
+
+        let mut result: Option<ProjectConfigs> = None;
+        while let Some(row) = rows.next().await? {
+            let read = result.get_or_insert_with(|| ProjectConfigs {
+                permission: ClientPermission {
+                    client_id: *client_id,
+                    project_id: *project_id,
+                    can_read: false,
+                    can_write: false,
+                },
+                revision: 0,
+                configs: Vec::new(),
+            });
+            read.permission.can_read = row.get::<i64>(0)? != 0;
+            read.permission.can_write = row.get::<i64>(1)? != 0;
+            read.revision = row.get::<i64>(2)?;
+
+            // The LEFT JOIN yields one all-NULL config row when there are none.
+            if row.get::<Option<String>>(3)?.is_some() {
+                read.configs.push(config_from_row_at(&row, 3)?);
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+/// Upserts archived clients, returning how many rows were written.
+async fn restore_clients(conn: &Connection, clients: &[Client]) -> AppResult<u64> {
+    let mut restored = 0;
+    for client in clients {
+        restored += conn
+            .execute(
+                r"
+                INSERT INTO clients (id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+                ON CONFLICT(id) DO UPDATE SET
+                    name = excluded.name,
+                    public_key = excluded.public_key,
+                    is_admin = excluded.is_admin,
+                    key_algorithm = excluded.key_algorithm,
+                    allowed_networks = excluded.allowed_networks
+                ",
+                params![
+                    client.id.to_string(),
+                    client.name.as_str(),
+                    client.public_key.as_str(),
+                    i64::from(client.is_admin),
+                    client.created_at.as_str(),
+                    client.key_algorithm.as_str(),
+                    join_client_networks(&client.allowed_networks)
+                ],
+            )
+            .await?;
+    }
+
+    Ok(restored)
+}
+
+/// Empties the database ahead of a replace restore. Archived projects are
+/// kept so their revision never goes backwards.
+async fn clear_for_replace(conn: &Connection, archived: &HashSet<String>) -> AppResult<()> {
+    conn.execute_batch(
+        "DELETE FROM client_permissions; DELETE FROM configs; DELETE FROM used_nonces; DELETE FROM clients;",
+    )
+    .await?;
+
+    let mut rows = conn.query("SELECT id FROM projects", ()).await?;
+    let mut stale = Vec::new();
+    while let Some(row) = rows.next().await? {
+        let id = row.get::<String>(0)?;
+        if !archived.contains(&id) {
+            stale.push(id);
+        }
+    }
+    drop(rows);
+    for id in stale {
+        conn.execute("DELETE FROM projects WHERE id = ?1", params![id])
+            .await?;
+    }
+
+    Ok(())
+}
+
+fn remote_auth_token(config: &AppConfig) -> AppResult<String> {
+    match &config.turso_auth_token {
+        Some(token) => Ok(token.clone()),
+        // Plain-HTTP URLs point at a local libsql server, which needs no token.
+        None if config.database_url.starts_with("http://") => Ok(String::new()),
+        None => Err(AppError::BadRequest(String::from(
+            "TURSO_AUTH_TOKEN is required for a remote DATABASE_URL",
+        ))),
+    }
+}
+
+fn is_unique_constraint_error(error: &libsql::Error) -> bool {
+    error.to_string().contains("UNIQUE constraint failed")
+}
+
+fn client_from_row(row: &Row) -> AppResult<Client> {
+    let id_raw = row.get::<String>(0)?;
+    let id = parse_uuid(&id_raw)?;
+    let name = row.get::<String>(1)?;
+    let public_key = row.get::<String>(2)?;
+    let is_admin = row.get::<i64>(3)? != 0;
+    let created_at = row.get::<String>(4)?;
+    let key_algorithm = parse_key_algorithm(&row.get::<String>(5)?)?;
+    let allowed_networks = parse_client_networks(&row.get::<String>(6)?);
+
+    Ok(Client {
+        id,
+        name,
+        public_key,
+        is_admin,
+        created_at,
+        key_algorithm,
+        allowed_networks,
+    })
+}
+
+fn project_from_row(row: &Row) -> AppResult<Project> {
+    let id_raw = row.get::<String>(0)?;
+    let id = parse_uuid(&id_raw)?;
+    let name = row.get::<String>(1)?;
+    let description = row.get::<String>(2)?;
+    let created_at = row.get::<String>(3)?;
+    let revision = row.get::<i64>(4)?;
+
+    Ok(Project {
+        id,
+        name,
+        description,
+        created_at,
+        revision,
+    })
+}
+
+fn config_from_row(row: &Row) -> AppResult<ConfigItem> {
+    config_from_row_at(row, 0)
+}
+
+/// Reads a config whose columns start at `offset`, for joined queries.
+fn config_from_row_at(row: &Row, offset: i32) -> AppResult<ConfigItem> {
+    let id_raw = row.get::<String>(offset)?;
+    let project_id_raw = row.get::<String>(offset + 1)?;
+    let id = parse_uuid(&id_raw)?;
+    let project_id = parse_uuid(&project_id_raw)?;
+    let key = row.get::<String>(offset + 2)?;
+    let value = row.get::<String>(offset + 3)?;
+    let version = row.get::<i64>(offset + 4)?;
+    let updated_at = row.get::<String>(offset + 5)?;
+
+    Ok(ConfigItem {
+        id,
+        project_id,
+        key,
+        value,
+        version,
+        updated_at,
+    })
+}
+
+const CERTIFICATE_COLUMNS: &str =
+    "serial, client_id, fingerprint, issued_at, expires_at, revoked_at";
+
+fn certificate_from_row(row: &Row) -> AppResult<ClientCertificate> {
+    Ok(ClientCertificate {
+        serial: row.get::<String>(0)?,
+        client_id: parse_uuid(&row.get::<String>(1)?)?,
+        fingerprint: row.get::<String>(2)?,
+        issued_at: row.get::<i64>(3)?,
+        expires_at: row.get::<i64>(4)?,
+        revoked_at: row.get::<Option<i64>>(5)?,
+    })
+}
+
+const SESSION_COLUMNS: &str = "id, client_id, scopes, created_at, expires_at, revoked_at";
+
+fn session_from_row(row: &Row) -> AppResult<Session> {
+    Ok(Session {
+        id: parse_uuid(&row.get::<String>(0)?)?,
+        client_id: parse_uuid(&row.get::<String>(1)?)?,
+        scopes: parse_session_scopes(&row.get::<String>(2)?)?,
+        created_at: row.get::<i64>(3)?,
+        expires_at: row.get::<i64>(4)?,
+        revoked_at: row.get::<Option<i64>>(5)?,
+    })
+}
+
+const PENDING_ACTION_COLUMNS: &str = "id, operation, requested_by, required_approvals, status, created_at, expires_at, resolved_at, resolved_by";
+
+/// Leaves `approvals` empty; see [`load_approvals`].
+fn pending_action_from_row(row: &Row) -> AppResult<PendingAction> {
+    let operation = parse_admin_operation(&row.get::<String>(1)?)?;
+    Ok(PendingAction {
+        id: parse_uuid(&row.get::<String>(0)?)?,
+        action: operation.action(),
+        operation,
+        requested_by: parse_uuid(&row.get::<String>(2)?)?,
+        approvals: Vec::new(),
+        required_approvals: parse_required_approvals(row.get::<i64>(3)?)?,
+        status: parse_pending_status(&row.get::<String>(4)?)?,
+        created_at: row.get::<i64>(5)?,
+        expires_at: row.get::<i64>(6)?,
+        resolved_at: row.get::<Option<i64>>(7)?,
+        resolved_by: row
+            .get::<Option<String>>(8)?
+            .as_deref()
+            .map(parse_uuid)
+            .transpose()?,
+    })
+}
+
+/// `(action_id, client_id)` approvals of one action, or of all of them.
+async fn load_approvals(
+    conn: &Connection,
+    action_id: Option<&str>,
+) -> AppResult<Vec<(Uuid, Uuid)>> {
+    let mut rows = conn
+        .query(
+            r"
+            SELECT action_id, client_id FROM pending_action_approvals
+            WHERE ?1 IS NULL OR action_id = ?1
+            ORDER BY approved_at ASC, client_id ASC
+            ",
+            params![action_id],
+        )
+        .await?;
+
+    let mut approvals = Vec::new();
+    while let Some(row) = rows.next().await? {
+        approvals.push((
+            parse_uuid(&row.get::<String>(0)?)?,
+            parse_uuid(&row.get::<String>(1)?)?,
+        ));
+    }
+    Ok(approvals)
+}
+
+fn permission_from_row(row: &Row) -> AppResult<ClientPermission> {
+    let client_id_raw = row.get::<String>(0)?;
+    let project_id_raw = row.get::<String>(1)?;
+    let client_id = parse_uuid(&client_id_raw)?;
+    let project_id = parse_uuid(&project_id_raw)?;
+    let can_read = row.get::<i64>(2)? != 0;
+    let can_write = row.get::<i64>(3)? != 0;
+
+    Ok(ClientPermission {
+        client_id,
+        project_id,
+        can_read,
+        can_write,
+    })
+}
@@ -0,0 +1,1063 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use uuid::Uuid;
+
+use super::{
+    ConfigSnapshot, Storage, certificate_conflict, check_expected_version, config_not_found,
+    limit_change_feed, project_name_conflict, replayed_request, validate_config_batch,
+    validate_config_key, validate_name, validate_nonce,
+};
+use crate::{
+    auth,
+    backup::{Backup, RestoreMode, RestoreSummary},
+    error::{AppError, AppResult},
+    keys::KeyAlgorithm,
+    migrations::{AppliedMigration, MIGRATIONS, SchemaStatus},
+    models::{
+        BatchWriteResponse, ChangeFeed, ChangeOp, Client, ClientCertificate, ClientPermission,
+        ConfigChange, ConfigItem, ConfigOperation, OtpEnrollment, PendingAction,
+        PendingActionStatus, Project, Session,
+    },
+};
+
+/// Process-local store with no persistence. Rows are kept in insertion order,
+/// which stands in for `created_at` ordering in the SQL backends.
+#[derive(Debug)]
+pub struct MemoryStore {
+    state: Mutex<MemoryState>,
+    started_at: String,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    clients: Vec<Client>,
+    projects: Vec<Project>,
+    configs: Vec<ConfigItem>,
+    permissions: Vec<ClientPermission>,
+    nonces: HashMap<(Uuid, String), i64>,
+    change_seq: i64,
+    changes: Vec<(Uuid, ConfigChange)>,
+    history_start: HashMap<Uuid, i64>,
+    certificates: Vec<ClientCertificate>,
+    /// Challenge to `(client_id, expires_at)`.
+    session_challenges: HashMap<String, (Uuid, i64)>,
+    /// Sessions with the hash of their token.
+    sessions: Vec<(Session, String)>,
+    otp: HashMap<Uuid, OtpEnrollment>,
+    /// `(client_id, code_hash, used_at)`.
+    recovery_codes: Vec<(Uuid, String, Option<i64>)>,
+    pending_actions: Vec<PendingAction>,
+}
+
+impl MemoryState {
+    /// Mirrors the SQL triggers: bumps the project revision and logs the write.
+    fn log_change(&mut self, item: &ConfigItem, op: ChangeOp) {
+        let Some(project) = self.projects.iter_mut().find(|p| p.id == item.project_id) else {
+            return;
+        };
+        project.revision += 1;
+        let change = ConfigChange {
+            revision: project.revision,
+            key: item.key.clone(),
+            op,
+            value: (op == ChangeOp::Set).then(|| item.value.clone()),
+            version: item.version,
+            changed_at: now_datetime(),
+        };
+        self.changes.push((item.project_id, change));
+    }
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(MemoryState::default()),
+            started_at: now_datetime(),
+        }
+    }
+
+    fn lock(&self) -> AppResult<MutexGuard<'_, MemoryState>> {
+        self.state
+            .lock()
+            .map_err(|_| AppError::Internal(String::from("memory store lock poisoned")))
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStore {
+    async fn migrate(&self) -> AppResult<Vec<i64>> {
+        Ok(Vec::new())
+    }
+
+    async fn schema_status(&self) -> AppResult<SchemaStatus> {
+        let applied: Vec<AppliedMigration> = MIGRATIONS
+            .iter()
+            .map(|migration| AppliedMigration {
+                version: migration.version,
+                name: migration.name.to_owned(),
+                checksum: migration.checksum(),
+                applied_at: self.started_at.clone(),
+            })
+            .collect();
+        SchemaStatus::from_applied(MIGRATIONS, &applied)
+    }
+
+    async fn ping(&self) -> AppResult<()> {
+        self.lock().map(|_| ())
+    }
+
+    async fn change_seq(&self) -> AppResult<i64> {
+        Ok(self.lock()?.change_seq)
+    }
+
+    async fn get_client_by_id(&self, client_id: &Uuid) -> AppResult<Option<Client>> {
+        let state = self.lock()?;
+        Ok(state.clients.iter().find(|c| c.id == *client_id).cloned())
+    }
+
+    async fn get_admin_client(&self) -> AppResult<Option<Client>> {
+        let state = self.lock()?;
+        Ok(state.clients.iter().find(|c| c.is_admin).cloned())
+    }
+
+    async fn create_client(
+        &self,
+        name: &str,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        is_admin: bool,
+    ) -> AppResult<Client> {
+        let name = validate_name(name, "client")?;
+        let client = Client {
+            id: Uuid::new_v4(),
+            name: name.to_owned(),
+            public_key: public_key.to_owned(),
+            is_admin,
+            created_at: now_datetime(),
+            key_algorithm,
+            allowed_networks: Vec::new(),
+        };
+
+        let mut state = self.lock()?;
+        state.clients.push(client.clone());
+        state.change_seq += 1;
+        Ok(client)
+    }
+
+    async fn set_client_public_key(
+        &self,
+        client_id: &Uuid,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        rotated_at: i64,
+    ) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let Some(client) = state.clients.iter_mut().find(|c| c.id == *client_id) else {
+            return Ok(false);
+        };
+
+        public_key.clone_into(&mut client.public_key);
+        client.key_algorithm = key_algorithm;
+        for (session, _) in &mut state.sessions {
+            if session.client_id == *client_id && session.revoked_at.is_none() {
+                session.revoked_at = Some(rotated_at);
+            }
+        }
+        for certificate in &mut state.certificates {
+            if certificate.client_id == *client_id && certificate.revoked_at.is_none() {
+                certificate.revoked_at = Some(rotated_at);
+            }
+        }
+        state.change_seq += 1;
+        Ok(true)
+    }
+
+    async fn set_client_networks(&self, client_id: &Uuid, networks: &[String]) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let Some(client) = state.clients.iter_mut().find(|c| c.id == *client_id) else {
+            return Ok(false);
+        };
+
+        networks.clone_into(&mut client.allowed_networks);
+        state.change_seq += 1;
+        Ok(true)
+    }
+
+    async fn list_clients(&self) -> AppResult<Vec<Client>> {
+        let state = self.lock()?;
+        Ok(state.clients.iter().rev().cloned().collect())
+    }
+
+    async fn delete_client(&self, client_id: &Uuid) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let before = state.clients.len();
+        state.clients.retain(|c| c.id != *client_id);
+        if state.clients.len() == before {
+            return Ok(false);
+        }
+
+        state.permissions.retain(|p| p.client_id != *client_id);
+        state.nonces.retain(|(id, _), _| id != client_id);
+        state.certificates.retain(|c| c.client_id != *client_id);
+        state
+            .session_challenges
+            .retain(|_, (id, _)| id != client_id);
+        state.sessions.retain(|(s, _)| s.client_id != *client_id);
+        state.otp.remove(client_id);
+        state.recovery_codes.retain(|(id, _, _)| id != client_id);
+        state
+            .pending_actions
+            .retain(|a| a.requested_by != *client_id);
+        for action in &mut state.pending_actions {
+            action.approvals.retain(|id| id != client_id);
+            if action.resolved_by == Some(*client_id) {
+                action.resolved_by = None;
+            }
+        }
+        state.change_seq += 1;
+        Ok(true)
+    }
+
+    async fn create_project(&self, name: &str, description: &str) -> AppResult<Project> {
+        let name = validate_name(name, "project")?;
+        let mut state = self.lock()?;
+        if state.projects.iter().any(|p| p.name == name) {
+            return Err(project_name_conflict());
+        }
+
+        let project = Project {
+            id: Uuid::new_v4(),
+            name: name.to_owned(),
+            description: description.to_owned(),
+            created_at: now_datetime(),
+            revision: 0,
+        };
+        state.projects.push(project.clone());
+        state.change_seq += 1;
+        Ok(project)
+    }
+
+    async fn get_project_by_id(&self, project_id: &Uuid) -> AppResult<Option<Project>> {
+        let state = self.lock()?;
+        Ok(state.projects.iter().find(|p| p.id == *project_id).cloned())
+    }
+
+    async fn get_project_by_name(&self, name: &str) -> AppResult<Option<Project>> {
+        let name = name.trim();
+        let state = self.lock()?;
+        Ok(state.projects.iter().find(|p| p.name == name).cloned())
+    }
+
+    async fn list_projects(&self) -> AppResult<Vec<Project>> {
+        let state = self.lock()?;
+        let mut projects = state.projects.clone();
+        projects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(projects)
+    }
+
+    async fn list_projects_for_client(&self, client_id: &Uuid) -> AppResult<Vec<Project>> {
+        let state = self.lock()?;
+        let mut projects: Vec<Project> = state
+            .projects
+            .iter()
+            .filter(|project| {
+                state.permissions.iter().any(|p| {
+                    p.client_id == *client_id
+                        && p.project_id == project.id
+                        && (p.can_read || p.can_write)
+                })
+            })
+            .cloned()
+            .collect();
+        projects.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(projects)
+    }
+
+    async fn delete_project(&self, project_id: &Uuid) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let before = state.projects.len();
+        state.projects.retain(|p| p.id != *project_id);
+        if state.projects.len() == before {
+            return Ok(false);
+        }
+
+        state.configs.retain(|c| c.project_id != *project_id);
+        state.permissions.retain(|p| p.project_id != *project_id);
+        state.changes.retain(|(id, _)| id != project_id);
+        state.history_start.remove(project_id);
+        state.change_seq += 1;
+        Ok(true)
+    }
+
+    async fn set_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+        can_read: bool,
+        can_write: bool,
+    ) -> AppResult<ClientPermission> {
+        let mut state = self.lock()?;
+        if !state.clients.iter().any(|c| c.id == *client_id) {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+        if !state.projects.iter().any(|p| p.id == *project_id) {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let permission = ClientPermission {
+            client_id: *client_id,
+            project_id: *project_id,
+            can_read,
+            can_write,
+        };
+        match state
+            .permissions
+            .iter_mut()
+            .find(|p| p.client_id == *client_id && p.project_id == *project_id)
+        {
+            Some(existing) => *existing = permission.clone(),
+            None => state.permissions.push(permission.clone()),
+        }
+        state.change_seq += 1;
+
+        Ok(permission)
+    }
+
+    async fn get_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+    ) -> AppResult<Option<ClientPermission>> {
+        let state = self.lock()?;
+        Ok(state
+            .permissions
+            .iter()
+            .find(|p| p.client_id == *client_id && p.project_id == *project_id)
+            .cloned())
+    }
+
+    async fn list_permissions_for_client(
+        &self,
+        client_id: &Uuid,
+    ) -> AppResult<Vec<ClientPermission>> {
+        let state = self.lock()?;
+        let project_name = |project_id: &Uuid| {
+            state
+                .projects
+                .iter()
+                .find(|p| p.id == *project_id)
+                .map(|p| p.name.clone())
+        };
+
+        let mut permissions: Vec<(String, ClientPermission)> = state
+            .permissions
+            .iter()
+            .filter(|p| p.client_id == *client_id)
+            .filter_map(|p| project_name(&p.project_id).map(|name| (name, p.clone())))
+            .collect();
+        permissions.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(permissions.into_iter().map(|(_, p)| p).collect())
+    }
+
+    async fn delete_permission(&self, client_id: &Uuid, project_id: &Uuid) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let before = state.permissions.len();
+        state
+            .permissions
+            .retain(|p| !(p.client_id == *client_id && p.project_id == *project_id));
+        if state.permissions.len() == before {
+            return Ok(false);
+        }
+
+        state.change_seq += 1;
+        Ok(true)
+    }
+
+    async fn register_nonce(
+        &self,
+        client_id: &Uuid,
+        nonce: &str,
+        now_timestamp: i64,
+        ttl_seconds: i64,
+    ) -> AppResult<()> {
+        let nonce = validate_nonce(nonce)?;
+        let mut state = self.lock()?;
+
+        let key = (*client_id, nonce.to_owned());
+        let cutoff = now_timestamp - ttl_seconds;
+        if state
+            .nonces
+            .get(&key)
+            .is_some_and(|created_at| *created_at >= cutoff)
+        {
+            return Err(replayed_request());
+        }
+
+        state.nonces.insert(key, now_timestamp);
+        Ok(())
+    }
+
+    async fn purge_expired_nonces(&self, cutoff: i64) -> AppResult<u64> {
+        let mut state = self.lock()?;
+        let before = state.nonces.len();
+        state.nonces.retain(|_, created_at| *created_at >= cutoff);
+        Ok((before - state.nonces.len()) as u64)
+    }
+
+    async fn create_client_certificate(&self, certificate: &ClientCertificate) -> AppResult<()> {
+        let mut state = self.lock()?;
+        if !state.clients.iter().any(|c| c.id == certificate.client_id) {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+        if state
+            .certificates
+            .iter()
+            .any(|c| c.serial == certificate.serial || c.fingerprint == certificate.fingerprint)
+        {
+            return Err(certificate_conflict());
+        }
+
+        state.certificates.push(certificate.clone());
+        state.change_seq += 1;
+        Ok(())
+    }
+
+    async fn get_client_certificate(
+        &self,
+        fingerprint: &str,
+    ) -> AppResult<Option<ClientCertificate>> {
+        let state = self.lock()?;
+        Ok(state
+            .certificates
+            .iter()
+            .find(|c| c.fingerprint == fingerprint)
+            .cloned())
+    }
+
+    async fn list_client_certificates(
+        &self,
+        client_id: Option<&Uuid>,
+    ) -> AppResult<Vec<ClientCertificate>> {
+        let state = self.lock()?;
+        let mut certificates: Vec<ClientCertificate> = state
+            .certificates
+            .iter()
+            .filter(|c| client_id.is_none_or(|id| c.client_id == *id))
+            .cloned()
+            .collect();
+        certificates.sort_by(|a, b| {
+            b.issued_at
+                .cmp(&a.issued_at)
+                .then_with(|| a.serial.cmp(&b.serial))
+        });
+        Ok(certificates)
+    }
+
+    async fn revoke_client_certificate(&self, serial: &str, revoked_at: i64) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let Some(certificate) = state
+            .certificates
+            .iter_mut()
+            .find(|c| c.serial == serial && c.revoked_at.is_none())
+        else {
+            return Ok(false);
+        };
+
+        certificate.revoked_at = Some(revoked_at);
+        state.change_seq += 1;
+        Ok(true)
+    }
+
+    async fn create_session_challenge(
+        &self,
+        challenge: &str,
+        client_id: &Uuid,
+        expires_at: i64,
+        now_timestamp: i64,
+    ) -> AppResult<()> {
+        let mut state = self.lock()?;
+        if !state.clients.iter().any(|c| c.id == *client_id) {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+
+        state
+            .session_challenges
+            .retain(|_, (_, expires_at)| *expires_at > now_timestamp);
+        state
+            .session_challenges
+            .insert(challenge.to_owned(), (*client_id, expires_at));
+        Ok(())
+    }
+
+    async fn take_session_challenge(
+        &self,
+        challenge: &str,
+        now_timestamp: i64,
+    ) -> AppResult<Option<Uuid>> {
+        let mut state = self.lock()?;
+        Ok(state
+            .session_challenges
+            .remove(challenge)
+            .filter(|(_, expires_at)| *expires_at > now_timestamp)
+            .map(|(client_id, _)| client_id))
+    }
+
+    async fn create_session(&self, session: &Session, token_hash: &str) -> AppResult<()> {
+        let mut state = self.lock()?;
+        if !state.clients.iter().any(|c| c.id == session.client_id) {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+
+        state
+            .sessions
+            .push((session.clone(), token_hash.to_owned()));
+        Ok(())
+    }
+
+    async fn get_session_by_token(&self, token_hash: &str) -> AppResult<Option<Session>> {
+        let state = self.lock()?;
+        Ok(state
+            .sessions
+            .iter()
+            .find(|(_, hash)| hash == token_hash)
+            .map(|(session, _)| session.clone()))
+    }
+
+    async fn list_sessions(&self, client_id: Option<&Uuid>) -> AppResult<Vec<Session>> {
+        let state = self.lock()?;
+        let mut sessions: Vec<Session> = state
+            .sessions
+            .iter()
+            .map(|(session, _)| session)
+            .filter(|s| client_id.is_none_or(|id| s.client_id == *id))
+            .cloned()
+            .collect();
+        sessions.sort_by(|a, b| {
+            b.created_at
+                .cmp(&a.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        Ok(sessions)
+    }
+
+    async fn revoke_session(&self, session_id: &Uuid, revoked_at: i64) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let Some((session, _)) = state
+            .sessions
+            .iter_mut()
+            .find(|(s, _)| s.id == *session_id && s.revoked_at.is_none())
+        else {
+            return Ok(false);
+        };
+
+        session.revoked_at = Some(revoked_at);
+        Ok(true)
+    }
+
+    async fn set_otp_secret(&self, client_id: &Uuid, secret: &str) -> AppResult<()> {
+        let mut state = self.lock()?;
+        if !state.clients.iter().any(|c| c.id == *client_id) {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+
+        state.otp.insert(
+            *client_id,
+            OtpEnrollment {
+                client_id: *client_id,
+                secret: secret.to_owned(),
+                enabled_at: None,
+                last_step: 0,
+                failures: 0,
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_otp(&self, client_id: &Uuid) -> AppResult<Option<OtpEnrollment>> {
+        Ok(self.lock()?.otp.get(client_id).cloned())
+    }
+
+    async fn enable_otp(&self, client_id: &Uuid, enabled_at: i64, step: i64) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let Some(enrollment) = state
+            .otp
+            .get_mut(client_id)
+            .filter(|e| e.enabled_at.is_none())
+        else {
+            return Ok(false);
+        };
+
+        enrollment.enabled_at = Some(enabled_at);
+        enrollment.last_step = step;
+        enrollment.failures = 0;
+        Ok(true)
+    }
+
+    async fn accept_otp_step(&self, client_id: &Uuid, step: i64) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let Some(enrollment) = state.otp.get_mut(client_id).filter(|e| e.last_step < step) else {
+            return Ok(false);
+        };
+
+        enrollment.last_step = step;
+        enrollment.failures = 0;
+        Ok(true)
+    }
+
+    async fn record_otp_failure(&self, client_id: &Uuid) -> AppResult<i64> {
+        let mut state = self.lock()?;
+        Ok(state.otp.get_mut(client_id).map_or(0, |enrollment| {
+            enrollment.failures += 1;
+            enrollment.failures
+        }))
+    }
+
+    async fn delete_otp(&self, client_id: &Uuid) -> AppResult<bool> {
+        Ok(self.lock()?.otp.remove(client_id).is_some())
+    }
+
+    async fn replace_recovery_codes(
+        &self,
+        client_id: &Uuid,
+        code_hashes: &[String],
+    ) -> AppResult<()> {
+        let mut state = self.lock()?;
+        if !state.clients.iter().any(|c| c.id == *client_id) {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+
+        state.recovery_codes.retain(|(id, _, _)| id != client_id);
+        state.recovery_codes.extend(
+            code_hashes
+                .iter()
+                .map(|code_hash| (*client_id, code_hash.clone(), None)),
+        );
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        client_id: &Uuid,
+        code_hash: &str,
+        used_at: i64,
+    ) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let Some((_, _, used)) = state
+            .recovery_codes
+            .iter_mut()
+            .find(|(id, hash, used)| id == client_id && hash == code_hash && used.is_none())
+        else {
+            return Ok(false);
+        };
+
+        *used = Some(used_at);
+        if let Some(enrollment) = state.otp.get_mut(client_id) {
+            enrollment.failures = 0;
+        }
+        Ok(true)
+    }
+
+    async fn create_pending_action(&self, action: &PendingAction) -> AppResult<()> {
+        let mut state = self.lock()?;
+        if !state.clients.iter().any(|c| c.id == action.requested_by) {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+
+        state.pending_actions.push(action.clone());
+        Ok(())
+    }
+
+    async fn get_pending_action(&self, action_id: &Uuid) -> AppResult<Option<PendingAction>> {
+        let state = self.lock()?;
+        Ok(state
+            .pending_actions
+            .iter()
+            .find(|a| a.id == *action_id)
+            .cloned())
+    }
+
+    async fn list_pending_actions(&self) -> AppResult<Vec<PendingAction>> {
+        let mut actions = self.lock()?.pending_actions.clone();
+        actions.sort_by(|a, b| {
+            b.created_at
+                .cmp(&a.created_at)
+                .then_with(|| a.id.cmp(&b.id))
+        });
+        Ok(actions)
+    }
+
+    async fn approve_pending_action(
+        &self,
+        action_id: &Uuid,
+        client_id: &Uuid,
+        _approved_at: i64,
+    ) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        if !state.clients.iter().any(|c| c.id == *client_id) {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+        let Some(action) = state.pending_actions.iter_mut().find(|a| {
+            a.id == *action_id
+                && a.status == PendingActionStatus::Pending
+                && !a.approvals.contains(client_id)
+        }) else {
+            return Ok(false);
+        };
+
+        action.approvals.push(*client_id);
+        Ok(true)
+    }
+
+    async fn resolve_pending_action(
+        &self,
+        action_id: &Uuid,
+        from: PendingActionStatus,
+        to: PendingActionStatus,
+        resolved_at: i64,
+        resolved_by: Option<&Uuid>,
+    ) -> AppResult<bool> {
+        let mut state = self.lock()?;
+        let Some(action) = state
+            .pending_actions
+            .iter_mut()
+            .find(|a| a.id == *action_id && a.status == from)
+        else {
+            return Ok(false);
+        };
+
+        action.status = to;
+        action.resolved_at = Some(resolved_at);
+        action.resolved_by = resolved_by.copied();
+        Ok(true)
+    }
+
+    async fn expire_pending_actions(&self, now_timestamp: i64) -> AppResult<u64> {
+        let mut state = self.lock()?;
+        let mut expired = 0;
+        for action in &mut state.pending_actions {
+            if action.status == PendingActionStatus::Pending && action.expires_at <= now_timestamp {
+                action.status = PendingActionStatus::Expired;
+                action.resolved_at = Some(action.expires_at);
+                expired += 1;
+            }
+        }
+        Ok(expired)
+    }
+
+    async fn upsert_config(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+        value: &str,
+    ) -> AppResult<ConfigItem> {
+        let mut state = self.lock()?;
+        if !state.projects.iter().any(|p| p.id == *project_id) {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let key = validate_config_key(key)?;
+        let updated_at = now_datetime();
+        state.change_seq += 1;
+        if let Some(existing) = state
+            .configs
+            .iter_mut()
+            .find(|c| c.project_id == *project_id && c.key == key)
+        {
+            value.clone_into(&mut existing.value);
+            existing.version += 1;
+            existing.updated_at = updated_at;
+            let item = existing.clone();
+            state.log_change(&item, ChangeOp::Set);
+            return Ok(item);
+        }
+
+        let item = ConfigItem {
+            id: Uuid::new_v4(),
+            project_id: *project_id,
+            key: key.to_owned(),
+            value: value.to_owned(),
+            version: 1,
+            updated_at,
+        };
+        state.configs.push(item.clone());
+        state.log_change(&item, ChangeOp::Set);
+        Ok(item)
+    }
+
+    async fn apply_config_batch(
+        &self,
+        project_id: &Uuid,
+        operations: &[ConfigOperation],
+    ) -> AppResult<BatchWriteResponse> {
+        validate_config_batch(operations)?;
+
+        let mut state = self.lock()?;
+        if !state.projects.iter().any(|p| p.id == *project_id) {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        // Check everything before touching anything, since there is no rollback.
+        for operation in operations {
+            let key = validate_config_key(operation.key())?;
+            let current = state
+                .configs
+                .iter()
+                .find(|c| c.project_id == *project_id && c.key == key)
+                .map(|c| c.version);
+            check_expected_version(key, current, operation.expected_version())?;
+            if matches!(operation, ConfigOperation::Delete { .. }) && current.is_none() {
+                return Err(config_not_found(key));
+            }
+        }
+
+        let updated_at = now_datetime();
+        state.change_seq += 1;
+        let mut response = BatchWriteResponse::default();
+        for operation in operations {
+            let key = validate_config_key(operation.key())?;
+            let existing = state
+                .configs
+                .iter()
+                .position(|c| c.project_id == *project_id && c.key == key);
+            match (operation, existing) {
+                (ConfigOperation::Set { value, .. }, Some(index)) => {
+                    let item = &mut state.configs[index];
+                    value.clone_into(&mut item.value);
+                    item.version += 1;
+                    item.updated_at.clone_from(&updated_at);
+                    let item = item.clone();
+                    state.log_change(&item, ChangeOp::Set);
+                    response.configs.push(item);
+                }
+                (ConfigOperation::Set { value, .. }, None) => {
+                    let item = ConfigItem {
+                        id: Uuid::new_v4(),
+                        project_id: *project_id,
+                        key: key.to_owned(),
+                        value: value.clone(),
+                        version: 1,
+                        updated_at: updated_at.clone(),
+                    };
+                    state.configs.push(item.clone());
+                    state.log_change(&item, ChangeOp::Set);
+                    response.configs.push(item);
+                }
+                (ConfigOperation::Delete { .. }, Some(index)) => {
+                    let item = state.configs.remove(index);
+                    state.log_change(&item, ChangeOp::Delete);
+                    response.deleted.push(item.key);
+                }
+                (ConfigOperation::Delete { .. }, None) => return Err(config_not_found(key)),
+            }
+        }
+
+        Ok(response)
+    }
+
+    async fn restore_backup(
+        &self,
+        backup: &Backup,
+        mode: RestoreMode,
+    ) -> AppResult<RestoreSummary> {
+        let mut state = self.lock()?;
+        state.change_seq += 1;
+        if mode == RestoreMode::Replace {
+            for item in std::mem::take(&mut state.configs) {
+                state.log_change(&item, ChangeOp::Delete);
+            }
+            state.clients.clear();
+            state.permissions.clear();
+            state.nonces.clear();
+            state.certificates.clear();
+            state.session_challenges.clear();
+            state.sessions.clear();
+            state.otp.clear();
+            state.recovery_codes.clear();
+            state.pending_actions.clear();
+
+            // Archived projects are kept so their revision never goes backwards.
+            let archived: Vec<Uuid> = backup.projects.iter().map(|p| p.id).collect();
+            state.projects.retain(|p| archived.contains(&p.id));
+            state.changes.retain(|(id, _)| archived.contains(id));
+            state.history_start.retain(|id, _| archived.contains(id));
+        }
+
+        for client in &backup.clients {
+            state.clients.retain(|c| c.id != client.id);
+            state.clients.push(client.clone());
+        }
+        for project in &backup.projects {
+            let mut project = project.clone();
+            if let Some(index) = state.projects.iter().position(|p| p.id == project.id) {
+                project.revision = project.revision.max(state.projects[index].revision);
+                state.projects.remove(index);
+            }
+            state.projects.push(project);
+        }
+        for permission in &backup.permissions {
+            state.permissions.retain(|p| {
+                p.client_id != permission.client_id || p.project_id != permission.project_id
+            });
+            state.permissions.push(permission.clone());
+        }
+
+        let mut configs = 0;
+        for item in &backup.configs {
+            let existing = state
+                .configs
+                .iter()
+                .position(|c| c.project_id == item.project_id && c.key == item.key);
+            match existing {
+                Some(index) if state.configs[index].version > item.version => continue,
+                Some(index) => state.configs[index] = item.clone(),
+                None => state.configs.push(item.clone()),
+            }
+            state.log_change(item, ChangeOp::Set);
+            configs += 1;
+        }
+
+        // A fresh revision moves ETags on even when no row changed, and the
+        // log does not describe how the restored state was reached.
+        for project in &backup.projects {
+            let Some(restored) = state.projects.iter_mut().find(|p| p.id == project.id) else {
+                continue;
+            };
+            restored.revision += 1;
+            let revision = restored.revision;
+            state.history_start.insert(project.id, revision);
+        }
+
+        Ok(RestoreSummary {
+            clients: backup.clients.len() as u64,
+            projects: backup.projects.len() as u64,
+            permissions: backup.permissions.len() as u64,
+            configs,
+        })
+    }
+
+    async fn config_snapshot(&self, project_id: &Uuid) -> AppResult<ConfigSnapshot> {
+        let state = self.lock()?;
+        let project = state
+            .projects
+            .iter()
+            .find(|p| p.id == *project_id)
+            .ok_or_else(|| AppError::NotFound(String::from("project not found")))?;
+
+        let mut configs: Vec<ConfigItem> = state
+            .configs
+            .iter()
+            .filter(|c| c.project_id == *project_id)
+            .cloned()
+            .collect();
+        configs.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(ConfigSnapshot {
+            revision: project.revision,
+            configs,
+        })
+    }
+
+    async fn config_changes(
+        &self,
+        project_id: &Uuid,
+        since: i64,
+        limit: usize,
+    ) -> AppResult<ChangeFeed> {
+        let state = self.lock()?;
+        let project = state
+            .projects
+            .iter()
+            .find(|p| p.id == *project_id)
+            .ok_or_else(|| AppError::NotFound(String::from("project not found")))?;
+        let history_start = state.history_start.get(project_id).copied().unwrap_or(0);
+
+        let reset = since < history_start || since > project.revision;
+        let changes = if reset {
+            Vec::new()
+        } else {
+            state
+                .changes
+                .iter()
+                .filter(|(id, change)| id == project_id && change.revision > since)
+                .take(limit.saturating_add(1))
+                .map(|(_, change)| change.clone())
+                .collect()
+        };
+
+        let mut feed = ChangeFeed {
+            project_id: *project_id,
+            revision: project.revision,
+            reset,
+            changes,
+            has_more: false,
+        };
+        limit_change_feed(&mut feed, limit);
+        Ok(feed)
+    }
+
+    async fn prune_config_changes(&self, keep: u64) -> AppResult<u64> {
+        let keep = i64::try_from(keep).unwrap_or(i64::MAX);
+        let mut state = self.lock()?;
+        let mut starts = HashMap::new();
+        for project in &state.projects {
+            let start = state.history_start.get(&project.id).copied().unwrap_or(0);
+            starts.insert(project.id, start.max(project.revision.saturating_sub(keep)));
+        }
+
+        let before = state.changes.len();
+        state
+            .changes
+            .retain(|(id, change)| starts.get(id).is_none_or(|start| change.revision > *start));
+        let pruned = (before - state.changes.len()) as u64;
+        state.history_start.extend(starts);
+        Ok(pruned)
+    }
+
+    async fn list_configs_for_project(&self, project_id: &Uuid) -> AppResult<Vec<ConfigItem>> {
+        let state = self.lock()?;
+        if !state.projects.iter().any(|p| p.id == *project_id) {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let mut items: Vec<ConfigItem> = state
+            .configs
+            .iter()
+            .filter(|c| c.project_id == *project_id)
+            .cloned()
+            .collect();
+        items.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(items)
+    }
+
+    async fn get_config_by_key(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+    ) -> AppResult<Option<ConfigItem>> {
+        let state = self.lock()?;
+        Ok(state
+            .configs
+            .iter()
+            .find(|c| c.project_id == *project_id && c.key == key)
+            .cloned())
+    }
+}
+
+/// Formats the current UTC time like sqlite's `datetime('now')`.
+fn now_datetime() -> String {
+    let now = auth::current_unix_timestamp().unwrap_or_default();
+    let formatted = OffsetDateTime::from_unix_timestamp(now)
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH)
+        .format(&Rfc3339)
+        .unwrap_or_default();
+    // The stored form: a space for the `T` and no offset.
+    formatted.trim_end_matches('Z').replacen('T', " ", 1)
+}
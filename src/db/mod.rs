@@ -0,0 +1,586 @@
+mod cached_store;
+mod libsql_store;
+mod memory_store;
+mod pool;
+mod postgres_store;
+
+#[cfg(test)]
+mod bench;
+#[cfg(test)]
+mod conformance;
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{
+    auth,
+    backup::{Backup, RestoreMode, RestoreSummary},
+    config::AppConfig,
+    crypto,
+    error::{AppError, AppResult},
+    keys::KeyAlgorithm,
+    migrations::SchemaStatus,
+    models::{
+        AdminOperation, BatchWriteResponse, ChangeFeed, ChangeOp, Client, ClientCertificate,
+        ClientPermission, ConfigItem, ConfigOperation, OtpEnrollment, PendingAction,
+        PendingActionStatus, Project, ReplicaStatus, Session, SessionScope,
+    },
+};
+
+pub use cached_store::CachedStore;
+pub use libsql_store::LibsqlStore;
+pub use memory_store::MemoryStore;
+pub use postgres_store::PostgresStore;
+
+/// Everything the server needs from its database. Each backend must pass the
+/// shared suite in `conformance.rs`.
+#[async_trait]
+pub trait Storage: Debug + Send + Sync {
+    async fn migrate(&self) -> AppResult<Vec<i64>>;
+    async fn schema_status(&self) -> AppResult<SchemaStatus>;
+    async fn ping(&self) -> AppResult<()>;
+    /// Counter bumped by every write to clients, projects, permissions or
+    /// configs, including writes made by other instances.
+    async fn change_seq(&self) -> AppResult<i64>;
+
+    /// Pulls from the primary when running as a replica; `None` otherwise.
+    async fn sync(&self) -> AppResult<Option<ReplicaStatus>> {
+        Ok(None)
+    }
+
+    fn replica_status(&self) -> Option<ReplicaStatus> {
+        None
+    }
+
+    async fn get_client_by_id(&self, client_id: &Uuid) -> AppResult<Option<Client>>;
+    async fn get_admin_client(&self) -> AppResult<Option<Client>>;
+    async fn create_client(
+        &self,
+        name: &str,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        is_admin: bool,
+    ) -> AppResult<Client>;
+    /// Swaps in a new key and revokes the client's open sessions and mTLS
+    /// certificates, which were obtained with the old one.
+    async fn set_client_public_key(
+        &self,
+        client_id: &Uuid,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        rotated_at: i64,
+    ) -> AppResult<bool>;
+    /// Replaces the CIDR ranges the client may connect from; `false` if the
+    /// client does not exist.
+    async fn set_client_networks(&self, client_id: &Uuid, networks: &[String]) -> AppResult<bool>;
+    async fn list_clients(&self) -> AppResult<Vec<Client>>;
+    async fn delete_client(&self, client_id: &Uuid) -> AppResult<bool>;
+
+    async fn create_project(&self, name: &str, description: &str) -> AppResult<Project>;
+    async fn get_project_by_id(&self, project_id: &Uuid) -> AppResult<Option<Project>>;
+    async fn get_project_by_name(&self, name: &str) -> AppResult<Option<Project>>;
+    async fn list_projects(&self) -> AppResult<Vec<Project>>;
+    async fn list_projects_for_client(&self, client_id: &Uuid) -> AppResult<Vec<Project>>;
+    /// Deletes a project with its configs, change log and grants; `false` if
+    /// it does not exist.
+    async fn delete_project(&self, project_id: &Uuid) -> AppResult<bool>;
+
+    async fn set_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+        can_read: bool,
+        can_write: bool,
+    ) -> AppResult<ClientPermission>;
+    async fn get_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+    ) -> AppResult<Option<ClientPermission>>;
+    async fn list_permissions_for_client(
+        &self,
+        client_id: &Uuid,
+    ) -> AppResult<Vec<ClientPermission>>;
+    async fn delete_permission(&self, client_id: &Uuid, project_id: &Uuid) -> AppResult<bool>;
+
+    /// Records a nonce; a row older than `ttl_seconds` for the same nonce is
+    /// reclaimed rather than treated as a replay.
+    async fn register_nonce(
+        &self,
+        client_id: &Uuid,
+        nonce: &str,
+        now_timestamp: i64,
+        ttl_seconds: i64,
+    ) -> AppResult<()>;
+    async fn purge_expired_nonces(&self, cutoff: i64) -> AppResult<u64>;
+
+    async fn create_client_certificate(&self, certificate: &ClientCertificate) -> AppResult<()>;
+    /// Looks an issued certificate up by its SHA-256 fingerprint.
+    async fn get_client_certificate(
+        &self,
+        fingerprint: &str,
+    ) -> AppResult<Option<ClientCertificate>>;
+    /// Certificates issued to `client_id`, or to every client, newest first.
+    async fn list_client_certificates(
+        &self,
+        client_id: Option<&Uuid>,
+    ) -> AppResult<Vec<ClientCertificate>>;
+    /// `false` if the serial is unknown or already revoked.
+    async fn revoke_client_certificate(&self, serial: &str, revoked_at: i64) -> AppResult<bool>;
+
+    /// Records a one-time login challenge, dropping any that have expired.
+    async fn create_session_challenge(
+        &self,
+        challenge: &str,
+        client_id: &Uuid,
+        expires_at: i64,
+        now_timestamp: i64,
+    ) -> AppResult<()>;
+    /// Consumes a challenge, returning its client if it had not expired.
+    async fn take_session_challenge(
+        &self,
+        challenge: &str,
+        now_timestamp: i64,
+    ) -> AppResult<Option<Uuid>>;
+    async fn create_session(&self, session: &Session, token_hash: &str) -> AppResult<()>;
+    /// Looks a session up by the SHA-256 hex of its token.
+    async fn get_session_by_token(&self, token_hash: &str) -> AppResult<Option<Session>>;
+    /// Sessions of `client_id`, or of every client, newest first.
+    async fn list_sessions(&self, client_id: Option<&Uuid>) -> AppResult<Vec<Session>>;
+    /// `false` if the session is unknown or already revoked.
+    async fn revoke_session(&self, session_id: &Uuid, revoked_at: i64) -> AppResult<bool>;
+
+    /// Starts (or restarts) a TOTP enrollment, disabled until confirmed.
+    async fn set_otp_secret(&self, client_id: &Uuid, secret: &str) -> AppResult<()>;
+    async fn get_otp(&self, client_id: &Uuid) -> AppResult<Option<OtpEnrollment>>;
+    /// Enables a pending enrollment whose first code was for `step`; `false`
+    /// if there is nothing pending.
+    async fn enable_otp(&self, client_id: &Uuid, enabled_at: i64, step: i64) -> AppResult<bool>;
+    /// Accepts a code for `step` and clears failures; `false` if a code for
+    /// `step` or later was already accepted.
+    async fn accept_otp_step(&self, client_id: &Uuid, step: i64) -> AppResult<bool>;
+    /// Counts a wrong code, returning the consecutive failures so far.
+    async fn record_otp_failure(&self, client_id: &Uuid) -> AppResult<i64>;
+    async fn delete_otp(&self, client_id: &Uuid) -> AppResult<bool>;
+    /// Replaces every recovery code of the client with `code_hashes`.
+    async fn replace_recovery_codes(
+        &self,
+        client_id: &Uuid,
+        code_hashes: &[String],
+    ) -> AppResult<()>;
+    /// Spends an unused recovery code and clears OTP failures; `false` if the
+    /// code is unknown or already used.
+    async fn use_recovery_code(
+        &self,
+        client_id: &Uuid,
+        code_hash: &str,
+        used_at: i64,
+    ) -> AppResult<bool>;
+
+    /// Records a gated action together with its requester's approval.
+    async fn create_pending_action(&self, action: &PendingAction) -> AppResult<()>;
+    async fn get_pending_action(&self, action_id: &Uuid) -> AppResult<Option<PendingAction>>;
+    /// Every recorded action, newest first.
+    async fn list_pending_actions(&self) -> AppResult<Vec<PendingAction>>;
+    /// Adds an admin's approval; `false` if the action is not pending or that
+    /// admin already approved it.
+    async fn approve_pending_action(
+        &self,
+        action_id: &Uuid,
+        client_id: &Uuid,
+        approved_at: i64,
+    ) -> AppResult<bool>;
+    /// Moves an action from status `from` to `to`; `false` if it was not in
+    /// `from`, so only one caller wins a race.
+    async fn resolve_pending_action(
+        &self,
+        action_id: &Uuid,
+        from: PendingActionStatus,
+        to: PendingActionStatus,
+        resolved_at: i64,
+        resolved_by: Option<&Uuid>,
+    ) -> AppResult<bool>;
+    /// Marks pending actions whose `expires_at` has passed as expired.
+    async fn expire_pending_actions(&self, now_timestamp: i64) -> AppResult<u64>;
+
+    async fn upsert_config(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+        value: &str,
+    ) -> AppResult<ConfigItem>;
+    async fn list_configs_for_project(&self, project_id: &Uuid) -> AppResult<Vec<ConfigItem>>;
+    async fn get_config_by_key(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+    ) -> AppResult<Option<ConfigItem>>;
+
+    /// A project's configs together with the revision they reflect.
+    async fn config_snapshot(&self, project_id: &Uuid) -> AppResult<ConfigSnapshot>;
+    /// Changes logged after revision `since`, oldest first and at most `limit`
+    /// of them.
+    async fn config_changes(
+        &self,
+        project_id: &Uuid,
+        since: i64,
+        limit: usize,
+    ) -> AppResult<ChangeFeed>;
+    /// Drops all but the last `keep` revisions of every project's change log
+    /// and moves `history_start` past them. Returns how many changes went.
+    async fn prune_config_changes(&self, keep: u64) -> AppResult<u64>;
+
+    /// Applies every operation in one transaction, or none of them.
+    async fn apply_config_batch(
+        &self,
+        project_id: &Uuid,
+        operations: &[ConfigOperation],
+    ) -> AppResult<BatchWriteResponse>;
+
+    /// Writes a validated archive in one transaction, keeping its ids,
+    /// timestamps and config versions.
+    async fn restore_backup(&self, backup: &Backup, mode: RestoreMode)
+    -> AppResult<RestoreSummary>;
+
+    /// The client's grant on a project plus its configs (or just `key`), or
+    /// `None` without a grant. SQL backends answer this in one query.
+    async fn read_configs(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+        key: Option<&str>,
+    ) -> AppResult<Option<ProjectConfigs>> {
+        let Some(permission) = self.get_permission(client_id, project_id).await? else {
+            return Ok(None);
+        };
+
+        let mut snapshot = self.config_snapshot(project_id).await?;
+        if let Some(key) = key {
+            snapshot.configs.retain(|item| item.key == key);
+        }
+
+        Ok(Some(ProjectConfigs {
+            permission,
+            revision: snapshot.revision,
+            configs: snapshot.configs,
+        }))
+    }
+
+    async fn bootstrap_admin_if_missing(
+        &self,
+        admin_name: &str,
+    ) -> AppResult<Option<BootstrapAdmin>> {
+        if self.get_admin_client().await?.is_some() {
+            return Ok(None);
+        }
+
+        let generated = crypto::generate_ed25519_keypair()?;
+        let client = self
+            .create_client(
+                admin_name,
+                &generated.public_key_b64,
+                generated.algorithm,
+                true,
+            )
+            .await?;
+
+        Ok(Some(BootstrapAdmin {
+            client,
+            private_key_pem: generated.private_key_pem,
+        }))
+    }
+
+    async fn reset_admin(&self) -> AppResult<BootstrapAdmin> {
+        let generated = crypto::generate_ed25519_keypair()?;
+
+        let client = if let Some(admin) = self.get_admin_client().await? {
+            self.set_client_public_key(
+                &admin.id,
+                &generated.public_key_b64,
+                generated.algorithm,
+                auth::current_unix_timestamp()?,
+            )
+            .await?;
+            self.get_client_by_id(&admin.id)
+                .await?
+                .ok_or_else(|| AppError::Internal(String::from("failed to load reset admin")))?
+        } else {
+            self.create_client(
+                "bootstrap-admin",
+                &generated.public_key_b64,
+                generated.algorithm,
+                true,
+            )
+            .await?
+        };
+
+        Ok(BootstrapAdmin {
+            client,
+            private_key_pem: generated.private_key_pem,
+        })
+    }
+}
+
+/// Cheap, cloneable handle to the configured [`Storage`] backend.
+#[derive(Debug, Clone)]
+pub struct Database {
+    store: Arc<dyn Storage>,
+    cache: Option<Arc<CachedStore>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProjectConfigs {
+    pub permission: ClientPermission,
+    pub revision: i64,
+    pub configs: Vec<ConfigItem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigSnapshot {
+    pub revision: i64,
+    pub configs: Vec<ConfigItem>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BootstrapAdmin {
+    pub client: Client,
+    pub private_key_pem: String,
+}
+
+impl Database {
+    /// Picks a backend from the `DATABASE_URL` scheme: `postgres://` or
+    /// `postgresql://` for Postgres, `memory://` for the in-process store,
+    /// and libSQL for everything else. Reads are cached in front of it unless
+    /// `cache_ttl_seconds` is 0.
+    pub async fn connect(config: &AppConfig) -> AppResult<Self> {
+        let url = config.database_url.as_str();
+        let store: Arc<dyn Storage> =
+            if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+                Arc::new(
+                    PostgresStore::connect(
+                        url,
+                        config.database_pool_size,
+                        config.postgres_ca_cert_path.as_deref().map(Path::new),
+                    )
+                    .await?,
+                )
+            } else if url.starts_with("memory://") {
+                Arc::new(MemoryStore::new())
+            } else {
+                Arc::new(LibsqlStore::connect(config).await?)
+            };
+
+        if config.cache_ttl_seconds == 0 {
+            return Ok(Self { store, cache: None });
+        }
+
+        let cache = Arc::new(CachedStore::new(
+            store,
+            Duration::from_secs(config.cache_ttl_seconds),
+        ));
+        Ok(Self {
+            store: cache.clone(),
+            cache: Some(cache),
+        })
+    }
+
+    pub fn new(store: impl Storage + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+            cache: None,
+        }
+    }
+
+    /// The read cache, when enabled.
+    pub fn cache(&self) -> Option<&CachedStore> {
+        self.cache.as_deref()
+    }
+}
+
+impl Deref for Database {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        self.store.as_ref()
+    }
+}
+
+fn validate_name<'a>(name: &'a str, kind: &str) -> AppResult<&'a str> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest(format!("{kind} name cannot be empty")));
+    }
+
+    Ok(name)
+}
+
+fn validate_config_key(key: &str) -> AppResult<&str> {
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(AppError::BadRequest(String::from(
+            "config key cannot be empty",
+        )));
+    }
+
+    Ok(key)
+}
+
+pub fn validate_nonce(nonce: &str) -> AppResult<&str> {
+    let nonce = nonce.trim();
+    if nonce.is_empty() {
+        return Err(AppError::Unauthorized(String::from("missing nonce")));
+    }
+
+    Ok(nonce)
+}
+
+fn parse_uuid(raw: &str) -> AppResult<Uuid> {
+    Ok(Uuid::parse_str(raw)?)
+}
+
+/// Stores fetch one change past `limit` to tell whether the feed goes on;
+/// this cuts it back and ends the feed at the last change kept.
+fn limit_change_feed(feed: &mut ChangeFeed, limit: usize) {
+    let limit = limit.max(1);
+    if feed.changes.len() > limit {
+        feed.changes.truncate(limit);
+        if let Some(last) = feed.changes.last() {
+            feed.revision = last.revision;
+        }
+        feed.has_more = true;
+    }
+}
+
+/// Rejects empty batches and batches that touch a key twice.
+fn validate_config_batch(operations: &[ConfigOperation]) -> AppResult<()> {
+    if operations.is_empty() {
+        return Err(AppError::BadRequest(String::from(
+            "batch must contain at least one operation",
+        )));
+    }
+
+    let mut keys = std::collections::HashSet::new();
+    for operation in operations {
+        let key = validate_config_key(operation.key())?;
+        if !keys.insert(key) {
+            return Err(AppError::BadRequest(format!(
+                "config {key} appears more than once in the batch"
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn check_expected_version(key: &str, current: Option<i64>, expected: Option<i64>) -> AppResult<()> {
+    let Some(expected) = expected else {
+        return Ok(());
+    };
+    if current.unwrap_or(0) == expected {
+        return Ok(());
+    }
+
+    Err(AppError::Conflict(format!(
+        "config {key} is at version {}, expected {expected}",
+        current.unwrap_or(0)
+    )))
+}
+
+fn parse_key_algorithm(raw: &str) -> AppResult<KeyAlgorithm> {
+    KeyAlgorithm::parse(raw)
+        .map_err(|_| AppError::Database(format!("unknown client key algorithm: {raw}")))
+}
+
+fn parse_session_scopes(raw: &str) -> AppResult<Vec<SessionScope>> {
+    raw.split_whitespace()
+        .map(|scope| {
+            SessionScope::parse(scope)
+                .ok_or_else(|| AppError::Database(format!("unknown session scope: {scope}")))
+        })
+        .collect()
+}
+
+fn join_session_scopes(scopes: &[SessionScope]) -> String {
+    scopes
+        .iter()
+        .map(|scope| scope.as_str())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_client_networks(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .filter(|network| !network.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+fn join_client_networks(networks: &[String]) -> String {
+    networks.join(",")
+}
+
+fn parse_admin_operation(raw: &str) -> AppResult<AdminOperation> {
+    serde_json::from_str(raw)
+        .map_err(|e| AppError::Database(format!("invalid pending admin operation: {e}")))
+}
+
+fn encode_admin_operation(operation: &AdminOperation) -> AppResult<String> {
+    serde_json::to_string(operation)
+        .map_err(|e| AppError::Internal(format!("failed to encode admin operation: {e}")))
+}
+
+fn parse_pending_status(raw: &str) -> AppResult<PendingActionStatus> {
+    PendingActionStatus::parse(raw)
+        .ok_or_else(|| AppError::Database(format!("unknown pending action status: {raw}")))
+}
+
+fn parse_required_approvals(raw: i64) -> AppResult<u32> {
+    u32::try_from(raw).map_err(|_| AppError::Database(format!("invalid required approvals: {raw}")))
+}
+
+/// Approver ids grouped by action, the requester first and the rest in the
+/// order they approved.
+fn group_approvals(rows: impl IntoIterator<Item = (Uuid, Uuid)>, actions: &mut [PendingAction]) {
+    let mut by_action: HashMap<Uuid, Vec<Uuid>> = HashMap::new();
+    for (action_id, client_id) in rows {
+        by_action.entry(action_id).or_default().push(client_id);
+    }
+    for action in actions {
+        let mut approvals = by_action.remove(&action.id).unwrap_or_default();
+        approvals.sort_by_key(|id| *id != action.requested_by);
+        action.approvals = approvals;
+    }
+}
+
+fn parse_change_op(raw: &str) -> AppResult<ChangeOp> {
+    match raw {
+        "set" => Ok(ChangeOp::Set),
+        "delete" => Ok(ChangeOp::Delete),
+        other => Err(AppError::Database(format!(
+            "unknown config change op: {other}"
+        ))),
+    }
+}
+
+fn config_not_found(key: &str) -> AppError {
+    AppError::NotFound(format!("config {key} not found"))
+}
+
+fn project_name_conflict() -> AppError {
+    AppError::Conflict(String::from("project name already exists"))
+}
+
+fn certificate_conflict() -> AppError {
+    AppError::Conflict(String::from("certificate already recorded"))
+}
+
+pub fn replayed_request() -> AppError {
+    AppError::Unauthorized(String::from("replayed request"))
+}
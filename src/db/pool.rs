@@ -0,0 +1,177 @@
+use std::fmt::Debug;
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::error::{AppError, AppResult};
+
+/// A backend connection a [`Pool`] can open, check and replace.
+#[async_trait]
+pub trait Pooled: Sized + Send {
+    /// What the pool keeps to open more connections like this one.
+    type Connector: Debug + Send + Sync;
+
+    async fn connect(connector: &Self::Connector) -> AppResult<Self>;
+
+    /// Per-connection cleanup run whenever a connection is returned to its
+    /// pool.
+    fn release(&mut self) {}
+
+    /// A broken connection is dropped instead of returned to the pool, and
+    /// replaced the next time its slot is needed.
+    fn is_broken(&self) -> bool {
+        false
+    }
+}
+
+/// Fixed-size pool of backend connections. Each connection is handed out
+/// exclusively, so per-connection state such as cached prepared statements
+/// needs no further locking.
+#[derive(Debug)]
+pub struct Pool<T: Pooled> {
+    connector: T::Connector,
+    idle: Mutex<Vec<T>>,
+    permits: Semaphore,
+}
+
+impl<T: Pooled> Pool<T> {
+    /// Opens `size` connections up front, so a bad connector fails at startup.
+    pub async fn connect(connector: T::Connector, size: usize) -> AppResult<Self> {
+        let mut connections = Vec::with_capacity(size);
+        for _ in 0..size {
+            connections.push(T::connect(&connector).await?);
+        }
+
+        Ok(Self {
+            connector,
+            permits: Semaphore::new(size),
+            idle: Mutex::new(connections),
+        })
+    }
+
+    /// Waits for an idle connection; it returns to the pool when the guard
+    /// drops. A slot whose connection was discarded is reconnected here.
+    pub async fn get(&self) -> AppResult<PoolGuard<'_, T>> {
+        let permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|_| AppError::Internal(String::from("connection pool closed")))?;
+        let idle = {
+            let mut idle = self
+                .idle
+                .lock()
+                .map_err(|_| AppError::Internal(String::from("connection pool lock poisoned")))?;
+            // Connections can also break while idle, e.g. when the server restarts.
+            idle.retain(|conn| !conn.is_broken());
+            idle.pop()
+        };
+        let conn = match idle {
+            Some(conn) => conn,
+            // On failure the permit is released and a later call tries again.
+            None => T::connect(&self.connector).await?,
+        };
+
+        Ok(PoolGuard {
+            pool: self,
+            conn: Some(conn),
+            _permit: permit,
+        })
+    }
+}
+
+pub struct PoolGuard<'a, T: Pooled> {
+    pool: &'a Pool<T>,
+    conn: Option<T>,
+    // Dropped after `Drop::drop` has put the connection back.
+    _permit: SemaphorePermit<'a>,
+}
+
+impl<T: Pooled> Deref for PoolGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.conn.as_ref().expect("connection taken before drop")
+    }
+}
+
+impl<T: Pooled> DerefMut for PoolGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.conn.as_mut().expect("connection taken before drop")
+    }
+}
+
+impl<T: Pooled> Drop for PoolGuard<'_, T> {
+    fn drop(&mut self) {
+        let Some(mut conn) = self.conn.take() else {
+            return;
+        };
+
+        conn.release();
+        if conn.is_broken() {
+            tracing::warn!("discarding a broken database connection");
+            return;
+        }
+        if let Ok(mut idle) = self.pool.idle.lock() {
+            idle.push(conn);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[derive(Debug, Default)]
+    struct Connector {
+        opened: AtomicUsize,
+        refuse: AtomicBool,
+    }
+
+    struct Conn {
+        id: usize,
+        broken: bool,
+    }
+
+    #[async_trait]
+    impl Pooled for Conn {
+        type Connector = Connector;
+
+        async fn connect(connector: &Connector) -> AppResult<Self> {
+            if connector.refuse.load(Ordering::SeqCst) {
+                return Err(AppError::Database(String::from("connection refused")));
+            }
+            let id = connector.opened.fetch_add(1, Ordering::SeqCst);
+            Ok(Self { id, broken: false })
+        }
+
+        fn is_broken(&self) -> bool {
+            self.broken
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_broken_connections() {
+        let pool = Pool::<Conn>::connect(Connector::default(), 1)
+            .await
+            .unwrap();
+        assert_eq!(pool.get().await.unwrap().id, 0);
+
+        pool.get().await.unwrap().broken = true;
+        pool.connector.refuse.store(true, Ordering::SeqCst);
+        assert!(pool.get().await.is_err());
+
+        // The failed reconnect gave the slot back, so the next call retries.
+        pool.connector.refuse.store(false, Ordering::SeqCst);
+        assert_eq!(pool.get().await.unwrap().id, 1);
+        assert_eq!(pool.get().await.unwrap().id, 1);
+
+        // Breaking while idle is noticed on the way out of the pool.
+        pool.idle.lock().unwrap()[0].broken = true;
+        assert_eq!(pool.get().await.unwrap().id, 2);
+    }
+}
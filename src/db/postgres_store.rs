@@ -0,0 +1,1739 @@
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::ops::Deref;
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rustls::{
+    ClientConfig, RootCertStore,
+    crypto::ring::default_provider,
+    pki_types::{CertificateDer, pem::PemObject},
+};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_postgres::{
+    Client as PgClient, Config as PgConfig, Connection, NoTls, Row, Statement,
+    config::{Host, SslMode},
+    error::SqlState,
+};
+use tokio_postgres_rustls::MakeRustlsConnect;
+use uuid::Uuid;
+
+use super::{
+    ConfigSnapshot, ProjectConfigs, Storage, certificate_conflict, check_expected_version,
+    config_not_found, encode_admin_operation, group_approvals, join_client_networks,
+    join_session_scopes, limit_change_feed, parse_admin_operation, parse_change_op,
+    parse_client_networks, parse_key_algorithm, parse_pending_status, parse_required_approvals,
+    parse_session_scopes, parse_uuid,
+    pool::{Pool, Pooled},
+    project_name_conflict, replayed_request, validate_config_batch, validate_config_key,
+    validate_name, validate_nonce,
+};
+use crate::{
+    backup::{Backup, RestoreMode, RestoreSummary},
+    error::{AppError, AppResult},
+    keys::KeyAlgorithm,
+    migrations::{AppliedMigration, POSTGRES_MIGRATIONS, SchemaStatus},
+    models::{
+        BatchWriteResponse, ChangeFeed, Client, ClientCertificate, ClientPermission, ConfigChange,
+        ConfigItem, ConfigOperation, OtpEnrollment, PendingAction, PendingActionStatus, Project,
+        Session,
+    },
+};
+
+const MIGRATIONS_TABLE_SQL: &str = r"
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version     BIGINT PRIMARY KEY,
+    name        TEXT NOT NULL,
+    checksum    TEXT NOT NULL,
+    applied_at  TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')
+);
+";
+
+/// Same text format sqlite's `datetime('now')` produces, so API timestamps
+/// look identical across backends.
+const NOW_TEXT: &str = "to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')";
+
+const CLIENT_BY_ID_SQL: &str = "SELECT id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks FROM clients WHERE id = $1";
+
+const PERMISSION_SQL: &str = r"
+SELECT client_id, project_id, can_read, can_write
+FROM client_permissions
+WHERE client_id = $1 AND project_id = $2
+";
+
+/// Reclaims an expired row for the same nonce; otherwise a conflict means replay.
+const REGISTER_NONCE_SQL: &str = r"
+INSERT INTO used_nonces (client_id, nonce, created_at) VALUES ($1, $2, $3)
+ON CONFLICT (client_id, nonce) DO UPDATE SET created_at = excluded.created_at
+WHERE used_nonces.created_at < $4
+";
+
+const READ_CONFIGS_SQL: &str = r"
+SELECT cp.can_read, cp.can_write, p.revision, c.id, c.project_id, c.key, c.value, c.version, c.updated_at
+FROM client_permissions cp
+JOIN projects p ON p.id = cp.project_id
+LEFT JOIN configs c ON c.project_id = cp.project_id
+WHERE cp.client_id = $1 AND cp.project_id = $2
+ORDER BY c.key ASC
+";
+
+const CONFIG_SNAPSHOT_SQL: &str = r"
+SELECT p.revision, c.id, c.project_id, c.key, c.value, c.version, c.updated_at
+FROM projects p
+LEFT JOIN configs c ON c.project_id = p.id
+WHERE p.id = $1
+ORDER BY c.key ASC
+";
+
+const READ_CONFIG_SQL: &str = r"
+SELECT cp.can_read, cp.can_write, p.revision, c.id, c.project_id, c.key, c.value, c.version, c.updated_at
+FROM client_permissions cp
+JOIN projects p ON p.id = cp.project_id
+LEFT JOIN configs c ON c.project_id = cp.project_id AND c.key = $3
+WHERE cp.client_id = $1 AND cp.project_id = $2
+";
+
+#[derive(Debug)]
+pub struct PostgresStore {
+    pool: Pool<PgConnection>,
+}
+
+/// A pooled connection with the prepared statements it has already parsed.
+struct PgConnection {
+    client: PgClient,
+    statements: HashMap<&'static str, Statement>,
+}
+
+impl PgConnection {
+    async fn prepared(&mut self, sql: &'static str) -> AppResult<Statement> {
+        if let Some(statement) = self.statements.get(sql) {
+            return Ok(statement.clone());
+        }
+
+        let statement = self.client.prepare(sql).await?;
+        self.statements.insert(sql, statement.clone());
+        Ok(statement)
+    }
+}
+
+/// Opens connections to one Postgres database, over TLS unless the URL has
+/// `sslmode=disable`.
+struct PgConnector {
+    config: PgConfig,
+    tls: Option<MakeRustlsConnect>,
+}
+
+impl std::fmt::Debug for PgConnector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PgConnector")
+            .field("config", &self.config)
+            .field("tls", &self.tls.is_some())
+            .finish()
+    }
+}
+
+impl PgConnector {
+    /// `sslmode` defaults to `prefer`, which libpq and tokio-postgres let fall
+    /// back to plain TCP when the server does not offer TLS. Local servers
+    /// keep plain TCP there; anything else gets `require`. Certificates are
+    /// always verified, against `ca_cert_path` or else the public web roots.
+    fn new(url: &str, ca_cert_path: Option<&Path>) -> AppResult<Self> {
+        let mut config = url
+            .parse::<PgConfig>()
+            .map_err(|e| AppError::BadRequest(format!("invalid postgres DATABASE_URL: {e}")))?;
+        match (config.get_ssl_mode(), is_local(&config)) {
+            (SslMode::Disable, false) => {
+                tracing::warn!(
+                    "sslmode=disable: postgres traffic, password included, is unencrypted"
+                );
+                return Ok(Self { config, tls: None });
+            }
+            (SslMode::Disable | SslMode::Prefer, true) => {
+                return Ok(Self { config, tls: None });
+            }
+            (SslMode::Prefer, false) => {
+                config.ssl_mode(SslMode::Require);
+            }
+            _ => {}
+        }
+
+        let mut roots = RootCertStore::empty();
+        match ca_cert_path {
+            Some(path) => {
+                let read_error = |e: rustls::pki_types::pem::Error| {
+                    AppError::BadRequest(format!(
+                        "failed to read POSTGRES_CA_CERT_PATH {}: {e}",
+                        path.display()
+                    ))
+                };
+                for cert in CertificateDer::pem_file_iter(path).map_err(read_error)? {
+                    roots
+                        .add(cert.map_err(read_error)?)
+                        .map_err(|e| AppError::BadRequest(format!("invalid postgres CA: {e}")))?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+        let tls = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| AppError::Crypto(format!("postgres TLS setup failed: {e}")))?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Self {
+            config,
+            tls: Some(MakeRustlsConnect::new(tls)),
+        })
+    }
+}
+
+/// Unix sockets, `localhost` and loopback addresses.
+fn is_local(config: &PgConfig) -> bool {
+    config.get_hosts().iter().all(|host| match host {
+        Host::Tcp(name) => {
+            name == "localhost" || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback())
+        }
+        #[cfg(unix)]
+        Host::Unix(_) => true,
+    })
+}
+
+fn spawn_connection<S, T>((client, connection): (PgClient, Connection<S, T>)) -> PgClient
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        if let Err(error) = connection.await {
+            tracing::error!("postgres connection closed: {error}");
+        }
+    });
+    client
+}
+
+#[async_trait]
+impl Pooled for PgConnection {
+    type Connector = PgConnector;
+
+    async fn connect(connector: &PgConnector) -> AppResult<Self> {
+        let client = match &connector.tls {
+            Some(tls) => spawn_connection(connector.config.connect(tls.clone()).await?),
+            None => spawn_connection(connector.config.connect(NoTls).await?),
+        };
+
+        // `CREATE ... IF NOT EXISTS` otherwise logs a NOTICE on every start.
+        client
+            .batch_execute("SET client_min_messages TO WARNING")
+            .await?;
+        Ok(Self {
+            client,
+            statements: HashMap::new(),
+        })
+    }
+
+    /// The client closes for good once its connection task ends, e.g. after a
+    /// server restart or a network error.
+    fn is_broken(&self) -> bool {
+        self.client.is_closed()
+    }
+}
+
+impl Deref for PgConnection {
+    type Target = PgClient;
+
+    fn deref(&self) -> &PgClient {
+        &self.client
+    }
+}
+
+impl std::fmt::Debug for PgConnection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PgConnection")
+            .field("cached_statements", &self.statements.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl PostgresStore {
+    pub async fn connect(
+        url: &str,
+        pool_size: usize,
+        ca_cert_path: Option<&Path>,
+    ) -> AppResult<Self> {
+        let connector = PgConnector::new(url, ca_cert_path)?;
+        Ok(Self {
+            pool: Pool::connect(connector, pool_size).await?,
+        })
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStore {
+    async fn migrate(&self) -> AppResult<Vec<i64>> {
+        let status = self.schema_status().await?;
+        status.ensure_supported()?;
+
+        let mut applied = Vec::new();
+        for migration in POSTGRES_MIGRATIONS {
+            if status.is_applied(migration.version) {
+                continue;
+            }
+
+            // One simple-query message runs as a single transaction.
+            let batch = format!(
+                "BEGIN;\n{}\nINSERT INTO schema_migrations (version, name, checksum) VALUES ({}, '{}', '{}');\nCOMMIT;",
+                migration.sql,
+                migration.version,
+                migration.name.replace('\'', "''"),
+                migration.checksum()
+            );
+            self.pool.get().await?.batch_execute(&batch).await?;
+
+            tracing::info!(
+                "applied migration {:04} {}",
+                migration.version,
+                migration.name
+            );
+            applied.push(migration.version);
+        }
+
+        Ok(applied)
+    }
+
+    async fn schema_status(&self) -> AppResult<SchemaStatus> {
+        let conn = self.pool.get().await?;
+        conn.batch_execute(MIGRATIONS_TABLE_SQL).await?;
+
+        let rows = conn
+            .query(
+                "SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version ASC",
+                &[],
+            )
+            .await?;
+
+        let mut applied = Vec::with_capacity(rows.len());
+        for row in &rows {
+            applied.push(AppliedMigration {
+                version: row.try_get(0)?,
+                name: row.try_get(1)?,
+                checksum: row.try_get(2)?,
+                applied_at: row.try_get(3)?,
+            });
+        }
+
+        SchemaStatus::from_applied(POSTGRES_MIGRATIONS, &applied)
+    }
+
+    async fn ping(&self) -> AppResult<()> {
+        self.pool.get().await?.simple_query("SELECT 1").await?;
+        Ok(())
+    }
+
+    async fn change_seq(&self) -> AppResult<i64> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt("SELECT seq FROM change_seq WHERE id = 1", &[])
+            .await?;
+
+        row.map_or(Ok(0), |row| Ok(row.try_get(0)?))
+    }
+
+    async fn get_client_by_id(&self, client_id: &Uuid) -> AppResult<Option<Client>> {
+        let mut conn = self.pool.get().await?;
+        let statement = conn.prepared(CLIENT_BY_ID_SQL).await?;
+        let row = conn
+            .query_opt(&statement, &[&client_id.to_string()])
+            .await?;
+
+        row.as_ref().map(client_from_row).transpose()
+    }
+
+    async fn get_admin_client(&self) -> AppResult<Option<Client>> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                "SELECT id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks FROM clients WHERE is_admin ORDER BY created_at ASC LIMIT 1",
+                &[],
+            )
+            .await?;
+
+        row.as_ref().map(client_from_row).transpose()
+    }
+
+    async fn create_client(
+        &self,
+        name: &str,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        is_admin: bool,
+    ) -> AppResult<Client> {
+        let name = validate_name(name, "client")?;
+
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_one(
+                "INSERT INTO clients (id, name, public_key, is_admin, key_algorithm) VALUES ($1, $2, $3, $4, $5) RETURNING id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks",
+                &[
+                    &Uuid::new_v4().to_string(),
+                    &name,
+                    &public_key,
+                    &is_admin,
+                    &key_algorithm.as_str(),
+                ],
+            )
+            .await?;
+
+        client_from_row(&row)
+    }
+
+    async fn set_client_public_key(
+        &self,
+        client_id: &Uuid,
+        public_key: &str,
+        key_algorithm: KeyAlgorithm,
+        rotated_at: i64,
+    ) -> AppResult<bool> {
+        let mut conn = self.pool.get().await?;
+        let tx = conn.client.transaction().await?;
+        let client = client_id.to_string();
+        let affected = tx
+            .execute(
+                "UPDATE clients SET public_key = $1, key_algorithm = $2 WHERE id = $3",
+                &[&public_key, &key_algorithm.as_str(), &client],
+            )
+            .await?;
+        for table in ["sessions", "client_certificates"] {
+            tx.execute(
+                &format!(
+                    "UPDATE {table} SET revoked_at = $2 WHERE client_id = $1 AND revoked_at IS NULL"
+                ),
+                &[&client, &rotated_at],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn set_client_networks(&self, client_id: &Uuid, networks: &[String]) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                "UPDATE clients SET allowed_networks = $1 WHERE id = $2",
+                &[&join_client_networks(networks), &client_id.to_string()],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn list_clients(&self) -> AppResult<Vec<Client>> {
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query(
+                "SELECT id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks FROM clients ORDER BY created_at DESC",
+                &[],
+            )
+            .await?;
+
+        rows.iter().map(client_from_row).collect()
+    }
+
+    async fn delete_project(&self, project_id: &Uuid) -> AppResult<bool> {
+        let mut conn = self.pool.get().await?;
+        let tx = conn.client.transaction().await?;
+        let project = project_id.to_string();
+        // Configs first: their delete triggers write to the project's change log.
+        tx.execute("DELETE FROM configs WHERE project_id = $1", &[&project])
+            .await?;
+        let affected = tx
+            .execute("DELETE FROM projects WHERE id = $1", &[&project])
+            .await?;
+        tx.commit().await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn delete_client(&self, client_id: &Uuid) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                "DELETE FROM clients WHERE id = $1",
+                &[&client_id.to_string()],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn create_project(&self, name: &str, description: &str) -> AppResult<Project> {
+        let name = validate_name(name, "project")?;
+
+        let conn = self.pool.get().await?;
+        let result = conn
+            .query_one(
+                "INSERT INTO projects (id, name, description) VALUES ($1, $2, $3) RETURNING id, name, description, created_at, revision",
+                &[&Uuid::new_v4().to_string(), &name, &description],
+            )
+            .await;
+
+        match result {
+            Ok(row) => project_from_row(&row),
+            Err(error) if is_unique_violation(&error) => Err(project_name_conflict()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn get_project_by_id(&self, project_id: &Uuid) -> AppResult<Option<Project>> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                "SELECT id, name, description, created_at, revision FROM projects WHERE id = $1",
+                &[&project_id.to_string()],
+            )
+            .await?;
+
+        row.as_ref().map(project_from_row).transpose()
+    }
+
+    async fn get_project_by_name(&self, name: &str) -> AppResult<Option<Project>> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                "SELECT id, name, description, created_at, revision FROM projects WHERE name = $1",
+                &[&name.trim()],
+            )
+            .await?;
+
+        row.as_ref().map(project_from_row).transpose()
+    }
+
+    async fn list_projects(&self) -> AppResult<Vec<Project>> {
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query(
+                "SELECT id, name, description, created_at, revision FROM projects ORDER BY name ASC",
+                &[],
+            )
+            .await?;
+
+        rows.iter().map(project_from_row).collect()
+    }
+
+    async fn list_projects_for_client(&self, client_id: &Uuid) -> AppResult<Vec<Project>> {
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query(
+                r"
+                SELECT p.id, p.name, p.description, p.created_at, p.revision
+                FROM projects p
+                JOIN client_permissions cp ON cp.project_id = p.id
+                WHERE cp.client_id = $1 AND (cp.can_read OR cp.can_write)
+                ORDER BY p.name ASC
+                ",
+                &[&client_id.to_string()],
+            )
+            .await?;
+
+        rows.iter().map(project_from_row).collect()
+    }
+
+    async fn set_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+        can_read: bool,
+        can_write: bool,
+    ) -> AppResult<ClientPermission> {
+        if self.get_client_by_id(client_id).await?.is_none() {
+            return Err(AppError::NotFound(String::from("client not found")));
+        }
+
+        if self.get_project_by_id(project_id).await?.is_none() {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_one(
+                r"
+                INSERT INTO client_permissions (client_id, project_id, can_read, can_write)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (client_id, project_id) DO UPDATE SET
+                    can_read = excluded.can_read,
+                    can_write = excluded.can_write
+                RETURNING client_id, project_id, can_read, can_write
+                ",
+                &[
+                    &client_id.to_string(),
+                    &project_id.to_string(),
+                    &can_read,
+                    &can_write,
+                ],
+            )
+            .await?;
+
+        permission_from_row(&row)
+    }
+
+    async fn get_permission(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+    ) -> AppResult<Option<ClientPermission>> {
+        let mut conn = self.pool.get().await?;
+        let statement = conn.prepared(PERMISSION_SQL).await?;
+        let row = conn
+            .query_opt(
+                &statement,
+                &[&client_id.to_string(), &project_id.to_string()],
+            )
+            .await?;
+
+        row.as_ref().map(permission_from_row).transpose()
+    }
+
+    async fn list_permissions_for_client(
+        &self,
+        client_id: &Uuid,
+    ) -> AppResult<Vec<ClientPermission>> {
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query(
+                r"
+                SELECT cp.client_id, cp.project_id, cp.can_read, cp.can_write
+                FROM client_permissions cp
+                JOIN projects p ON p.id = cp.project_id
+                WHERE cp.client_id = $1
+                ORDER BY p.name ASC
+                ",
+                &[&client_id.to_string()],
+            )
+            .await?;
+
+        rows.iter().map(permission_from_row).collect()
+    }
+
+    async fn create_client_certificate(&self, certificate: &ClientCertificate) -> AppResult<()> {
+        let conn = self.pool.get().await?;
+        let result = conn
+            .execute(
+                r"
+                INSERT INTO client_certificates (serial, client_id, fingerprint, issued_at, expires_at, revoked_at)
+                VALUES ($1, $2, $3, $4, $5, $6)
+                ",
+                &[
+                    &certificate.serial,
+                    &certificate.client_id.to_string(),
+                    &certificate.fingerprint,
+                    &certificate.issued_at,
+                    &certificate.expires_at,
+                    &certificate.revoked_at,
+                ],
+            )
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(error) if is_unique_violation(&error) => Err(certificate_conflict()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    async fn get_client_certificate(
+        &self,
+        fingerprint: &str,
+    ) -> AppResult<Option<ClientCertificate>> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                &format!(
+                    "SELECT {CERTIFICATE_COLUMNS} FROM client_certificates WHERE fingerprint = $1"
+                ),
+                &[&fingerprint],
+            )
+            .await?;
+
+        row.as_ref().map(certificate_from_row).transpose()
+    }
+
+    async fn list_client_certificates(
+        &self,
+        client_id: Option<&Uuid>,
+    ) -> AppResult<Vec<ClientCertificate>> {
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query(
+                &format!(
+                    "SELECT {CERTIFICATE_COLUMNS} FROM client_certificates
+                     WHERE $1::TEXT IS NULL OR client_id = $1
+                     ORDER BY issued_at DESC, serial ASC"
+                ),
+                &[&client_id.map(Uuid::to_string)],
+            )
+            .await?;
+
+        rows.iter().map(certificate_from_row).collect()
+    }
+
+    async fn revoke_client_certificate(&self, serial: &str, revoked_at: i64) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                "UPDATE client_certificates SET revoked_at = $2 WHERE serial = $1 AND revoked_at IS NULL",
+                &[&serial, &revoked_at],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn create_session_challenge(
+        &self,
+        challenge: &str,
+        client_id: &Uuid,
+        expires_at: i64,
+        now_timestamp: i64,
+    ) -> AppResult<()> {
+        let conn = self.pool.get().await?;
+        conn.execute(
+            "DELETE FROM session_challenges WHERE expires_at <= $1",
+            &[&now_timestamp],
+        )
+        .await?;
+        conn.execute(
+            "INSERT INTO session_challenges (challenge, client_id, expires_at) VALUES ($1, $2, $3)",
+            &[&challenge, &client_id.to_string(), &expires_at],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn take_session_challenge(
+        &self,
+        challenge: &str,
+        now_timestamp: i64,
+    ) -> AppResult<Option<Uuid>> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                "DELETE FROM session_challenges WHERE challenge = $1 RETURNING client_id, expires_at",
+                &[&challenge],
+            )
+            .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        if row.try_get::<_, i64>(1)? <= now_timestamp {
+            return Ok(None);
+        }
+        Ok(Some(parse_uuid(row.try_get(0)?)?))
+    }
+
+    async fn create_session(&self, session: &Session, token_hash: &str) -> AppResult<()> {
+        let conn = self.pool.get().await?;
+        conn.execute(
+            r"
+            INSERT INTO sessions (id, client_id, token_hash, scopes, created_at, expires_at, revoked_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ",
+            &[
+                &session.id.to_string(),
+                &session.client_id.to_string(),
+                &token_hash,
+                &join_session_scopes(&session.scopes),
+                &session.created_at,
+                &session.expires_at,
+                &session.revoked_at,
+            ],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_session_by_token(&self, token_hash: &str) -> AppResult<Option<Session>> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                &format!("SELECT {SESSION_COLUMNS} FROM sessions WHERE token_hash = $1"),
+                &[&token_hash],
+            )
+            .await?;
+
+        row.as_ref().map(session_from_row).transpose()
+    }
+
+    async fn list_sessions(&self, client_id: Option<&Uuid>) -> AppResult<Vec<Session>> {
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query(
+                &format!(
+                    "SELECT {SESSION_COLUMNS} FROM sessions
+                     WHERE $1::TEXT IS NULL OR client_id = $1
+                     ORDER BY created_at DESC, id ASC"
+                ),
+                &[&client_id.map(Uuid::to_string)],
+            )
+            .await?;
+
+        rows.iter().map(session_from_row).collect()
+    }
+
+    async fn revoke_session(&self, session_id: &Uuid, revoked_at: i64) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                "UPDATE sessions SET revoked_at = $2 WHERE id = $1 AND revoked_at IS NULL",
+                &[&session_id.to_string(), &revoked_at],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn set_otp_secret(&self, client_id: &Uuid, secret: &str) -> AppResult<()> {
+        let conn = self.pool.get().await?;
+        conn.execute(
+            r"
+            INSERT INTO client_otp (client_id, secret) VALUES ($1, $2)
+            ON CONFLICT (client_id) DO UPDATE SET
+                secret = excluded.secret, enabled_at = NULL, last_step = 0, failures = 0
+            ",
+            &[&client_id.to_string(), &secret],
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn get_otp(&self, client_id: &Uuid) -> AppResult<Option<OtpEnrollment>> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                "SELECT client_id, secret, enabled_at, last_step, failures FROM client_otp WHERE client_id = $1",
+                &[&client_id.to_string()],
+            )
+            .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        Ok(Some(OtpEnrollment {
+            client_id: parse_uuid(row.try_get(0)?)?,
+            secret: row.try_get(1)?,
+            enabled_at: row.try_get(2)?,
+            last_step: row.try_get(3)?,
+            failures: row.try_get(4)?,
+        }))
+    }
+
+    async fn enable_otp(&self, client_id: &Uuid, enabled_at: i64, step: i64) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                r"
+                UPDATE client_otp SET enabled_at = $2, last_step = $3, failures = 0
+                WHERE client_id = $1 AND enabled_at IS NULL
+                ",
+                &[&client_id.to_string(), &enabled_at, &step],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn accept_otp_step(&self, client_id: &Uuid, step: i64) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                "UPDATE client_otp SET last_step = $2, failures = 0 WHERE client_id = $1 AND last_step < $2",
+                &[&client_id.to_string(), &step],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn record_otp_failure(&self, client_id: &Uuid) -> AppResult<i64> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                "UPDATE client_otp SET failures = failures + 1 WHERE client_id = $1 RETURNING failures",
+                &[&client_id.to_string()],
+            )
+            .await?;
+
+        match row {
+            Some(row) => Ok(row.try_get(0)?),
+            None => Ok(0),
+        }
+    }
+
+    async fn delete_otp(&self, client_id: &Uuid) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                "DELETE FROM client_otp WHERE client_id = $1",
+                &[&client_id.to_string()],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn replace_recovery_codes(
+        &self,
+        client_id: &Uuid,
+        code_hashes: &[String],
+    ) -> AppResult<()> {
+        let mut conn = self.pool.get().await?;
+        let tx = conn.client.transaction().await?;
+        let client = client_id.to_string();
+        tx.execute(
+            "DELETE FROM recovery_codes WHERE client_id = $1",
+            &[&client],
+        )
+        .await?;
+        for code_hash in code_hashes {
+            tx.execute(
+                "INSERT INTO recovery_codes (client_id, code_hash) VALUES ($1, $2)",
+                &[&client, code_hash],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn use_recovery_code(
+        &self,
+        client_id: &Uuid,
+        code_hash: &str,
+        used_at: i64,
+    ) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let client = client_id.to_string();
+        let affected = conn
+            .execute(
+                r"
+                UPDATE recovery_codes SET used_at = $3
+                WHERE client_id = $1 AND code_hash = $2 AND used_at IS NULL
+                ",
+                &[&client, &code_hash, &used_at],
+            )
+            .await?;
+        if affected == 0 {
+            return Ok(false);
+        }
+
+        conn.execute(
+            "UPDATE client_otp SET failures = 0 WHERE client_id = $1",
+            &[&client],
+        )
+        .await?;
+        Ok(true)
+    }
+
+    async fn create_pending_action(&self, action: &PendingAction) -> AppResult<()> {
+        let mut conn = self.pool.get().await?;
+        let tx = conn.client.transaction().await?;
+        let id = action.id.to_string();
+        tx.execute(
+            r"
+            INSERT INTO pending_actions (id, operation, requested_by, required_approvals, status, created_at, expires_at, resolved_at, resolved_by)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            ",
+            &[
+                &id,
+                &encode_admin_operation(&action.operation)?,
+                &action.requested_by.to_string(),
+                &i64::from(action.required_approvals),
+                &action.status.as_str(),
+                &action.created_at,
+                &action.expires_at,
+                &action.resolved_at,
+                &action.resolved_by.map(|id| id.to_string()),
+            ],
+        )
+        .await?;
+        for client_id in &action.approvals {
+            tx.execute(
+                "INSERT INTO pending_action_approvals (action_id, client_id, approved_at) VALUES ($1, $2, $3)",
+                &[&id, &client_id.to_string(), &action.created_at],
+            )
+            .await?;
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    async fn get_pending_action(&self, action_id: &Uuid) -> AppResult<Option<PendingAction>> {
+        let conn = self.pool.get().await?;
+        let id = action_id.to_string();
+        let row = conn
+            .query_opt(
+                &format!("SELECT {PENDING_ACTION_COLUMNS} FROM pending_actions WHERE id = $1"),
+                &[&id],
+            )
+            .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let mut actions = [pending_action_from_row(&row)?];
+        group_approvals(load_approvals(&conn.client, Some(&id)).await?, &mut actions);
+        let [action] = actions;
+        Ok(Some(action))
+    }
+
+    async fn list_pending_actions(&self) -> AppResult<Vec<PendingAction>> {
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query(
+                &format!(
+                    "SELECT {PENDING_ACTION_COLUMNS} FROM pending_actions ORDER BY created_at DESC, id ASC"
+                ),
+                &[],
+            )
+            .await?;
+
+        let mut actions = rows
+            .iter()
+            .map(pending_action_from_row)
+            .collect::<AppResult<Vec<_>>>()?;
+        group_approvals(load_approvals(&conn.client, None).await?, &mut actions);
+        Ok(actions)
+    }
+
+    async fn approve_pending_action(
+        &self,
+        action_id: &Uuid,
+        client_id: &Uuid,
+        approved_at: i64,
+    ) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                r"
+                INSERT INTO pending_action_approvals (action_id, client_id, approved_at)
+                SELECT id, $2, $3 FROM pending_actions WHERE id = $1 AND status = 'pending'
+                ON CONFLICT (action_id, client_id) DO NOTHING
+                ",
+                &[&action_id.to_string(), &client_id.to_string(), &approved_at],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn resolve_pending_action(
+        &self,
+        action_id: &Uuid,
+        from: PendingActionStatus,
+        to: PendingActionStatus,
+        resolved_at: i64,
+        resolved_by: Option<&Uuid>,
+    ) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                r"
+                UPDATE pending_actions SET status = $3, resolved_at = $4, resolved_by = $5
+                WHERE id = $1 AND status = $2
+                ",
+                &[
+                    &action_id.to_string(),
+                    &from.as_str(),
+                    &to.as_str(),
+                    &resolved_at,
+                    &resolved_by.map(Uuid::to_string),
+                ],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn expire_pending_actions(&self, now_timestamp: i64) -> AppResult<u64> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                r"
+                UPDATE pending_actions SET status = 'expired', resolved_at = expires_at
+                WHERE status = 'pending' AND expires_at <= $1
+                ",
+                &[&now_timestamp],
+            )
+            .await?;
+
+        Ok(affected)
+    }
+
+    async fn delete_permission(&self, client_id: &Uuid, project_id: &Uuid) -> AppResult<bool> {
+        let conn = self.pool.get().await?;
+        let affected = conn
+            .execute(
+                "DELETE FROM client_permissions WHERE client_id = $1 AND project_id = $2",
+                &[&client_id.to_string(), &project_id.to_string()],
+            )
+            .await?;
+
+        Ok(affected > 0)
+    }
+
+    async fn register_nonce(
+        &self,
+        client_id: &Uuid,
+        nonce: &str,
+        now_timestamp: i64,
+        ttl_seconds: i64,
+    ) -> AppResult<()> {
+        let nonce = validate_nonce(nonce)?;
+
+        let cutoff = now_timestamp - ttl_seconds;
+        let mut conn = self.pool.get().await?;
+        let statement = conn.prepared(REGISTER_NONCE_SQL).await?;
+        let inserted = conn
+            .execute(
+                &statement,
+                &[&client_id.to_string(), &nonce, &now_timestamp, &cutoff],
+            )
+            .await?;
+
+        if inserted == 0 {
+            return Err(replayed_request());
+        }
+
+        Ok(())
+    }
+
+    async fn purge_expired_nonces(&self, cutoff: i64) -> AppResult<u64> {
+        let conn = self.pool.get().await?;
+        let purged = conn
+            .execute("DELETE FROM used_nonces WHERE created_at < $1", &[&cutoff])
+            .await?;
+
+        Ok(purged)
+    }
+
+    async fn upsert_config(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+        value: &str,
+    ) -> AppResult<ConfigItem> {
+        if self.get_project_by_id(project_id).await?.is_none() {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let key = validate_config_key(key)?;
+        let sql = format!(
+            r"
+            INSERT INTO configs (id, project_id, key, value, version, updated_at)
+            VALUES ($1, $2, $3, $4, 1, {NOW_TEXT})
+            ON CONFLICT (project_id, key) DO UPDATE SET
+                value = excluded.value,
+                version = configs.version + 1,
+                updated_at = {NOW_TEXT}
+            RETURNING id, project_id, key, value, version, updated_at
+            "
+        );
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_one(
+                &sql,
+                &[
+                    &Uuid::new_v4().to_string(),
+                    &project_id.to_string(),
+                    &key,
+                    &value,
+                ],
+            )
+            .await?;
+
+        config_from_row(&row)
+    }
+
+    async fn config_snapshot(&self, project_id: &Uuid) -> AppResult<ConfigSnapshot> {
+        let mut conn = self.pool.get().await?;
+        let statement = conn.prepared(CONFIG_SNAPSHOT_SQL).await?;
+        let rows = conn.query(&statement, &[&project_id.to_string()]).await?;
+
+        let first = rows
+            .first()
+            .ok_or_else(|| AppError::NotFound(String::from("project not found")))?;
+        let mut configs = Vec::with_capacity(rows.len());
+        for row in &rows {
+            if row.try_get::<_, Option<&str>>(1)?.is_some() {
+                configs.push(config_from_row_at(row, 1)?);
+            }
+        }
+
+        Ok(ConfigSnapshot {
+            revision: first.try_get(0)?,
+            configs,
+        })
+    }
+
+    async fn config_changes(
+        &self,
+        project_id: &Uuid,
+        since: i64,
+        limit: usize,
+    ) -> AppResult<ChangeFeed> {
+        let conn = self.pool.get().await?;
+        let project = project_id.to_string();
+        let row = conn
+            .query_opt(
+                "SELECT revision, history_start FROM projects WHERE id = $1",
+                &[&project],
+            )
+            .await?
+            .ok_or_else(|| AppError::NotFound(String::from("project not found")))?;
+        let (revision, history_start): (i64, i64) = (row.try_get(0)?, row.try_get(1)?);
+
+        let mut feed = ChangeFeed {
+            project_id: *project_id,
+            revision,
+            reset: since < history_start || since > revision,
+            changes: Vec::new(),
+            has_more: false,
+        };
+        if feed.reset {
+            return Ok(feed);
+        }
+
+        // Bounded by the revision read above, so a write committed in between
+        // shows up in the next poll instead of being skipped.
+        let rows = conn
+            .query(
+                r"
+                SELECT revision, key, op, value, version, changed_at
+                FROM config_changes
+                WHERE project_id = $1 AND revision > $2 AND revision <= $3
+                ORDER BY revision ASC
+                LIMIT $4
+                ",
+                &[
+                    &project,
+                    &since,
+                    &revision,
+                    &i64::try_from(limit.saturating_add(1)).unwrap_or(i64::MAX),
+                ],
+            )
+            .await?;
+        for row in &rows {
+            feed.changes.push(ConfigChange {
+                revision: row.try_get(0)?,
+                key: row.try_get(1)?,
+                op: parse_change_op(row.try_get(2)?)?,
+                value: row.try_get(3)?,
+                version: row.try_get(4)?,
+                changed_at: row.try_get(5)?,
+            });
+        }
+
+        limit_change_feed(&mut feed, limit);
+        Ok(feed)
+    }
+
+    async fn prune_config_changes(&self, keep: u64) -> AppResult<u64> {
+        let keep = i64::try_from(keep).unwrap_or(i64::MAX);
+        let mut conn = self.pool.get().await?;
+        let tx = conn.client.transaction().await?;
+        tx.execute(
+            r"
+            UPDATE projects SET history_start = revision - $1
+            WHERE history_start < revision - $1
+            ",
+            &[&keep],
+        )
+        .await?;
+        let pruned = tx
+            .execute(
+                r"
+                DELETE FROM config_changes
+                USING projects
+                WHERE projects.id = config_changes.project_id
+                    AND config_changes.revision <= projects.history_start
+                ",
+                &[],
+            )
+            .await?;
+        tx.commit().await?;
+
+        Ok(pruned)
+    }
+
+    async fn apply_config_batch(
+        &self,
+        project_id: &Uuid,
+        operations: &[ConfigOperation],
+    ) -> AppResult<BatchWriteResponse> {
+        validate_config_batch(operations)?;
+
+        let mut conn = self.pool.get().await?;
+        let tx = conn.client.transaction().await?;
+        let project = project_id.to_string();
+        if tx
+            .query_opt("SELECT 1 FROM projects WHERE id = $1", &[&project])
+            .await?
+            .is_none()
+        {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let mut response = BatchWriteResponse::default();
+        for operation in operations {
+            let key = validate_config_key(operation.key())?;
+            // FOR UPDATE holds the row so the version check stays valid until commit.
+            let current = tx
+                .query_opt(
+                    "SELECT version FROM configs WHERE project_id = $1 AND key = $2 FOR UPDATE",
+                    &[&project, &key],
+                )
+                .await?
+                .map(|row| row.try_get::<_, i64>(0))
+                .transpose()?;
+            check_expected_version(key, current, operation.expected_version())?;
+
+            match operation {
+                ConfigOperation::Set { value, .. } => {
+                    let row = tx
+                        .query_one(
+                            &format!(
+                                r"
+                                INSERT INTO configs (id, project_id, key, value, version, updated_at)
+                                VALUES ($1, $2, $3, $4, 1, {NOW_TEXT})
+                                ON CONFLICT (project_id, key) DO UPDATE SET
+                                    value = excluded.value,
+                                    version = configs.version + 1,
+                                    updated_at = {NOW_TEXT}
+                                RETURNING id, project_id, key, value, version, updated_at
+                                "
+                            ),
+                            &[&Uuid::new_v4().to_string(), &project, &key, value],
+                        )
+                        .await?;
+                    let item = config_from_row(&row)?;
+                    // A key that did not exist has no row to lock, so a
+                    // concurrent insert can only be noticed here.
+                    check_expected_version(
+                        key,
+                        Some(item.version - 1).filter(|version| *version > 0),
+                        operation.expected_version(),
+                    )?;
+                    response.configs.push(item);
+                }
+                ConfigOperation::Delete { .. } => {
+                    if current.is_none() {
+                        return Err(config_not_found(key));
+                    }
+                    tx.execute(
+                        "DELETE FROM configs WHERE project_id = $1 AND key = $2",
+                        &[&project, &key],
+                    )
+                    .await?;
+                    response.deleted.push(key.to_owned());
+                }
+            }
+        }
+
+        tx.commit().await?;
+        Ok(response)
+    }
+
+    async fn restore_backup(
+        &self,
+        backup: &Backup,
+        mode: RestoreMode,
+    ) -> AppResult<RestoreSummary> {
+        let mut conn = self.pool.get().await?;
+        let tx = conn.client.transaction().await?;
+        let archived: HashSet<String> = backup.projects.iter().map(|p| p.id.to_string()).collect();
+        if mode == RestoreMode::Replace {
+            clear_for_replace(&tx, &archived).await?;
+        }
+
+        let mut summary = RestoreSummary {
+            clients: restore_clients(&tx, &backup.clients).await?,
+            ..RestoreSummary::default()
+        };
+        for project in &backup.projects {
+            summary.projects += tx
+                .execute(
+                    r"
+                    INSERT INTO projects (id, name, description, created_at, revision)
+                    VALUES ($1, $2, $3, $4, $5)
+                    ON CONFLICT(id) DO UPDATE SET
+                        name = excluded.name,
+                        description = excluded.description,
+                        revision = GREATEST(projects.revision, excluded.revision)
+                    ",
+                    &[
+                        &project.id.to_string(),
+                        &project.name,
+                        &project.description,
+                        &project.created_at,
+                        &project.revision,
+                    ],
+                )
+                .await?;
+        }
+        for permission in &backup.permissions {
+            summary.permissions += tx
+                .execute(
+                    r"
+                    INSERT INTO client_permissions (client_id, project_id, can_read, can_write)
+                    VALUES ($1, $2, $3, $4)
+                    ON CONFLICT(client_id, project_id) DO UPDATE SET
+                        can_read = excluded.can_read,
+                        can_write = excluded.can_write
+                    ",
+                    &[
+                        &permission.client_id.to_string(),
+                        &permission.project_id.to_string(),
+                        &permission.can_read,
+                        &permission.can_write,
+                    ],
+                )
+                .await?;
+        }
+        for item in &backup.configs {
+            summary.configs += tx
+                .execute(
+                    r"
+                    INSERT INTO configs (id, project_id, key, value, version, updated_at)
+                    VALUES ($1, $2, $3, $4, $5, $6)
+                    ON CONFLICT(project_id, key) DO UPDATE SET
+                        value = excluded.value,
+                        version = excluded.version,
+                        updated_at = excluded.updated_at
+                    WHERE excluded.version >= configs.version
+                    ",
+                    &[
+                        &item.id.to_string(),
+                        &item.project_id.to_string(),
+                        &item.key,
+                        &item.value,
+                        &item.version,
+                        &item.updated_at,
+                    ],
+                )
+                .await?;
+        }
+
+        // A fresh revision moves ETags on even when no row changed, and the
+        // log does not describe how the restored state was reached.
+        for id in &archived {
+            tx.execute(
+                "UPDATE projects SET revision = revision + 1, history_start = revision + 1 WHERE id = $1",
+                &[id],
+            )
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    async fn list_configs_for_project(&self, project_id: &Uuid) -> AppResult<Vec<ConfigItem>> {
+        if self.get_project_by_id(project_id).await?.is_none() {
+            return Err(AppError::NotFound(String::from("project not found")));
+        }
+
+        let conn = self.pool.get().await?;
+        let rows = conn
+            .query(
+                r"
+                SELECT id, project_id, key, value, version, updated_at
+                FROM configs
+                WHERE project_id = $1
+                ORDER BY key ASC
+                ",
+                &[&project_id.to_string()],
+            )
+            .await?;
+
+        rows.iter().map(config_from_row).collect()
+    }
+
+    async fn get_config_by_key(
+        &self,
+        project_id: &Uuid,
+        key: &str,
+    ) -> AppResult<Option<ConfigItem>> {
+        let conn = self.pool.get().await?;
+        let row = conn
+            .query_opt(
+                r"
+                SELECT id, project_id, key, value, version, updated_at
+                FROM configs
+                WHERE project_id = $1 AND key = $2
+                ",
+                &[&project_id.to_string(), &key],
+            )
+            .await?;
+
+        row.as_ref().map(config_from_row).transpose()
+    }
+
+    async fn read_configs(
+        &self,
+        client_id: &Uuid,
+        project_id: &Uuid,
+        key: Option<&str>,
+    ) -> AppResult<Option<ProjectConfigs>> {
+        let mut conn = self.pool.get().await?;
+        let (client_id_text, project_id_text) = (client_id.to_string(), project_id.to_string());
+        let rows = if let Some(key) = key {
+            let statement = conn.prepared(READ_CONFIG_SQL).await?;
+            conn.query(&statement, &[&client_id_text, &project_id_text, &key])
+                .await?
+        } else {
+            let statement = conn.prepared(READ_CONFIGS_SQL).await?;
+            conn.query(&statement, &[&client_id_text, &project_id_text])
+                .await?
+        };
+
+        let Some(first) = rows.first() else {
+            return Ok(None);
+        };
+
+        let permission = ClientPermission {
+            client_id: *client_id,
+            project_id: *project_id,
+            can_read: first.try_get(0)?,
+            can_write: first.try_get(1)?,
+        };
+        // The LEFT JOIN yields one all-NULL config row when there are none.
+        let mut configs = Vec::with_capacity(rows.len());
+        for row in &rows {
+            if row.try_get::<_, Option<&str>>(3)?.is_some() {
+                configs.push(config_from_row_at(row, 3)?);
+            }
+        }
+
+        Ok(Some(ProjectConfigs {
+            permission,
+            revision: first.try_get(2)?,
+            configs,
+        }))
+    }
+}
+
+/// Upserts archived clients, returning how many rows were written.
+async fn restore_clients(
+    tx: &tokio_postgres::Transaction<'_>,
+    clients: &[Client],
+) -> AppResult<u64> {
+    let mut restored = 0;
+    for client in clients {
+        restored += tx
+            .execute(
+                r"
+                INSERT INTO clients (id, name, public_key, is_admin, created_at, key_algorithm, allowed_networks)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                ON CONFLICT(id) DO UPDATE SET
+                    name = excluded.name,
+                    public_key = excluded.public_key,
+                    is_admin = excluded.is_admin,
+                    key_algorithm = excluded.key_algorithm,
+                    allowed_networks = excluded.allowed_networks
+                ",
+                &[
+                    &client.id.to_string(),
+                    &client.name,
+                    &client.public_key,
+                    &client.is_admin,
+                    &client.created_at,
+                    &client.key_algorithm.as_str(),
+                    &join_client_networks(&client.allowed_networks),
+                ],
+            )
+            .await?;
+    }
+
+    Ok(restored)
+}
+
+/// Empties the database ahead of a replace restore. Archived projects are
+/// kept so their revision never goes backwards.
+async fn clear_for_replace(
+    tx: &tokio_postgres::Transaction<'_>,
+    archived: &HashSet<String>,
+) -> AppResult<()> {
+    tx.batch_execute(
+        "DELETE FROM client_permissions; DELETE FROM configs; DELETE FROM used_nonces; DELETE FROM clients;",
+    )
+    .await?;
+
+    for row in tx.query("SELECT id FROM projects", &[]).await? {
+        let id: String = row.try_get(0)?;
+        if !archived.contains(&id) {
+            tx.execute("DELETE FROM projects WHERE id = $1", &[&id])
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+fn is_unique_violation(error: &tokio_postgres::Error) -> bool {
+    error.code() == Some(&SqlState::UNIQUE_VIOLATION)
+}
+
+fn client_from_row(row: &Row) -> AppResult<Client> {
+    Ok(Client {
+        id: parse_uuid(row.try_get(0)?)?,
+        name: row.try_get(1)?,
+        public_key: row.try_get(2)?,
+        is_admin: row.try_get(3)?,
+        created_at: row.try_get(4)?,
+        key_algorithm: parse_key_algorithm(row.try_get(5)?)?,
+        allowed_networks: parse_client_networks(row.try_get(6)?),
+    })
+}
+
+const SESSION_COLUMNS: &str = "id, client_id, scopes, created_at, expires_at, revoked_at";
+
+fn session_from_row(row: &Row) -> AppResult<Session> {
+    Ok(Session {
+        id: parse_uuid(row.try_get(0)?)?,
+        client_id: parse_uuid(row.try_get(1)?)?,
+        scopes: parse_session_scopes(row.try_get(2)?)?,
+        created_at: row.try_get(3)?,
+        expires_at: row.try_get(4)?,
+        revoked_at: row.try_get(5)?,
+    })
+}
+
+const PENDING_ACTION_COLUMNS: &str = "id, operation, requested_by, required_approvals, status, created_at, expires_at, resolved_at, resolved_by";
+
+/// Leaves `approvals` empty; see [`load_approvals`].
+fn pending_action_from_row(row: &Row) -> AppResult<PendingAction> {
+    let operation = parse_admin_operation(row.try_get(1)?)?;
+    Ok(PendingAction {
+        id: parse_uuid(row.try_get(0)?)?,
+        action: operation.action(),
+        operation,
+        requested_by: parse_uuid(row.try_get(2)?)?,
+        approvals: Vec::new(),
+        required_approvals: parse_required_approvals(row.try_get(3)?)?,
+        status: parse_pending_status(row.try_get(4)?)?,
+        created_at: row.try_get(5)?,
+        expires_at: row.try_get(6)?,
+        resolved_at: row.try_get(7)?,
+        resolved_by: row
+            .try_get::<_, Option<&str>>(8)?
+            .map(parse_uuid)
+            .transpose()?,
+    })
+}
+
+/// `(action_id, client_id)` approvals of one action, or of all of them.
+async fn load_approvals(
+    client: &tokio_postgres::Client,
+    action_id: Option<&str>,
+) -> AppResult<Vec<(Uuid, Uuid)>> {
+    let rows = client
+        .query(
+            r"
+            SELECT action_id, client_id FROM pending_action_approvals
+            WHERE $1::TEXT IS NULL OR action_id = $1
+            ORDER BY approved_at ASC, client_id ASC
+            ",
+            &[&action_id],
+        )
+        .await?;
+
+    rows.iter()
+        .map(|row| Ok((parse_uuid(row.try_get(0)?)?, parse_uuid(row.try_get(1)?)?)))
+        .collect()
+}
+
+fn project_from_row(row: &Row) -> AppResult<Project> {
+    Ok(Project {
+        id: parse_uuid(row.try_get(0)?)?,
+        name: row.try_get(1)?,
+        description: row.try_get(2)?,
+        created_at: row.try_get(3)?,
+        revision: row.try_get(4)?,
+    })
+}
+
+fn config_from_row(row: &Row) -> AppResult<ConfigItem> {
+    config_from_row_at(row, 0)
+}
+
+/// Reads a config whose columns start at `offset`, for joined queries.
+fn config_from_row_at(row: &Row, offset: usize) -> AppResult<ConfigItem> {
+    Ok(ConfigItem {
+        id: parse_uuid(row.try_get(offset)?)?,
+        project_id: parse_uuid(row.try_get(offset + 1)?)?,
+        key: row.try_get(offset + 2)?,
+        value: row.try_get(offset + 3)?,
+        version: row.try_get(offset + 4)?,
+        updated_at: row.try_get(offset + 5)?,
+    })
+}
+
+const CERTIFICATE_COLUMNS: &str =
+    "serial, client_id, fingerprint, issued_at, expires_at, revoked_at";
+
+fn certificate_from_row(row: &Row) -> AppResult<ClientCertificate> {
+    Ok(ClientCertificate {
+        serial: row.try_get(0)?,
+        client_id: parse_uuid(row.try_get(1)?)?,
+        fingerprint: row.try_get(2)?,
+        issued_at: row.try_get(3)?,
+        expires_at: row.try_get(4)?,
+        revoked_at: row.try_get(5)?,
+    })
+}
+
+fn permission_from_row(row: &Row) -> AppResult<ClientPermission> {
+    Ok(ClientPermission {
+        client_id: parse_uuid(row.try_get(0)?)?,
+        project_id: parse_uuid(row.try_get(1)?)?,
+        can_read: row.try_get(2)?,
+        can_write: row.try_get(3)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_servers_need_tls() {
+        let connector = |url: &str| PgConnector::new(url, None).unwrap();
+
+        let remote = connector("postgres://app@db.example.com/app");
+        assert!(remote.tls.is_some());
+        assert_eq!(remote.config.get_ssl_mode(), SslMode::Require);
+
+        let local = connector("postgres://app@localhost/app");
+        assert!(local.tls.is_none());
+        let local = connector("postgres://app@127.0.0.1/app?sslmode=require");
+        assert!(local.tls.is_some());
+        assert_eq!(local.config.get_ssl_mode(), SslMode::Require);
+
+        // An explicit opt-out is honoured, with a warning.
+        assert!(
+            connector("postgres://app@db.example.com/app?sslmode=disable")
+                .tls
+                .is_none()
+        );
+        assert!(
+            PgConnector::new("postgres://app@db.example.com/app?sslmode=verify", None).is_err()
+        );
+    }
+}
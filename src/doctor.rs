@@ -0,0 +1,433 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::{
+    auth,
+    cli::DoctorArgs,
+    config::AppConfig,
+    crypto::{self, SignatureVersion},
+    db::Database,
+    error::{AppError, AppResult},
+    models::EffectivePermissions,
+    tls::Endpoint,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    Fail,
+    Skip,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+impl CheckResult {
+    fn pass(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Pass,
+            detail: detail.into(),
+        }
+    }
+
+    fn fail(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Fail,
+            detail: detail.into(),
+        }
+    }
+
+    fn skip(name: &'static str, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status: CheckStatus::Skip,
+            detail: detail.into(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub async fn run(args: &DoctorArgs) -> Vec<CheckResult> {
+    let mut results = Vec::new();
+
+    let config = match AppConfig::from_env() {
+        Ok(config) => {
+            results.push(CheckResult::pass(
+                "config",
+                format!(
+                    "listen_addr={}, database={}",
+                    config.listen_addr,
+                    database_kind(&config)
+                ),
+            ));
+            Some(config)
+        }
+        Err(error) => {
+            results.push(CheckResult::fail("config", error.to_string()));
+            None
+        }
+    };
+
+    results.extend(check_database(config.as_ref()).await);
+
+    let server = args.server.clone().or_else(|| {
+        config
+            .as_ref()
+            .map(|config| crate::status_connect_addr(&config.listen_addr))
+    });
+    let Some(server) = server else {
+        results.push(CheckResult::skip(
+            "server",
+            "no --server given and config is invalid",
+        ));
+        return results;
+    };
+    let server = match Endpoint::for_config(server, config.as_ref()) {
+        Ok(server) => server,
+        Err(error) => {
+            results.push(CheckResult::fail("tls", error.to_string()));
+            return results;
+        }
+    };
+
+    let max_drift = config
+        .as_ref()
+        .map_or(300, |config| config.max_clock_drift_seconds);
+    let server_up = check_server(&server, max_drift, &mut results).await;
+
+    match (&args.client_id, &args.key) {
+        (Some(client_id), Some(key)) if server_up => {
+            results.extend(check_client(&server, *client_id, key).await);
+        }
+        (Some(_), Some(_)) => {
+            results.push(CheckResult::skip("client", "server is not reachable"));
+        }
+        _ => results.push(CheckResult::skip(
+            "client",
+            "pass --client-id and --key to verify a client key",
+        )),
+    }
+
+    results
+}
+
+pub fn print_human(results: &[CheckResult]) {
+    for result in results {
+        let label = match result.status {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Fail => "FAIL",
+            CheckStatus::Skip => "skip",
+        };
+        println!("[{label}] {}: {}", result.name, result.detail);
+    }
+}
+
+async fn check_database(config: Option<&AppConfig>) -> Vec<CheckResult> {
+    let Some(config) = config else {
+        return vec![
+            CheckResult::skip("database", "config is invalid"),
+            CheckResult::skip("schema", "config is invalid"),
+        ];
+    };
+
+    let db = match Database::connect(config).await {
+        Ok(db) => db,
+        Err(error) => {
+            return vec![
+                CheckResult::fail("database", error.to_string()),
+                CheckResult::skip("schema", "database is not reachable"),
+            ];
+        }
+    };
+
+    if let Err(error) = db.ping().await {
+        return vec![
+            CheckResult::fail("database", error.to_string()),
+            CheckResult::skip("schema", "database is not reachable"),
+        ];
+    }
+
+    let schema = match db.schema_status().await {
+        Ok(status) if status.is_newer_than_binary() => CheckResult::fail(
+            "schema",
+            format!(
+                "database is at version {} but this binary only knows {}; upgrade cloudconfig",
+                status.current_version, status.latest_version
+            ),
+        ),
+        Ok(status) => match status.pending().count() {
+            0 => CheckResult::pass(
+                "schema",
+                format!("version {} (up to date)", status.current_version),
+            ),
+            pending => CheckResult::fail(
+                "schema",
+                format!(
+                    "version {} with {pending} pending migration(s); run `cloudconfig migrate up`",
+                    status.current_version
+                ),
+            ),
+        },
+        Err(error) => CheckResult::fail("schema", error.to_string()),
+    };
+
+    vec![CheckResult::pass("database", "connected"), schema]
+}
+
+async fn check_server(server: &Endpoint, max_drift: i64, results: &mut Vec<CheckResult>) -> bool {
+    let sent_at = unix_now_f64();
+    let response = match http_request(server, "GET", "/health", &[], &[]).await {
+        Ok(response) => response,
+        Err(error) => {
+            results.push(CheckResult::fail(
+                "server",
+                format!("{server}/health unreachable: {error}"),
+            ));
+            results.push(CheckResult::skip("clock", "server is not reachable"));
+            return false;
+        }
+    };
+    let received_at = unix_now_f64();
+
+    if response.status == 200 {
+        results.push(CheckResult::pass(
+            "server",
+            format!("{server}/health returned 200"),
+        ));
+    } else {
+        results.push(CheckResult::fail(
+            "server",
+            format!("{server}/health returned {}", response.status),
+        ));
+    }
+
+    let server_time = response
+        .header("date")
+        .and_then(|value| httpdate::parse_http_date(value).ok())
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok());
+    match server_time {
+        Some(server_time) => {
+            // `Date` has one-second resolution; assume the middle of that second.
+            #[allow(clippy::cast_possible_truncation)]
+            let skew = (server_time.as_secs_f64() + 0.5 - f64::midpoint(sent_at, received_at))
+                .round() as i64;
+            let detail = format!("server clock differs by {skew}s (allowed ±{max_drift}s)");
+            if skew.abs() > max_drift {
+                results.push(CheckResult::fail("clock", detail));
+            } else {
+                results.push(CheckResult::pass("clock", detail));
+            }
+        }
+        None => results.push(CheckResult::skip(
+            "clock",
+            "server response had no parsable Date header",
+        )),
+    }
+
+    true
+}
+
+async fn check_client(
+    server: &Endpoint,
+    client_id: uuid::Uuid,
+    key: &std::path::Path,
+) -> Vec<CheckResult> {
+    let response = match signed_get(server, client_id, key, "/api/permissions").await {
+        Ok(response) => response,
+        Err(error) => {
+            return vec![
+                CheckResult::fail("client", error.to_string()),
+                CheckResult::skip("permissions", "client request failed"),
+            ];
+        }
+    };
+
+    if response.status != 200 {
+        let body = String::from_utf8_lossy(&response.body);
+        return vec![
+            CheckResult::fail(
+                "client",
+                format!("signed request rejected with {}: {body}", response.status),
+            ),
+            CheckResult::skip("permissions", "client request was rejected"),
+        ];
+    }
+
+    let client_check = CheckResult::pass("client", format!("{client_id} signature accepted"));
+    let permissions = match serde_json::from_slice::<EffectivePermissions>(&response.body) {
+        Ok(effective) => CheckResult::pass("permissions", describe_permissions(&effective)),
+        Err(error) => CheckResult::fail("permissions", format!("unexpected response: {error}")),
+    };
+
+    vec![client_check, permissions]
+}
+
+async fn signed_get(
+    server: &Endpoint,
+    client_id: uuid::Uuid,
+    key: &std::path::Path,
+    path: &str,
+) -> AppResult<HttpResponse> {
+    let private_key_pem = std::fs::read_to_string(key)
+        .map_err(|e| AppError::BadRequest(format!("failed to read {}: {e}", key.display())))?;
+    let timestamp = auth::current_unix_timestamp()?;
+    let nonce = uuid::Uuid::new_v4().to_string();
+    let key_id = crypto::key_id(&crypto::public_key_from_pem(&private_key_pem)?)?;
+    let canonical = crypto::CanonicalRequestV2 {
+        timestamp,
+        method: "GET",
+        host: &server.addr,
+        path_and_query: path,
+        content_type: "",
+        nonce: &nonce,
+        key_id: &key_id,
+        signed_headers: &[],
+        body: &[],
+    }
+    .canonical_string();
+    let signature = crypto::sign_canonical(&private_key_pem, &canonical)?;
+
+    let headers = [
+        ("X-Client-Id", client_id.to_string()),
+        ("X-Timestamp", timestamp.to_string()),
+        ("X-Nonce", nonce),
+        ("X-Signature-Version", SignatureVersion::V2.to_string()),
+        ("X-Key-Id", key_id),
+        ("X-Signature", signature),
+    ];
+    http_request(server, "GET", path, &headers, &[])
+        .await
+        .map_err(|e| AppError::Internal(format!("request to {server} failed: {e}")))
+}
+
+fn describe_permissions(effective: &EffectivePermissions) -> String {
+    let mut parts = vec![format!(
+        "admin={}",
+        if effective.is_admin { "yes" } else { "no" }
+    )];
+    if effective.permissions.is_empty() {
+        parts.push(String::from("no project grants"));
+    }
+    for permission in &effective.permissions {
+        let access = match (permission.can_read, permission.can_write) {
+            (_, true) => "read/write",
+            (true, false) => "read",
+            (false, false) => "none",
+        };
+        parts.push(format!("{}={access}", permission.project_id));
+    }
+    parts.join(", ")
+}
+
+fn database_kind(config: &AppConfig) -> &'static str {
+    let url = config.database_url.as_str();
+    if url.starts_with("postgres://") || url.starts_with("postgresql://") {
+        "postgres"
+    } else if url.starts_with("memory://") {
+        "in-process memory"
+    } else if config.turso_replica_path.is_some() {
+        "embedded replica"
+    } else if config.is_remote_database() {
+        "remote"
+    } else if url == ":memory:" {
+        "in-memory"
+    } else {
+        "local file"
+    }
+}
+
+fn unix_now_f64() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |elapsed| elapsed.as_secs_f64())
+}
+
+async fn http_request(
+    server: &Endpoint,
+    method: &str,
+    path: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> Result<HttpResponse, std::io::Error> {
+    let mut stream = server.connect().await?;
+    let mut request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Length: {}\r\n",
+        server.addr,
+        body.len()
+    );
+    for (name, value) in headers {
+        request.push_str(name);
+        request.push_str(": ");
+        request.push_str(value);
+        request.push_str("\r\n");
+    }
+    request.push_str("\r\n");
+
+    stream.write_all(request.as_bytes()).await?;
+    stream.write_all(body).await?;
+    stream.flush().await?;
+
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await?;
+    parse_response(&raw)
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidData, "malformed response"))
+}
+
+fn parse_response(raw: &[u8]) -> Option<HttpResponse> {
+    let split = raw.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..split]).ok()?;
+    let mut lines = head.split("\r\n");
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_owned(), value.trim().to_owned()))
+        .collect();
+
+    Some(HttpResponse {
+        status,
+        headers,
+        body: raw[split + 4..].to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_response;
+
+    #[test]
+    fn parses_status_headers_and_body() {
+        let raw = b"HTTP/1.1 401 Unauthorized\r\ndate: Sun, 18 Oct 2026 12:00:00 GMT\r\ncontent-length: 2\r\n\r\n{}";
+        let response = parse_response(raw).unwrap();
+
+        assert_eq!(response.status, 401);
+        assert_eq!(
+            response.header("Date"),
+            Some("Sun, 18 Oct 2026 12:00:00 GMT")
+        );
+        assert_eq!(response.body, b"{}");
+    }
+}
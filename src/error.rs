@@ -56,6 +56,17 @@ impl From<libsql::Error> for AppError {
     }
 }
 
+impl From<tokio_postgres::Error> for AppError {
+    /// The cause, e.g. the server's message or a TLS certificate problem, is
+    /// only in the source; the error itself just says "db error".
+    fn from(value: tokio_postgres::Error) -> Self {
+        match std::error::Error::source(&value) {
+            Some(source) => Self::Database(format!("{value}: {source}")),
+            None => Self::Database(value.to_string()),
+        }
+    }
+}
+
 impl From<std::time::SystemTimeError> for AppError {
     fn from(value: std::time::SystemTimeError) -> Self {
         Self::Internal(value.to_string())
@@ -68,6 +79,12 @@ impl From<rcgen::Error> for AppError {
     }
 }
 
+impl From<rustls::Error> for AppError {
+    fn from(value: rustls::Error) -> Self {
+        Self::Crypto(value.to_string())
+    }
+}
+
 impl From<ring::error::Unspecified> for AppError {
     fn from(_: ring::error::Unspecified) -> Self {
         Self::Crypto(String::from("cryptographic operation failed"))
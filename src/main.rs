@@ -1,36 +1,74 @@
+mod approvals;
 mod auth;
+mod backup;
+mod ca;
 mod cli;
+mod codegen;
 mod config;
 mod crypto;
 mod db;
+mod doctor;
 mod error;
+mod http_signature;
+mod keys;
+mod migrations;
 mod models;
+mod network;
+mod nonce;
+mod otp;
 mod routes;
+mod server_key;
+mod session;
 mod static_files;
+mod tls;
 
 use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use axum::http::{HeaderName, Method, header};
-use axum::{Router, middleware, routing::get};
+use axum::http::{HeaderMap, HeaderName, Method, StatusCode, header};
+use axum::{
+    Json, Router,
+    extract::State,
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+};
 use clap::Parser;
+use serde_json::json;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::{TcpListener, TcpStream};
+use tokio::net::TcpListener;
 use tower_http::{
     cors::{Any, CorsLayer},
     trace::TraceLayer,
 };
 
 use crate::{
-    cli::{Cli, Command},
+    ca::CertificateAuthority,
+    cli::{
+        BackupArgs, Cli, CodegenArgs, Command, DoctorArgs, KeygenArgs, MigrateAction, ResetArgs,
+        RestoreArgs, SignArgs,
+    },
     config::AppConfig,
+    crypto::SignatureVersion,
     db::Database,
     error::{AppError, AppResult},
+    models::ConfigItem,
+    nonce::Nonces,
+    server_key::ServerKey,
 };
 
+const CHANGE_PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub db: Database,
+    pub nonces: Nonces,
     pub config: AppConfig,
+    /// Issues mTLS client certificates; `None` unless `MTLS_CA_*` is set.
+    pub ca: Option<Arc<CertificateAuthority>>,
+    /// Signs `/api` responses; `None` unless `SERVER_KEY_PATH` is set.
+    pub server_key: Option<Arc<ServerKey>>,
 }
 
 #[tokio::main]
@@ -47,8 +85,15 @@ async fn run() -> AppResult<()> {
     let cli = Cli::parse();
     match cli.command {
         Some(Command::Init) => run_init().await,
-        Some(Command::Reset) => run_reset().await,
+        Some(Command::Reset(args)) => run_reset(&args).await,
         Some(Command::Status) => run_status().await,
+        Some(Command::Codegen(args)) => run_codegen(args).await,
+        Some(Command::Keygen(args)) => run_keygen(&args),
+        Some(Command::Sign(args)) => run_sign(&args),
+        Some(Command::Doctor(args)) => run_doctor(&args).await,
+        Some(Command::Backup(args)) => run_backup(&args).await,
+        Some(Command::Restore(args)) => run_restore(&args).await,
+        Some(Command::Migrate { action }) => run_migrate(action).await,
         Some(Command::Start) | None => run_start().await,
     }
 }
@@ -64,6 +109,11 @@ async fn run_init() -> AppResult<()> {
             println!("Client ID: {}", bootstrap.client.id);
             println!("Private key (store this safely, shown once):");
             println!("{}", bootstrap.private_key_pem);
+            let recovery_codes = otp::issue_recovery_codes(&db, &bootstrap.client.id).await?;
+            println!("Recovery codes, each usable once in place of a one-time code (shown once):");
+            for code in recovery_codes {
+                println!("  {code}");
+            }
         }
         None => {
             if let Some(admin) = db.get_admin_client().await? {
@@ -96,8 +146,42 @@ async fn run_start() -> AppResult<()> {
         );
     }
 
-    let state = AppState { db, config };
-    let app = build_router(state.clone());
+    spawn_database_tasks(&config, &db);
+    let nonces = Nonces::from_config(&config, &db);
+    nonces.spawn_cleanup();
+
+    let ca = match (&config.mtls_ca_cert_path, &config.mtls_ca_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let (ca, created) = CertificateAuthority::load_or_create(cert_path, key_path)?;
+            if created {
+                println!("Created the client certificate authority at {cert_path}");
+            }
+            tracing::info!("mTLS client CA SHA-256 fingerprint {}", ca.fingerprint());
+            Some(Arc::new(ca))
+        }
+        _ => None,
+    };
+
+    let server_key = match &config.server_key_path {
+        Some(path) => {
+            let (key, created) = ServerKey::load_or_create(path)?;
+            if created {
+                println!("Generated the response signing key at {path}");
+            }
+            tracing::info!("response signing public key {}", key.public_key());
+            Some(Arc::new(key))
+        }
+        None => None,
+    };
+
+    let state = AppState {
+        db,
+        nonces,
+        config,
+        ca,
+        server_key,
+    };
+    let app = build_router(state.clone()).into_make_service_with_connect_info::<tls::PeerInfo>();
 
     let listener = TcpListener::bind(&state.config.listen_addr)
         .await
@@ -105,25 +189,126 @@ async fn run_start() -> AppResult<()> {
             AppError::Internal(format!("failed to bind {}: {e}", state.config.listen_addr))
         })?;
 
+    let (Some(cert_path), Some(key_path)) =
+        (&state.config.tls_cert_path, &state.config.tls_key_path)
+    else {
+        tracing::info!(
+            "CloudConfig server listening on http://{}",
+            state.config.listen_addr
+        );
+        return axum::serve(listener, app)
+            .with_graceful_shutdown(shutdown_signal())
+            .await
+            .map_err(|e| AppError::Internal(e.to_string()));
+    };
+
+    if let Some(fingerprint) = tls::ensure_self_signed(&state.config)? {
+        println!("Generated a self-signed TLS certificate at {cert_path}");
+        println!("SHA-256 fingerprint (pin this in clients): {fingerprint}");
+    }
+    let cert = Arc::new(tls::ReloadingCert::load(
+        cert_path.as_ref(),
+        key_path.as_ref(),
+    )?);
+    tracing::info!("TLS certificate SHA-256 fingerprint {}", cert.fingerprint());
+    if state.config.tls_reload_interval_seconds > 0 {
+        Arc::clone(&cert).spawn_reload(Duration::from_secs(
+            state.config.tls_reload_interval_seconds,
+        ));
+    }
+
     tracing::info!(
-        "CloudConfig server listening on {}",
+        "CloudConfig server listening on https://{}",
         state.config.listen_addr
     );
-
-    axum::serve(listener, app)
+    let client_ca = state.ca.as_ref().map(|ca| ca.root());
+    axum::serve(tls::TlsListener::new(listener, cert, client_ca)?, app)
         .with_graceful_shutdown(shutdown_signal())
         .await
-        .map_err(|e| AppError::Internal(e.to_string()))?;
+        .map_err(|e| AppError::Internal(e.to_string()))
+}
 
-    Ok(())
+/// Replica sync, cache change checks and change-log pruning, as configured.
+fn spawn_database_tasks(config: &AppConfig, db: &Database) {
+    if db.replica_status().is_some() && config.turso_sync_interval_seconds > 0 {
+        spawn_replica_sync(
+            db.clone(),
+            Duration::from_secs(config.turso_sync_interval_seconds),
+        );
+    }
+
+    if db.cache().is_some() && config.cache_sync_interval_seconds > 0 {
+        spawn_cache_sync(
+            db.clone(),
+            Duration::from_secs(config.cache_sync_interval_seconds),
+        );
+    }
+
+    if config.change_history_limit > 0 {
+        spawn_change_pruning(db.clone(), config.change_history_limit);
+    }
 }
 
-async fn run_reset() -> AppResult<()> {
+fn spawn_replica_sync(db: Database, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if let Err(error) = db.sync().await {
+                tracing::warn!("replica sync failed: {error}");
+            }
+        }
+    });
+}
+
+/// Drops cached reads whenever another instance changes the database.
+fn spawn_cache_sync(db: Database, interval: Duration) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let Some(cache) = db.cache() else {
+                return;
+            };
+            if let Err(error) = cache.check_for_changes().await {
+                tracing::warn!("cache change check failed: {error}");
+            }
+        }
+    });
+}
+
+/// Trims every project's change log to its last `keep` revisions, at startup
+/// and then hourly.
+fn spawn_change_pruning(db: Database, keep: u64) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CHANGE_PRUNE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            match db.prune_config_changes(keep).await {
+                Ok(0) => {}
+                Ok(pruned) => tracing::debug!("pruned {pruned} old config changes"),
+                Err(error) => tracing::warn!("change history pruning failed: {error}"),
+            }
+        }
+    });
+}
+
+async fn run_reset(args: &ResetArgs) -> AppResult<()> {
     let config = AppConfig::from_env()?;
     let db = Database::connect(&config).await?;
     db.migrate().await?;
 
+    if let Some(admin) = db.get_admin_client().await? {
+        otp::verify(&db, &admin.id, args.otp.as_deref()).await?;
+    }
+
     let bootstrap = db.reset_admin().await?;
+    // The new key works from anywhere until ranges are set again.
+    db.set_client_networks(&bootstrap.client.id, &[]).await?;
     println!("Admin credentials regenerated.");
     println!("Client ID: {}", bootstrap.client.id);
     println!("Private key (store this safely, shown once):");
@@ -134,28 +319,383 @@ async fn run_reset() -> AppResult<()> {
 
 async fn run_status() -> AppResult<()> {
     let config = AppConfig::from_env()?;
-    let connect_addr = status_connect_addr(&config.listen_addr);
+    let endpoint =
+        tls::Endpoint::for_config(status_connect_addr(&config.listen_addr), Some(&config))?;
 
-    match health_check(&connect_addr).await {
+    match health_check(&endpoint).await {
         Ok(true) => {
-            println!("status: running (healthy) at http://{connect_addr}/health");
+            println!("status: running (healthy) at {endpoint}/health");
             Ok(())
         }
         Ok(false) => {
-            println!("status: unhealthy response from http://{connect_addr}/health");
+            println!("status: unhealthy response from {endpoint}/health");
             Err(AppError::NotFound(format!(
-                "cloudconfig responded but /health was not 200 at http://{connect_addr}/health",
+                "cloudconfig responded but /health was not 200 at {endpoint}/health",
             )))
         }
         Err(error) => {
-            println!("status: not running at http://{connect_addr}/health");
+            println!("status: not running at {endpoint}/health");
             Err(AppError::NotFound(format!(
-                "cloudconfig is not reachable at http://{connect_addr}/health: {error}",
+                "cloudconfig is not reachable at {endpoint}/health: {error}",
             )))
         }
     }
 }
 
+async fn run_backup(args: &BackupArgs) -> AppResult<()> {
+    let encryption = backup::Encryption {
+        passphrase: args
+            .passphrase_file
+            .as_deref()
+            .map(read_passphrase)
+            .transpose()?,
+        recipients: args.recipient.clone(),
+        identities: None,
+    };
+
+    let config = AppConfig::from_env()?;
+    let db = Database::connect(&config).await?;
+    let archive = backup::create(&db).await?;
+    let bytes = backup::encode(&archive, &encryption)?;
+    crypto::write_secret_file(&args.out, &bytes)
+        .map_err(|e| AppError::Internal(format!("failed to write {}: {e}", args.out.display())))?;
+
+    let encrypted = encryption.passphrase.is_some() || !encryption.recipients.is_empty();
+    println!(
+        "Backed up {} client(s), {} project(s), {} permission(s) and {} config(s) to {}{}",
+        archive.clients.len(),
+        archive.projects.len(),
+        archive.permissions.len(),
+        archive.configs.len(),
+        args.out.display(),
+        if encrypted { " (encrypted)" } else { "" }
+    );
+    if !encrypted {
+        eprintln!(
+            "warning: the archive is not encrypted and holds every config value in plain text; \
+             pass --passphrase-file or --recipient to encrypt it"
+        );
+    }
+    Ok(())
+}
+
+async fn run_restore(args: &RestoreArgs) -> AppResult<()> {
+    let encryption = backup::Encryption {
+        passphrase: args
+            .passphrase_file
+            .as_deref()
+            .map(read_passphrase)
+            .transpose()?,
+        recipients: Vec::new(),
+        identities: args.identity.as_deref().map(read_file).transpose()?,
+    };
+    let bytes = std::fs::read(&args.input).map_err(|e| {
+        AppError::BadRequest(format!("failed to read {}: {e}", args.input.display()))
+    })?;
+    let archive = backup::decode(&bytes, &encryption)?;
+
+    let config = AppConfig::from_env()?;
+    let db = Database::connect(&config).await?;
+    db.migrate().await?;
+    let summary = backup::restore(&db, &archive, args.mode).await?;
+
+    println!(
+        "Restored {} client(s), {} project(s), {} permission(s) and {} config(s) from {}",
+        summary.clients,
+        summary.projects,
+        summary.permissions,
+        summary.configs,
+        args.input.display()
+    );
+    if summary.configs < archive.configs.len() as u64 {
+        println!(
+            "Kept {} existing config(s) with a newer version than the archive.",
+            archive.configs.len() as u64 - summary.configs
+        );
+    }
+    Ok(())
+}
+
+fn read_passphrase(path: &std::path::Path) -> AppResult<age::secrecy::SecretString> {
+    let passphrase = read_file(path)?;
+    let passphrase = passphrase.trim_end_matches(['\r', '\n']);
+    if passphrase.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "passphrase file {} is empty",
+            path.display()
+        )));
+    }
+
+    Ok(passphrase.into())
+}
+
+async fn run_migrate(action: MigrateAction) -> AppResult<()> {
+    let config = AppConfig::from_env()?;
+    let db = Database::connect(&config).await?;
+
+    if let MigrateAction::Up = action {
+        let applied = db.migrate().await?;
+        if applied.is_empty() {
+            println!("Schema is up to date.");
+        } else {
+            println!("Applied {} migration(s).", applied.len());
+        }
+    }
+
+    let status = db.schema_status().await?;
+    println!(
+        "Schema version: {} (this binary supports {})",
+        status.current_version, status.latest_version
+    );
+    for migration in &status.migrations {
+        match &migration.applied_at {
+            Some(applied_at) => println!(
+                "  [applied] {:04} {} at {applied_at}",
+                migration.version, migration.name
+            ),
+            None => println!("  [pending] {:04} {}", migration.version, migration.name),
+        }
+    }
+    if status.is_newer_than_binary() {
+        println!("Database is newer than this binary; upgrade cloudconfig before starting it.");
+    }
+
+    Ok(())
+}
+
+async fn run_codegen(args: CodegenArgs) -> AppResult<()> {
+    let (project_label, items) = if let Some(input) = &args.input {
+        let raw = read_file(input)?;
+        let items = serde_json::from_str::<Vec<ConfigItem>>(&raw).map_err(|e| {
+            AppError::BadRequest(format!("invalid config export {}: {e}", input.display()))
+        })?;
+        let label = args.project.clone().unwrap_or_else(|| {
+            items
+                .first()
+                .map_or_else(String::new, |item| item.project_id.to_string())
+        });
+        (label, items)
+    } else {
+        let project_ref = args.project.as_deref().unwrap_or_default();
+        let config = AppConfig::from_env()?;
+        let db = Database::connect(&config).await?;
+        db.migrate().await?;
+
+        let project = match uuid::Uuid::parse_str(project_ref) {
+            Ok(project_id) => db.get_project_by_id(&project_id).await?,
+            Err(_) => db.get_project_by_name(project_ref).await?,
+        }
+        .ok_or_else(|| AppError::NotFound(format!("project not found: {project_ref}")))?;
+        let items = db.list_configs_for_project(&project.id).await?;
+        (project.name, items)
+    };
+
+    let generated = codegen::generate_struct(&args.struct_name, &project_label, &items)?;
+    match &args.output {
+        Some(output) => {
+            write_file(output, &generated)?;
+            println!("Wrote {} ({} keys)", output.display(), items.len());
+        }
+        None => print!("{generated}"),
+    }
+
+    Ok(())
+}
+
+fn run_keygen(args: &KeygenArgs) -> AppResult<()> {
+    let generated = crypto::generate_keypair(args.algorithm)?;
+
+    let Some(out) = &args.out else {
+        print!("{}", generated.private_key_pem);
+        println!("Public key: {}", generated.public_key_b64);
+        return Ok(());
+    };
+
+    let public_path = format!("{}.pub", out.display());
+    crypto::write_secret_file(out, generated.private_key_pem.as_bytes())
+        .map_err(|e| AppError::Internal(format!("failed to write {}: {e}", out.display())))?;
+    write_file(
+        public_path.as_ref(),
+        &format!("{}\n", generated.public_key_b64),
+    )?;
+    println!("Private key written to {}", out.display());
+    println!("Public key written to {public_path}");
+    println!("Public key: {}", generated.public_key_b64);
+
+    Ok(())
+}
+
+fn run_sign(args: &SignArgs) -> AppResult<()> {
+    let private_key_pem = read_file(&args.key)?;
+    let body = match (&args.body, &args.body_file) {
+        (Some(body), _) => body.clone().into_bytes(),
+        (None, Some(path)) => std::fs::read(path)
+            .map_err(|e| AppError::BadRequest(format!("failed to read {}: {e}", path.display())))?,
+        (None, None) => Vec::new(),
+    };
+
+    let method = args.method.to_ascii_uppercase();
+    let path = if args.path.starts_with('/') {
+        args.path.clone()
+    } else {
+        format!("/{}", args.path)
+    };
+    let content_type = if body.is_empty() {
+        ""
+    } else {
+        "application/json"
+    };
+    let extra_headers = args
+        .headers
+        .iter()
+        .map(|raw| {
+            raw.split_once(':')
+                .map(|(name, value)| (name.trim().to_owned(), value.trim().to_owned()))
+                .filter(|(name, _)| !name.is_empty())
+                .ok_or_else(|| {
+                    AppError::BadRequest(format!("invalid --header {raw} (expected Name: value)"))
+                })
+        })
+        .collect::<AppResult<Vec<_>>>()?;
+    let timestamp = auth::current_unix_timestamp()?;
+    let nonce = uuid::Uuid::new_v4().to_string();
+
+    let mut headers = vec![
+        ("X-Client-Id", args.client_id.to_string()),
+        ("X-Timestamp", timestamp.to_string()),
+        ("X-Nonce", nonce.clone()),
+    ];
+    for (name, value) in &extra_headers {
+        headers.push((name.as_str(), value.clone()));
+    }
+    let host = args
+        .host
+        .as_deref()
+        .or_else(|| args.curl.as_deref().map(url_host));
+    let version = sign_version(args);
+    let canonical = match version {
+        SignatureVersion::V1 => crypto::canonical_string(timestamp, &method, &path, &nonce, &body),
+        SignatureVersion::V2 => {
+            let host = host.ok_or_else(|| {
+                AppError::BadRequest(String::from("signature version 2 needs --host or --curl"))
+            })?;
+            let key_id = crypto::key_id(&crypto::public_key_from_pem(&private_key_pem)?)?;
+            let canonical = crypto::CanonicalRequestV2 {
+                timestamp,
+                method: &method,
+                host,
+                path_and_query: &path,
+                content_type,
+                nonce: &nonce,
+                key_id: &key_id,
+                signed_headers: &extra_headers,
+                body: &body,
+            }
+            .canonical_string();
+            headers.push(("X-Signature-Version", version.to_string()));
+            headers.push(("X-Key-Id", key_id));
+            if !extra_headers.is_empty() {
+                let names = extra_headers.iter().map(|(name, _)| name.as_str());
+                headers.push(("X-Signed-Headers", names.collect::<Vec<_>>().join(",")));
+            }
+            canonical
+        }
+    };
+    headers.push((
+        "X-Signature",
+        crypto::sign_canonical(&private_key_pem, &canonical)?,
+    ));
+
+    let Some(base_url) = &args.curl else {
+        for (name, value) in &headers {
+            println!("{name}: {value}");
+        }
+        return Ok(());
+    };
+
+    let url = format!("{}{path}", base_url.trim_end_matches('/'));
+    let mut parts = vec![format!("curl -sS -X {method} {}", shell_quote(&url))];
+    for (name, value) in &headers {
+        parts.push(format!("-H {}", shell_quote(&format!("{name}: {value}"))));
+    }
+    if !body.is_empty() {
+        parts.push(format!("-H 'Content-Type: {content_type}'"));
+        // curl reads the file itself, so the bytes sent are the bytes signed
+        // even when they are not UTF-8.
+        let data = match &args.body_file {
+            Some(path) => shell_quote(&format!("@{}", path.display())),
+            None => shell_quote(args.body.as_deref().unwrap_or_default()),
+        };
+        parts.push(format!("--data-binary {data}"));
+    }
+    let command = parts.join(" \\\n  ");
+    println!("{command}");
+
+    Ok(())
+}
+
+/// Version 2 needs the host, so without `--host` or `--curl` the default
+/// drops to version 1.
+fn sign_version(args: &SignArgs) -> SignatureVersion {
+    args.signature_version
+        .unwrap_or(if args.host.is_some() || args.curl.is_some() {
+            SignatureVersion::V2
+        } else {
+            SignatureVersion::V1
+        })
+}
+
+/// The `host[:port]` curl sends as `Host` for a base URL.
+fn url_host(base_url: &str) -> &str {
+    let rest = base_url
+        .split_once("://")
+        .map_or(base_url, |(_, rest)| rest);
+    rest.split(['/', '?']).next().unwrap_or(rest)
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn read_file(path: &std::path::Path) -> AppResult<String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| AppError::BadRequest(format!("failed to read {}: {e}", path.display())))
+}
+
+fn write_file(path: &std::path::Path, contents: &str) -> AppResult<()> {
+    std::fs::write(path, contents)
+        .map_err(|e| AppError::Internal(format!("failed to write {}: {e}", path.display())))
+}
+
+async fn run_doctor(args: &DoctorArgs) -> AppResult<()> {
+    let results = doctor::run(args).await;
+
+    if args.json {
+        let ok = results
+            .iter()
+            .all(|result| result.status != doctor::CheckStatus::Fail);
+        let report = serde_json::json!({ "ok": ok, "checks": results });
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).map_err(|e| AppError::Internal(e.to_string()))?
+        );
+    } else {
+        doctor::print_human(&results);
+    }
+
+    let failed = results
+        .iter()
+        .filter(|result| result.status == doctor::CheckStatus::Fail)
+        .count();
+    if failed > 0 {
+        return Err(AppError::Internal(format!(
+            "{failed} doctor check(s) failed"
+        )));
+    }
+
+    Ok(())
+}
+
 fn status_connect_addr(listen_addr: &str) -> String {
     match listen_addr.parse::<SocketAddr>() {
         Ok(socket) if socket.ip().is_unspecified() => format!("127.0.0.1:{}", socket.port()),
@@ -164,10 +704,12 @@ fn status_connect_addr(listen_addr: &str) -> String {
     }
 }
 
-async fn health_check(connect_addr: &str) -> Result<bool, std::io::Error> {
-    let mut stream = TcpStream::connect(connect_addr).await?;
-    let request =
-        format!("GET /health HTTP/1.1\r\nHost: {connect_addr}\r\nConnection: close\r\n\r\n");
+async fn health_check(endpoint: &tls::Endpoint) -> Result<bool, std::io::Error> {
+    let mut stream = endpoint.connect().await?;
+    let request = format!(
+        "GET /health HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        endpoint.addr
+    );
     stream.write_all(request.as_bytes()).await?;
     stream.flush().await?;
 
@@ -180,29 +722,75 @@ async fn health_check(connect_addr: &str) -> Result<bool, std::io::Error> {
 fn build_router(state: AppState) -> Router {
     let admin_layer = middleware::from_fn_with_state(state.clone(), auth::require_client_signature);
     let user_layer = middleware::from_fn_with_state(state.clone(), auth::require_client_signature);
+    let signing_layer = middleware::from_fn_with_state(state.clone(), server_key::sign_response);
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods([Method::GET, Method::POST, Method::PUT, Method::DELETE])
         .allow_headers([
             header::CONTENT_TYPE,
+            header::AUTHORIZATION,
             HeaderName::from_static("x-client-id"),
             HeaderName::from_static("x-signature"),
             HeaderName::from_static("x-timestamp"),
             HeaderName::from_static("x-nonce"),
+            HeaderName::from_static("x-signature-version"),
+            HeaderName::from_static("x-key-id"),
+            HeaderName::from_static("x-signed-headers"),
+            HeaderName::from_static("signature-input"),
+            HeaderName::from_static("signature"),
+            HeaderName::from_static("content-digest"),
+            HeaderName::from_static("x-otp"),
+            header::IF_NONE_MATCH,
+        ])
+        .expose_headers([
+            header::ETAG,
+            header::LAST_MODIFIED,
+            HeaderName::from_static(routes::user::CONFIG_REVISION_HEADER),
+            HeaderName::from_static(server_key::SERVER_SIGNATURE_HEADER),
+            HeaderName::from_static(server_key::SERVER_TIMESTAMP_HEADER),
         ]);
 
     Router::new()
         .route("/health", get(health))
+        .route("/server-key", get(server_key::public_key))
+        .route("/crl", get(ca::crl))
+        .route("/session/challenge", post(session::challenge))
+        .route("/session", post(session::create).delete(session::logout))
         .nest("/admin", routes::admin::router().route_layer(admin_layer))
-        .nest("/api", routes::user::router().route_layer(user_layer))
+        .nest(
+            "/api",
+            routes::user::router()
+                .route_layer(user_layer)
+                .layer(signing_layer),
+        )
         .fallback(static_files::serve)
         .layer(TraceLayer::new_for_http())
         .layer(cors)
         .with_state(state)
 }
 
-async fn health() -> &'static str {
-    "ok"
+/// Plain `ok` for liveness probes. With `Accept: application/json` the
+/// replica's sync status is included, and a failing sync answers 503.
+async fn health(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    if !wants_json {
+        return "ok".into_response();
+    }
+
+    let Some(replica) = state.db.replica_status() else {
+        return Json(json!({ "status": "ok" })).into_response();
+    };
+    if replica.last_error.is_some() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "degraded", "replica": replica })),
+        )
+            .into_response();
+    }
+    Json(json!({ "status": "ok", "replica": replica })).into_response()
 }
 
 async fn shutdown_signal() {
@@ -210,3 +798,119 @@ async fn shutdown_signal() {
         tracing::warn!("failed to register ctrl+c handler: {err}");
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+    use crate::db::LibsqlStore;
+
+    /// Stands in for a libSQL server that is up but failing: every request,
+    /// the sync protocol probe included, gets a 503.
+    async fn failing_libsql_server() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let mut request = [0; 4096];
+                    let _ = stream.read(&mut request).await;
+                    let _ = stream
+                        .write_all(
+                            b"HTTP/1.1 503 Service Unavailable\r\ncontent-length: 0\r\nconnection: close\r\n\r\n",
+                        )
+                        .await;
+                });
+            }
+        });
+        format!("http://{addr}")
+    }
+
+    async fn check(state: &AppState, accept: Option<&'static str>) -> (StatusCode, String) {
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(header::ACCEPT, HeaderValue::from_static(accept));
+        }
+        let response = health(State(state.clone()), headers).await;
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), 4096)
+            .await
+            .unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn health_reports_a_failing_replica_only_as_json() {
+        let dir =
+            std::env::temp_dir().join(format!("cloudconfig-replica-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = AppConfig {
+            turso_replica_path: Some(dir.join("replica.db").display().to_string()),
+            ..AppConfig::for_tests(&failing_libsql_server().await)
+        };
+        let db = Database::new(LibsqlStore::connect(&config).await.unwrap());
+        let state = AppState {
+            nonces: Nonces::from_config(&config, &db),
+            db,
+            config,
+            ca: None,
+            server_key: None,
+        };
+
+        // Probes keep the plain-text contract while the replica serves local data.
+        assert_eq!(
+            check(&state, None).await,
+            (StatusCode::OK, String::from("ok"))
+        );
+
+        let (status, body) = check(&state, Some("application/json")).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["status"], "degraded");
+        assert!(body["replica"]["last_error"].is_string(), "{body}");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    fn sign_args(extra: &[&str], key: &std::path::Path) -> SignArgs {
+        let client_id = uuid::Uuid::new_v4().to_string();
+        let key = key.display().to_string();
+        let mut command_line = vec![
+            "cloudconfig",
+            "sign",
+            "--path",
+            "/api/projects",
+            "--client-id",
+            &client_id,
+            "--key",
+            &key,
+        ];
+        command_line.extend_from_slice(extra);
+        match Cli::try_parse_from(command_line).unwrap().command {
+            Some(Command::Sign(args)) => args,
+            other => panic!("parsed {other:?}"),
+        }
+    }
+
+    #[test]
+    fn sign_without_a_host_uses_version_1() {
+        let key =
+            std::env::temp_dir().join(format!("cloudconfig-sign-{}.pem", uuid::Uuid::new_v4()));
+        let keypair = crypto::generate_ed25519_keypair().unwrap();
+        std::fs::write(&key, keypair.private_key_pem).unwrap();
+
+        let args = sign_args(&[], &key);
+        assert_eq!(sign_version(&args), SignatureVersion::V1);
+        run_sign(&args).unwrap();
+
+        let args = sign_args(&["--curl", "http://127.0.0.1:8080"], &key);
+        assert_eq!(sign_version(&args), SignatureVersion::V2);
+        run_sign(&args).unwrap();
+
+        // Asking for version 2 outright still needs the host.
+        assert!(run_sign(&sign_args(&["--signature-version", "2"], &key)).is_err());
+
+        std::fs::remove_file(&key).ok();
+    }
+}
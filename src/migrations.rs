@@ -0,0 +1,248 @@
+use serde::Serialize;
+
+use crate::{
+    crypto,
+    error::{AppError, AppResult},
+};
+
+/// A forward-only schema change. Applied migrations are recorded with the
+/// checksum of their SQL, so editing one after release is detected.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        crypto::sha256_hex(self.sql.as_bytes())
+    }
+}
+
+/// libSQL migrations, ordered by `version`; append new entries, never edit or
+/// reorder old ones.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../migrations/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "change_sequence",
+        sql: include_str!("../migrations/0002_change_sequence.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "project_revisions",
+        sql: include_str!("../migrations/0003_project_revisions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "client_certificates",
+        sql: include_str!("../migrations/0004_client_certificates.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "client_key_algorithm",
+        sql: include_str!("../migrations/0005_client_key_algorithm.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "sessions",
+        sql: include_str!("../migrations/0006_sessions.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "admin_otp",
+        sql: include_str!("../migrations/0007_admin_otp.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "admin_approvals",
+        sql: include_str!("../migrations/0008_admin_approvals.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "client_networks",
+        sql: include_str!("../migrations/0009_client_networks.sql"),
+    },
+];
+
+/// Postgres equivalents of [`MIGRATIONS`], sharing the same version numbers.
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial_schema",
+        sql: include_str!("../migrations/postgres/0001_initial_schema.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "change_sequence",
+        sql: include_str!("../migrations/postgres/0002_change_sequence.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "project_revisions",
+        sql: include_str!("../migrations/postgres/0003_project_revisions.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "client_certificates",
+        sql: include_str!("../migrations/postgres/0004_client_certificates.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "client_key_algorithm",
+        sql: include_str!("../migrations/postgres/0005_client_key_algorithm.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "sessions",
+        sql: include_str!("../migrations/postgres/0006_sessions.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "admin_otp",
+        sql: include_str!("../migrations/postgres/0007_admin_otp.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "admin_approvals",
+        sql: include_str!("../migrations/postgres/0008_admin_approvals.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "client_networks",
+        sql: include_str!("../migrations/postgres/0009_client_networks.sql"),
+    },
+];
+
+pub fn latest_version(known: &[Migration]) -> i64 {
+    known.last().map_or(0, |migration| migration.version)
+}
+
+/// A row read back from a backend's `schema_migrations` table.
+#[derive(Debug, Clone)]
+pub struct AppliedMigration {
+    pub version: i64,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationState {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SchemaStatus {
+    pub current_version: i64,
+    pub latest_version: i64,
+    pub migrations: Vec<MigrationState>,
+}
+
+impl SchemaStatus {
+    /// Compares what a backend recorded against the migrations this binary
+    /// knows, failing if an applied migration's SQL has since changed.
+    pub fn from_applied(known: &[Migration], applied: &[AppliedMigration]) -> AppResult<Self> {
+        let mut migrations = Vec::new();
+        for migration in known {
+            let record = applied
+                .iter()
+                .find(|record| record.version == migration.version);
+            if let Some(record) = record
+                && record.checksum != migration.checksum()
+            {
+                return Err(AppError::Internal(format!(
+                    "checksum mismatch for applied migration {:04} {}",
+                    migration.version, migration.name
+                )));
+            }
+
+            migrations.push(MigrationState {
+                version: migration.version,
+                name: migration.name.to_owned(),
+                applied_at: record.map(|record| record.applied_at.clone()),
+            });
+        }
+
+        let latest_version = latest_version(known);
+        for record in applied {
+            if record.version > latest_version {
+                migrations.push(MigrationState {
+                    version: record.version,
+                    name: record.name.clone(),
+                    applied_at: Some(record.applied_at.clone()),
+                });
+            }
+        }
+
+        Ok(Self {
+            current_version: applied
+                .iter()
+                .map(|record| record.version)
+                .max()
+                .unwrap_or(0),
+            latest_version,
+            migrations,
+        })
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &MigrationState> {
+        self.migrations
+            .iter()
+            .filter(|migration| migration.applied_at.is_none())
+    }
+
+    pub fn is_newer_than_binary(&self) -> bool {
+        self.current_version > self.latest_version
+    }
+
+    /// Errors when this binary must not run against the database.
+    pub fn ensure_supported(&self) -> AppResult<()> {
+        if self.is_newer_than_binary() {
+            return Err(AppError::Conflict(format!(
+                "database schema version {} is newer than this binary supports ({}); upgrade cloudconfig",
+                self.current_version, self.latest_version
+            )));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_applied(&self, version: i64) -> bool {
+        self.migrations
+            .iter()
+            .any(|state| state.version == version && state.applied_at.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MIGRATIONS, POSTGRES_MIGRATIONS};
+
+    #[test]
+    fn versions_are_strictly_increasing() {
+        for known in [MIGRATIONS, POSTGRES_MIGRATIONS] {
+            for pair in known.windows(2) {
+                assert!(pair[0].version < pair[1].version);
+            }
+            assert_eq!(known.first().map(|m| m.version), Some(1));
+        }
+    }
+
+    #[test]
+    fn backends_share_migration_versions() {
+        let libsql: Vec<_> = MIGRATIONS.iter().map(|m| (m.version, m.name)).collect();
+        let postgres: Vec<_> = POSTGRES_MIGRATIONS
+            .iter()
+            .map(|m| (m.version, m.name))
+            .collect();
+        assert_eq!(libsql, postgres);
+    }
+}
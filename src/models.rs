@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
+use crate::keys::KeyAlgorithm;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Client {
     pub id: Uuid,
@@ -8,6 +10,263 @@ pub struct Client {
     pub public_key: String,
     pub is_admin: bool,
     pub created_at: String,
+    #[serde(default)]
+    pub key_algorithm: KeyAlgorithm,
+    /// CIDR ranges the client may connect from; empty allows any address.
+    #[serde(default)]
+    pub allowed_networks: Vec<String>,
+}
+
+/// A certificate the internal CA issued for mTLS. Timestamps are Unix seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientCertificate {
+    /// Hex serial number.
+    pub serial: String,
+    pub client_id: Uuid,
+    /// SHA-256 of the DER certificate, `AB:CD:...`.
+    pub fingerprint: String,
+    pub issued_at: i64,
+    pub expires_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+/// What a console session token may do. Signed requests are never limited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionScope {
+    /// `GET` requests under `/api`.
+    Read,
+    /// Every other request under `/api`.
+    Write,
+    /// `/admin`, for admin clients only.
+    Admin,
+}
+
+impl SessionScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Admin => "admin",
+        }
+    }
+
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "read" => Some(Self::Read),
+            "write" => Some(Self::Write),
+            "admin" => Some(Self::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// A bearer session issued for a signed challenge. Timestamps are Unix
+/// seconds; the token itself is only ever stored hashed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Session {
+    pub id: Uuid,
+    pub client_id: Uuid,
+    pub scopes: Vec<SessionScope>,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionChallengeRequest {
+    pub client_id: Uuid,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionChallengeResponse {
+    pub challenge: String,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateSessionRequest {
+    pub client_id: Uuid,
+    pub challenge: String,
+    /// Base64 signature over the session canonical string.
+    pub signature: String,
+    pub scopes: Vec<SessionScope>,
+    /// Lifetime, capped at `SESSION_TTL_SECONDS`.
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+}
+
+/// A client's TOTP secret; never serialized.
+#[derive(Debug, Clone)]
+pub struct OtpEnrollment {
+    pub client_id: Uuid,
+    /// Base32, as shown to authenticator apps.
+    pub secret: String,
+    /// `None` until a first code confirms the enrollment.
+    pub enabled_at: Option<i64>,
+    /// Last accepted time step; codes at or before it are rejected.
+    pub last_step: i64,
+    /// Consecutive wrong codes since the last accepted one.
+    pub failures: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OtpEnrollmentResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ConfirmOtpRequest {
+    pub code: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
+}
+
+/// Admin actions that can be made to wait for other admins' approval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApprovalAction {
+    /// Creating a client with `is_admin`.
+    CreateAdmin,
+    DeleteProject,
+    /// Granting or revoking permissions on a project in `PRODUCTION_PROJECTS`.
+    ProductionPermissions,
+}
+
+impl ApprovalAction {
+    pub const ALL: [Self; 3] = [
+        Self::CreateAdmin,
+        Self::DeleteProject,
+        Self::ProductionPermissions,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::CreateAdmin => "create-admin",
+            Self::DeleteProject => "delete-project",
+            Self::ProductionPermissions => "production-permissions",
+        }
+    }
+
+    pub fn parse(raw: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|action| action.as_str() == raw)
+    }
+}
+
+/// An admin request held back for approval, replayed once approved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum AdminOperation {
+    CreateAdmin {
+        name: String,
+        public_key: String,
+        key_algorithm: KeyAlgorithm,
+    },
+    DeleteProject {
+        project_id: Uuid,
+    },
+    SetPermission {
+        client_id: Uuid,
+        project_id: Uuid,
+        can_read: bool,
+        can_write: bool,
+    },
+    RevokePermission {
+        client_id: Uuid,
+        project_id: Uuid,
+    },
+}
+
+impl AdminOperation {
+    pub fn action(&self) -> ApprovalAction {
+        match self {
+            Self::CreateAdmin { .. } => ApprovalAction::CreateAdmin,
+            Self::DeleteProject { .. } => ApprovalAction::DeleteProject,
+            Self::SetPermission { .. } | Self::RevokePermission { .. } => {
+                ApprovalAction::ProductionPermissions
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PendingActionStatus {
+    Pending,
+    Executed,
+    Rejected,
+    /// Approved, but the operation itself returned an error.
+    Failed,
+    /// Not approved before `expires_at`.
+    Expired,
+}
+
+impl PendingActionStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Executed => "executed",
+            Self::Rejected => "rejected",
+            Self::Failed => "failed",
+            Self::Expired => "expired",
+        }
+    }
+
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "pending" => Some(Self::Pending),
+            "executed" => Some(Self::Executed),
+            "rejected" => Some(Self::Rejected),
+            "failed" => Some(Self::Failed),
+            "expired" => Some(Self::Expired),
+            _ => None,
+        }
+    }
+}
+
+/// A gated admin action waiting for, or done with, approvals. Timestamps are
+/// Unix seconds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingAction {
+    pub id: Uuid,
+    pub action: ApprovalAction,
+    pub operation: AdminOperation,
+    pub requested_by: Uuid,
+    /// Distinct admins that approved, the requester first.
+    pub approvals: Vec<Uuid>,
+    /// Approvals needed to execute, fixed when the action was requested.
+    pub required_approvals: u32,
+    pub status: PendingActionStatus,
+    pub created_at: i64,
+    pub expires_at: i64,
+    pub resolved_at: Option<i64>,
+    /// The admin whose approval executed the action, or who rejected it.
+    pub resolved_by: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct IssueCertificateRequest {
+    /// PEM certificate signing request. Without one the server generates the
+    /// key pair and returns the private key once.
+    #[serde(default)]
+    pub csr: Option<String>,
+    /// Lifetime, capped at `MTLS_CERT_TTL_HOURS`.
+    #[serde(default)]
+    pub ttl_hours: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedCertificate {
+    #[serde(flatten)]
+    pub certificate: ClientCertificate,
+    pub certificate_pem: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key_pem: Option<String>,
+    pub ca_certificate_pem: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +275,9 @@ pub struct Project {
     pub name: String,
     pub description: String,
     pub created_at: String,
+    /// Bumped by every config write in the project.
+    #[serde(default)]
+    pub revision: i64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,6 +290,38 @@ pub struct ConfigItem {
     pub updated_at: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeOp {
+    Set,
+    Delete,
+}
+
+/// One config write as recorded in a project's change log. `value` is
+/// `None` for deletes, and `version` is the config's version at that point.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigChange {
+    pub revision: i64,
+    pub key: String,
+    pub op: ChangeOp,
+    pub value: Option<String>,
+    pub version: i64,
+    pub changed_at: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeFeed {
+    pub project_id: Uuid,
+    /// Project revision the feed is complete up to; pass it as `since` next time.
+    pub revision: i64,
+    /// `since` predates the retained history, so `changes` is empty and the
+    /// full config list has to be fetched again.
+    pub reset: bool,
+    pub changes: Vec<ConfigChange>,
+    /// The page was cut at the requested limit; more changes follow `revision`.
+    pub has_more: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClientPermission {
     pub client_id: Uuid,
@@ -36,15 +330,53 @@ pub struct ClientPermission {
     pub can_write: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EffectivePermissions {
+    pub client_id: Uuid,
+    pub is_admin: bool,
+    pub permissions: Vec<ClientPermission>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReplicaStatus {
+    pub replica_path: String,
+    pub last_sync_at: Option<i64>,
+    pub last_success_at: Option<i64>,
+    pub frame_no: Option<u64>,
+    pub frames_synced_total: u64,
+    pub sync_count: u64,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub entries: usize,
+    pub invalidations: u64,
+    pub ttl_seconds: u64,
+    pub change_seq: Option<i64>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct CreateClientRequest {
     pub name: String,
+    /// Registers an existing public key instead of generating a key pair.
+    #[serde(default)]
+    pub public_key: Option<String>,
+    #[serde(default)]
+    pub key_algorithm: Option<KeyAlgorithm>,
+    #[serde(default)]
+    pub is_admin: bool,
 }
 
 #[derive(Debug, Serialize)]
 pub struct CreateClientResponse {
     pub client: Client,
-    pub private_key_pem: String,
+    /// Absent when the request supplied its own public key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key_pem: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -64,9 +396,64 @@ pub struct UpdateConfigValueRequest {
     pub value: String,
 }
 
+/// One step of an atomic batch. `expected_version` makes the whole batch
+/// fail unless the key is currently at that version; `0` means "must not
+/// exist yet".
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum ConfigOperation {
+    Set {
+        key: String,
+        value: String,
+        expected_version: Option<i64>,
+    },
+    Delete {
+        key: String,
+        expected_version: Option<i64>,
+    },
+}
+
+impl ConfigOperation {
+    pub fn key(&self) -> &str {
+        match self {
+            Self::Set { key, .. } | Self::Delete { key, .. } => key,
+        }
+    }
+
+    pub fn expected_version(&self) -> Option<i64> {
+        match self {
+            Self::Set {
+                expected_version, ..
+            }
+            | Self::Delete {
+                expected_version, ..
+            } => *expected_version,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BatchWriteRequest {
+    pub operations: Vec<ConfigOperation>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BatchWriteResponse {
+    /// Configs written by `set` operations, in request order.
+    pub configs: Vec<ConfigItem>,
+    /// Keys removed by `delete` operations, in request order.
+    pub deleted: Vec<String>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct SetPermissionRequest {
     pub project_id: Uuid,
     pub can_read: bool,
     pub can_write: bool,
 }
+
+/// A client's allowed networks. Setting an empty list lifts the restriction.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClientNetworks {
+    pub allowed_networks: Vec<String>,
+}
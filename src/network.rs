@@ -0,0 +1,171 @@
+//! CIDR ranges for per-client source address allowlists, and finding the
+//! client's address behind trusted reverse proxies.
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::error::{AppError, AppResult};
+
+pub const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// An address range such as `10.0.0.0/8` or `2001:db8::/32`. A bare address
+/// is a single-host range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNetwork {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                mask_v4(ip.to_bits(), self.prefix) == network.to_bits()
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                mask_v6(ip.to_bits(), self.prefix) == network.to_bits()
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpNetwork {
+    type Err = AppError;
+
+    fn from_str(raw: &str) -> AppResult<Self> {
+        let invalid = || AppError::BadRequest(format!("invalid CIDR range: {raw}"));
+        let (addr, prefix) = match raw.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (raw.trim(), None),
+        };
+        let addr = canonical(addr.parse::<IpAddr>().map_err(|_| invalid())?);
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().map_err(|_| invalid())?,
+            None => max_prefix,
+        };
+        if prefix > max_prefix {
+            return Err(invalid());
+        }
+
+        // Host bits are dropped, so `10.1.2.3/8` is stored as `10.0.0.0/8`.
+        let addr = match addr {
+            IpAddr::V4(ip) => IpAddr::V4(mask_v4(ip.to_bits(), prefix).into()),
+            IpAddr::V6(ip) => IpAddr::V6(mask_v6(ip.to_bits(), prefix).into()),
+        };
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Parses every range, normalised and without duplicates.
+pub fn parse_networks<S: AsRef<str>>(ranges: &[S]) -> AppResult<Vec<IpNetwork>> {
+    let mut networks: Vec<IpNetwork> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let network = range.as_ref().parse()?;
+        if !networks.contains(&network) {
+            networks.push(network);
+        }
+    }
+    Ok(networks)
+}
+
+/// The address a request came from. Requests from a trusted proxy are traced
+/// back through `X-Forwarded-For`, right to left, to the first hop that is
+/// not itself a trusted proxy.
+pub fn client_address(peer: IpAddr, forwarded_for: Option<&str>, trusted: &[IpNetwork]) -> IpAddr {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|network| network.contains(ip));
+    let mut address = canonical(peer);
+    if !is_trusted(address) {
+        return address;
+    }
+
+    for hop in forwarded_for.unwrap_or_default().rsplit(',') {
+        let Ok(hop) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        address = canonical(hop);
+        if !is_trusted(address) {
+            break;
+        }
+    }
+    address
+}
+
+/// IPv4-mapped IPv6 addresses (`::ffff:10.0.0.1`) compare as IPv4.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+fn mask_v4(bits: u32, prefix: u8) -> u32 {
+    bits & u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0)
+}
+
+fn mask_v6(bits: u128, prefix: u8) -> u128 {
+    bits & u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(raw: &str) -> IpAddr {
+        raw.parse().unwrap()
+    }
+
+    #[test]
+    fn matches_ranges() {
+        let network: IpNetwork = "10.1.2.3/8".parse().unwrap();
+        assert_eq!(network.to_string(), "10.0.0.0/8");
+        assert!(network.contains(ip("10.255.0.1")));
+        assert!(network.contains(ip("::ffff:10.0.0.1")));
+        assert!(!network.contains(ip("11.0.0.1")));
+
+        let host: IpNetwork = "2001:db8::1".parse().unwrap();
+        assert_eq!(host.to_string(), "2001:db8::1/128");
+        assert!(host.contains(ip("2001:db8::1")));
+        assert!(!host.contains(ip("2001:db8::2")));
+
+        let any: IpNetwork = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(ip("192.0.2.1")));
+        assert!(!any.contains(ip("2001:db8::1")));
+
+        for invalid in ["10.0.0.0/33", "10.0.0/8", "::/129", "host", "10.0.0.0/"] {
+            assert!(invalid.parse::<IpNetwork>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn follows_forwarded_for_only_from_trusted_proxies() {
+        let trusted = parse_networks(&["10.0.0.0/8"]).unwrap();
+        let forwarded = Some("198.51.100.7, 203.0.113.9, 10.0.0.2");
+
+        assert_eq!(
+            client_address(ip("10.0.0.1"), forwarded, &trusted),
+            ip("203.0.113.9")
+        );
+        // An untrusted peer cannot claim another address.
+        assert_eq!(
+            client_address(ip("192.0.2.1"), forwarded, &trusted),
+            ip("192.0.2.1")
+        );
+        assert_eq!(
+            client_address(ip("10.0.0.1"), None, &trusted),
+            ip("10.0.0.1")
+        );
+        assert_eq!(
+            client_address(ip("10.0.0.1"), Some("junk, 10.0.0.3"), &trusted),
+            ip("10.0.0.3")
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::{BuildHasher, RandomState};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::{
+    config::{AppConfig, NonceStoreKind},
+    db::{self, Database},
+    error::{AppError, AppResult},
+};
+
+const SHARD_COUNT: usize = 16;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Remembers recently seen request nonces so a signed request cannot be
+/// replayed while its timestamp is still inside the accepted window.
+#[async_trait]
+pub trait NonceStore: Debug + Send + Sync {
+    /// Records `nonce`, failing with `Unauthorized` if it is still remembered.
+    async fn register(&self, client_id: &Uuid, nonce: &str, now_timestamp: i64) -> AppResult<()>;
+
+    /// Forgets nonces older than the TTL and returns how many were dropped.
+    async fn purge_expired(&self, now_timestamp: i64) -> AppResult<u64>;
+}
+
+/// Cloneable handle to the configured [`NonceStore`].
+#[derive(Debug, Clone)]
+pub struct Nonces {
+    store: Arc<dyn NonceStore>,
+}
+
+impl Nonces {
+    pub fn from_config(config: &AppConfig, db: &Database) -> Self {
+        let ttl_seconds = config.nonce_ttl_seconds();
+        match config.nonce_store {
+            NonceStoreKind::Memory => Self::new(MemoryNonceStore::new(ttl_seconds)),
+            NonceStoreKind::Database => Self::new(DatabaseNonceStore {
+                db: db.clone(),
+                ttl_seconds,
+            }),
+        }
+    }
+
+    pub fn new(store: impl NonceStore + 'static) -> Self {
+        Self {
+            store: Arc::new(store),
+        }
+    }
+
+    /// Purges expired nonces once a minute for the life of the process.
+    pub fn spawn_cleanup(&self) {
+        let nonces = self.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(CLEANUP_INTERVAL);
+            ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let result = match crate::auth::current_unix_timestamp() {
+                    Ok(now) => nonces.purge_expired(now).await,
+                    Err(error) => Err(error),
+                };
+                match result {
+                    Ok(0) => {}
+                    Ok(purged) => tracing::debug!("purged {purged} expired nonces"),
+                    Err(error) => tracing::warn!("nonce cleanup failed: {error}"),
+                }
+            }
+        });
+    }
+}
+
+impl Deref for Nonces {
+    type Target = dyn NonceStore;
+
+    fn deref(&self) -> &Self::Target {
+        self.store.as_ref()
+    }
+}
+
+/// Process-local store. Fast, but each instance only sees its own traffic.
+#[derive(Debug)]
+pub struct MemoryNonceStore {
+    shards: Vec<Mutex<HashMap<(Uuid, String), i64>>>,
+    hasher: RandomState,
+    ttl_seconds: i64,
+}
+
+impl MemoryNonceStore {
+    pub fn new(ttl_seconds: i64) -> Self {
+        Self {
+            shards: (0..SHARD_COUNT)
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            hasher: RandomState::new(),
+            ttl_seconds,
+        }
+    }
+
+    fn shard(
+        &self,
+        key: &(Uuid, String),
+    ) -> AppResult<MutexGuard<'_, HashMap<(Uuid, String), i64>>> {
+        #[allow(clippy::cast_possible_truncation)]
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        self.shards[index]
+            .lock()
+            .map_err(|_| AppError::Internal(String::from("nonce store lock poisoned")))
+    }
+}
+
+#[async_trait]
+impl NonceStore for MemoryNonceStore {
+    async fn register(&self, client_id: &Uuid, nonce: &str, now_timestamp: i64) -> AppResult<()> {
+        let key = (*client_id, db::validate_nonce(nonce)?.to_owned());
+        let mut shard = self.shard(&key)?;
+
+        // An entry the cleanup task has not reached yet may already be expired.
+        let cutoff = now_timestamp - self.ttl_seconds;
+        if shard.get(&key).is_some_and(|seen_at| *seen_at >= cutoff) {
+            return Err(db::replayed_request());
+        }
+
+        shard.insert(key, now_timestamp);
+        Ok(())
+    }
+
+    async fn purge_expired(&self, now_timestamp: i64) -> AppResult<u64> {
+        let cutoff = now_timestamp - self.ttl_seconds;
+        let mut purged = 0;
+        for shard in &self.shards {
+            let mut shard = shard
+                .lock()
+                .map_err(|_| AppError::Internal(String::from("nonce store lock poisoned")))?;
+            let before = shard.len();
+            shard.retain(|_, seen_at| *seen_at >= cutoff);
+            purged += (before - shard.len()) as u64;
+        }
+
+        Ok(purged)
+    }
+}
+
+/// Keeps nonces in the `used_nonces` table so every instance sharing the
+/// database rejects the same replays.
+#[derive(Debug)]
+pub struct DatabaseNonceStore {
+    db: Database,
+    ttl_seconds: i64,
+}
+
+#[async_trait]
+impl NonceStore for DatabaseNonceStore {
+    async fn register(&self, client_id: &Uuid, nonce: &str, now_timestamp: i64) -> AppResult<()> {
+        self.db
+            .register_nonce(client_id, nonce, now_timestamp, self.ttl_seconds)
+            .await
+    }
+
+    async fn purge_expired(&self, now_timestamp: i64) -> AppResult<u64> {
+        self.db
+            .purge_expired_nonces(now_timestamp - self.ttl_seconds)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::{MemoryNonceStore, NonceStore};
+    use crate::error::AppError;
+
+    #[tokio::test]
+    async fn memory_store_rejects_replays_until_expiry() {
+        let store = MemoryNonceStore::new(600);
+        let client = Uuid::new_v4();
+        let now = 1_700_000_000;
+
+        store.register(&client, "n-1", now).await.unwrap();
+        assert!(matches!(
+            store.register(&client, "n-1", now + 600).await,
+            Err(AppError::Unauthorized(_))
+        ));
+        store.register(&Uuid::new_v4(), "n-1", now).await.unwrap();
+
+        assert_eq!(store.purge_expired(now + 600).await.unwrap(), 0);
+        assert_eq!(store.purge_expired(now + 601).await.unwrap(), 2);
+        store.register(&client, "n-1", now + 601).await.unwrap();
+    }
+}
@@ -0,0 +1,241 @@
+//! Optional TOTP second factor (RFC 6238: HMAC-SHA1, 30 s steps, 6 digits)
+//! for admin clients, with single-use offline recovery codes. Destructive
+//! admin requests carry the current code in `X-OTP`.
+
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
+use uuid::Uuid;
+
+use crate::{
+    auth, crypto,
+    db::Database,
+    error::{AppError, AppResult},
+};
+
+pub const OTP_HEADER: &str = "X-OTP";
+const STEP_SECONDS: i64 = 30;
+/// Steps either side of now that are still accepted, for clock drift.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+/// Wrong codes in a row after which only a recovery code is accepted.
+const MAX_FAILURES: i64 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A new random secret, base32 encoded for authenticator apps.
+pub fn generate_secret() -> AppResult<String> {
+    let mut bytes = [0_u8; 20];
+    SystemRandom::new().fill(&mut bytes)?;
+    Ok(base32_encode(&bytes))
+}
+
+pub fn otpauth_uri(client_id: &Uuid, secret: &str) -> String {
+    format!("otpauth://totp/cloudconfig:{client_id}?secret={secret}&issuer=cloudconfig")
+}
+
+/// Checks `code` for a client that has enabled one-time codes; clients that
+/// have not pass without one. Accepts a current TOTP code or an unused
+/// recovery code.
+pub async fn verify(db: &Database, client_id: &Uuid, code: Option<&str>) -> AppResult<()> {
+    let Some(enrollment) = db
+        .get_otp(client_id)
+        .await?
+        .filter(|enrollment| enrollment.enabled_at.is_some())
+    else {
+        return Ok(());
+    };
+    let Some(code) = code.map(str::trim).filter(|code| !code.is_empty()) else {
+        return Err(AppError::Forbidden(String::from("one-time code required")));
+    };
+
+    let now = auth::current_unix_timestamp()?;
+    if is_totp_code(code) {
+        if enrollment.failures >= MAX_FAILURES {
+            tracing::warn!("one-time codes locked for client {client_id} after repeated failures");
+            return Err(AppError::Forbidden(String::from(
+                "too many wrong one-time codes; use a recovery code",
+            )));
+        }
+        if let Some(step) = matching_step(&enrollment.secret, code, now)? {
+            if db.accept_otp_step(client_id, step).await? {
+                return Ok(());
+            }
+            return Err(AppError::Forbidden(String::from(
+                "one-time code already used",
+            )));
+        }
+    } else if db
+        .use_recovery_code(client_id, &hash_recovery_code(code), now)
+        .await?
+    {
+        tracing::warn!("recovery code used by client {client_id}");
+        return Ok(());
+    }
+
+    let failures = db.record_otp_failure(client_id).await?;
+    tracing::warn!("wrong one-time code for client {client_id} ({failures} in a row)");
+    Err(AppError::Forbidden(String::from("invalid one-time code")))
+}
+
+/// Enables a pending enrollment once `code` proves the app has the secret.
+pub async fn confirm(db: &Database, client_id: &Uuid, code: &str) -> AppResult<()> {
+    let enrollment = db
+        .get_otp(client_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("no one-time code enrollment")))?;
+    if enrollment.enabled_at.is_some() {
+        return Err(AppError::Conflict(String::from(
+            "one-time codes are already enabled",
+        )));
+    }
+
+    let now = auth::current_unix_timestamp()?;
+    let code = code.trim();
+    let step = if is_totp_code(code) {
+        matching_step(&enrollment.secret, code, now)?
+    } else {
+        None
+    };
+    let Some(step) = step else {
+        return Err(AppError::BadRequest(String::from("invalid one-time code")));
+    };
+    if !db.enable_otp(client_id, now, step).await? {
+        return Err(AppError::Conflict(String::from(
+            "one-time codes are already enabled",
+        )));
+    }
+
+    Ok(())
+}
+
+/// Replaces the client's recovery codes, returning the new ones. Only their
+/// hashes are stored, so they cannot be shown again.
+pub async fn issue_recovery_codes(db: &Database, client_id: &Uuid) -> AppResult<Vec<String>> {
+    let rng = SystemRandom::new();
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let mut bytes = [0_u8; 10];
+        rng.fill(&mut bytes)?;
+        let digits = hex::encode(bytes);
+        let groups: Vec<&str> = (0..digits.len())
+            .step_by(5)
+            .map(|start| &digits[start..start + 5])
+            .collect();
+        codes.push(groups.join("-"));
+    }
+
+    let hashes: Vec<String> = codes.iter().map(|code| hash_recovery_code(code)).collect();
+    db.replace_recovery_codes(client_id, &hashes).await?;
+    Ok(codes)
+}
+
+/// The 6-digit code for time step `step`.
+pub fn code_at(secret: &[u8], step: i64) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, secret);
+    let tag = hmac::sign(&key, &step.to_be_bytes());
+    let digest = tag.as_ref();
+    let offset = usize::from(digest[digest.len() - 1] & 0x0f);
+    let value = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    format!("{:06}", value % 1_000_000)
+}
+
+fn matching_step(secret: &str, code: &str, now: i64) -> AppResult<Option<i64>> {
+    let secret = base32_decode(secret)
+        .ok_or_else(|| AppError::Internal(String::from("stored OTP secret is not base32")))?;
+    let current = now / STEP_SECONDS;
+    Ok(
+        (current - ALLOWED_DRIFT_STEPS..=current + ALLOWED_DRIFT_STEPS)
+            .find(|step| codes_equal(&code_at(&secret, *step), code)),
+    )
+}
+
+/// Compares without stopping at the first differing digit.
+fn codes_equal(expected: &str, actual: &str) -> bool {
+    expected.len() == actual.len()
+        && expected
+            .bytes()
+            .zip(actual.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn is_totp_code(code: &str) -> bool {
+    code.len() == 6 && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Recovery codes are compared without dashes, spaces or case.
+fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    crypto::sha256_hex(normalized.as_bytes())
+}
+
+fn base32_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(5) * 8);
+    let mut buffer = 0_u32;
+    let mut bits = 0;
+    for &byte in bytes {
+        buffer = (buffer << 8) | u32::from(byte);
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(char::from(
+                BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize],
+            ));
+        }
+    }
+    if bits > 0 {
+        encoded.push(char::from(
+            BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize],
+        ));
+    }
+    encoded
+}
+
+fn base32_decode(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() * 5 / 8);
+    let mut buffer = 0_u32;
+    let mut bits = 0;
+    for c in encoded.chars().filter(|c| !matches!(c, '=' | ' ' | '-')) {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|&letter| char::from(letter) == c.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | u32::try_from(value).ok()?;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push(u8::try_from((buffer >> bits) & 0xff).ok()?);
+        }
+    }
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_rfc_6238_vectors() {
+        let secret = b"12345678901234567890";
+        assert_eq!(code_at(secret, 59 / STEP_SECONDS), "287082");
+        assert_eq!(code_at(secret, 1_111_111_109 / STEP_SECONDS), "081804");
+        assert_eq!(code_at(secret, 2_000_000_000 / STEP_SECONDS), "279037");
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert_eq!(base32_decode("mzxw6ytboi======").unwrap(), b"foobar");
+        let secret = generate_secret().unwrap();
+        assert_eq!(base32_decode(&secret).unwrap().len(), 20);
+    }
+}
@@ -2,32 +2,64 @@ use axum::{
     Json, Router,
     extract::{Extension, Path, State},
     http::StatusCode,
-    response::IntoResponse,
-    routing::{delete, post},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
 };
 use uuid::Uuid;
 
 use crate::{
-    AppState,
-    auth::{AuthenticatedClient, require_admin},
-    crypto,
+    AppState, approvals,
+    auth::{self, AuthenticatedClient, require_admin, require_admin_otp},
+    ca, crypto,
     error::{AppError, AppResult},
+    keys,
     models::{
-        CreateClientRequest, CreateClientResponse, CreateProjectRequest, SetPermissionRequest,
-        UpsertConfigRequest,
+        AdminOperation, BatchWriteRequest, ClientNetworks, ConfirmOtpRequest, CreateClientRequest,
+        CreateClientResponse, CreateProjectRequest, IssueCertificateRequest, OtpEnrollmentResponse,
+        RecoveryCodesResponse, SetPermissionRequest, UpsertConfigRequest,
     },
+    network, otp,
+    routes::{validate_batch_values, validate_json_string},
 };
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/clients", post(create_client).get(list_clients))
         .route("/clients/{id}", delete(delete_client))
+        .route(
+            "/clients/{client_id}/networks",
+            get(get_client_networks).put(set_client_networks),
+        )
         .route("/projects", post(create_project).get(list_projects))
+        .route("/projects/{project_id}", delete(delete_project))
         .route(
             "/projects/{project_id}/configs",
             post(upsert_project_config).get(list_project_configs),
         )
+        .route("/projects/{project_id}/batch", post(batch_project_configs))
         .route("/clients/{client_id}/permissions", post(set_permission))
+        .route(
+            "/clients/{client_id}/certificates",
+            post(issue_certificate).get(list_client_certificates),
+        )
+        .route("/certificates", get(list_certificates))
+        .route("/certificates/revoked", get(list_revoked_certificates))
+        .route("/certificates/{serial}", delete(revoke_certificate))
+        .route("/clients/{client_id}/sessions", get(list_client_sessions))
+        .route("/sessions", get(list_sessions))
+        .route("/sessions/{session_id}", delete(revoke_session))
+        .route("/otp", post(enroll_otp).delete(disable_otp))
+        .route("/otp/confirm", post(confirm_otp))
+        .route("/otp/recovery-codes", post(regenerate_recovery_codes))
+        .route("/approvals", get(list_pending_actions))
+        .route("/approvals/{action_id}", get(get_pending_action))
+        .route(
+            "/approvals/{action_id}/approve",
+            post(approve_pending_action),
+        )
+        .route("/approvals/{action_id}/reject", post(reject_pending_action))
+        .route("/sync", post(sync_replica))
+        .route("/cache", get(cache_stats))
         .route(
             "/clients/{client_id}/permissions/{project_id}",
             delete(revoke_permission),
@@ -38,22 +70,67 @@ async fn create_client(
     State(state): State<AppState>,
     Extension(auth_client): Extension<AuthenticatedClient>,
     Json(payload): Json<CreateClientRequest>,
-) -> AppResult<impl IntoResponse> {
-    require_admin(&auth_client)?;
+) -> AppResult<Response> {
+    if payload.is_admin {
+        require_admin_otp(&state, &auth_client).await?;
+    } else {
+        require_admin(&auth_client)?;
+    }
+
+    let (public_key, private_key_pem) = if let Some(raw) = payload.public_key.as_deref() {
+        let public_key = keys::PublicKey::parse(raw)?;
+        if let Some(algorithm) = payload.key_algorithm
+            && algorithm != public_key.algorithm
+        {
+            return Err(AppError::BadRequest(format!(
+                "public_key is an {} key, not {algorithm}",
+                public_key.algorithm
+            )));
+        }
+        (public_key, None)
+    } else {
+        let generated = crypto::generate_keypair(payload.key_algorithm.unwrap_or_default())?;
+        let public_key = keys::PublicKey {
+            algorithm: generated.algorithm,
+            public_key_b64: generated.public_key_b64,
+        };
+        (public_key, Some(generated.private_key_pem))
+    };
+    if payload.is_admin {
+        let operation = AdminOperation::CreateAdmin {
+            name: payload.name.clone(),
+            public_key: public_key.public_key_b64.clone(),
+            key_algorithm: public_key.algorithm,
+        };
+        if private_key_pem.is_some() && state.config.approvals.gates(operation.action()) {
+            // Whoever's approval runs it would never see the generated key.
+            return Err(AppError::BadRequest(String::from(
+                "admins that need approval must be created with a public_key",
+            )));
+        }
+        if let Some(pending) = approvals::hold(&state, &auth_client, operation).await? {
+            return Ok((StatusCode::ACCEPTED, Json(pending)).into_response());
+        }
+    }
 
-    let generated = crypto::generate_ed25519_keypair()?;
     let client = state
         .db
-        .create_client(&payload.name, &generated.public_key_b64, false)
+        .create_client(
+            &payload.name,
+            &public_key.public_key_b64,
+            public_key.algorithm,
+            payload.is_admin,
+        )
         .await?;
 
     Ok((
         StatusCode::CREATED,
         Json(CreateClientResponse {
             client,
-            private_key_pem: generated.private_key_pem,
+            private_key_pem,
         }),
-    ))
+    )
+        .into_response())
 }
 
 async fn list_clients(
@@ -70,7 +147,7 @@ async fn delete_client(
     Extension(auth_client): Extension<AuthenticatedClient>,
     Path(client_id): Path<Uuid>,
 ) -> AppResult<impl IntoResponse> {
-    require_admin(&auth_client)?;
+    require_admin_otp(&state, &auth_client).await?;
 
     if auth_client.id == client_id {
         return Err(AppError::Conflict(String::from(
@@ -86,6 +163,62 @@ async fn delete_client(
     Ok(StatusCode::NO_CONTENT)
 }
 
+async fn get_client_networks(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(client_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+
+    let client = state
+        .db
+        .get_client_by_id(&client_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("client not found")))?;
+    Ok(Json(ClientNetworks {
+        allowed_networks: client.allowed_networks,
+    }))
+}
+
+/// Replaces the CIDR ranges a client may connect from. Ranges are stored
+/// normalised, so `10.1.2.3/8` becomes `10.0.0.0/8`.
+async fn set_client_networks(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(client_id): Path<Uuid>,
+    Json(payload): Json<ClientNetworks>,
+) -> AppResult<impl IntoResponse> {
+    require_admin_otp(&state, &auth_client).await?;
+
+    let networks = network::parse_networks(&payload.allowed_networks)?;
+    if auth_client.id == client_id
+        && !networks.is_empty()
+        && !auth_client
+            .source
+            .is_some_and(|ip| networks.iter().any(|network| network.contains(ip)))
+    {
+        return Err(AppError::Conflict(String::from(
+            "allowed networks must include the address of this request",
+        )));
+    }
+
+    let allowed_networks: Vec<String> = networks.iter().map(ToString::to_string).collect();
+    if !state
+        .db
+        .set_client_networks(&client_id, &allowed_networks)
+        .await?
+    {
+        return Err(AppError::NotFound(String::from("client not found")));
+    }
+
+    tracing::info!(
+        "allowed networks of client {client_id} set to [{}] by {}",
+        allowed_networks.join(", "),
+        auth_client.id
+    );
+    Ok(Json(ClientNetworks { allowed_networks }))
+}
+
 async fn create_project(
     State(state): State<AppState>,
     Extension(auth_client): Extension<AuthenticatedClient>,
@@ -107,6 +240,24 @@ async fn list_projects(
     Ok(Json(projects))
 }
 
+async fn delete_project(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(project_id): Path<Uuid>,
+) -> AppResult<Response> {
+    require_admin_otp(&state, &auth_client).await?;
+
+    let operation = AdminOperation::DeleteProject { project_id };
+    if let Some(pending) = approvals::hold(&state, &auth_client, operation).await? {
+        return Ok((StatusCode::ACCEPTED, Json(pending)).into_response());
+    }
+    if !state.db.delete_project(&project_id).await? {
+        return Err(AppError::NotFound(String::from("project not found")));
+    }
+
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
 async fn upsert_project_config(
     State(state): State<AppState>,
     Extension(auth_client): Extension<AuthenticatedClient>,
@@ -124,6 +275,22 @@ async fn upsert_project_config(
     Ok(Json(config_item))
 }
 
+async fn batch_project_configs(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(project_id): Path<Uuid>,
+    Json(payload): Json<BatchWriteRequest>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+    validate_batch_values(&payload.operations)?;
+
+    let response = state
+        .db
+        .apply_config_batch(&project_id, &payload.operations)
+        .await?;
+    Ok(Json(response))
+}
+
 async fn list_project_configs(
     State(state): State<AppState>,
     Extension(auth_client): Extension<AuthenticatedClient>,
@@ -139,36 +306,294 @@ async fn set_permission(
     Extension(auth_client): Extension<AuthenticatedClient>,
     Path(client_id): Path<Uuid>,
     Json(payload): Json<SetPermissionRequest>,
-) -> AppResult<impl IntoResponse> {
-    require_admin(&auth_client)?;
+) -> AppResult<Response> {
+    require_admin_otp(&state, &auth_client).await?;
 
     let can_read = payload.can_read || payload.can_write;
+    let operation = AdminOperation::SetPermission {
+        client_id,
+        project_id: payload.project_id,
+        can_read,
+        can_write: payload.can_write,
+    };
+    if let Some(pending) = approvals::hold(&state, &auth_client, operation).await? {
+        return Ok((StatusCode::ACCEPTED, Json(pending)).into_response());
+    }
     let permission = state
         .db
         .set_permission(&client_id, &payload.project_id, can_read, payload.can_write)
         .await?;
 
-    Ok(Json(permission))
+    Ok(Json(permission).into_response())
 }
 
 async fn revoke_permission(
     State(state): State<AppState>,
     Extension(auth_client): Extension<AuthenticatedClient>,
     Path((client_id, project_id)): Path<(Uuid, Uuid)>,
-) -> AppResult<impl IntoResponse> {
-    require_admin(&auth_client)?;
+) -> AppResult<Response> {
+    require_admin_otp(&state, &auth_client).await?;
 
+    let operation = AdminOperation::RevokePermission {
+        client_id,
+        project_id,
+    };
+    if let Some(pending) = approvals::hold(&state, &auth_client, operation).await? {
+        return Ok((StatusCode::ACCEPTED, Json(pending)).into_response());
+    }
     let removed = state.db.delete_permission(&client_id, &project_id).await?;
     if !removed {
         return Err(AppError::NotFound(String::from("permission not found")));
     }
 
+    Ok(StatusCode::NO_CONTENT.into_response())
+}
+
+async fn issue_certificate(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(client_id): Path<Uuid>,
+    payload: Option<Json<IssueCertificateRequest>>,
+) -> AppResult<impl IntoResponse> {
+    require_admin_otp(&state, &auth_client).await?;
+    // A certificate for another admin would let this one act as them, e.g.
+    // to approve its own pending actions.
+    if client_id != auth_client.id
+        && state
+            .db
+            .get_client_by_id(&client_id)
+            .await?
+            .is_some_and(|client| client.is_admin)
+    {
+        return Err(AppError::Forbidden(String::from(
+            "admins must request their own certificates",
+        )));
+    }
+
+    let request = payload.map(|Json(request)| request).unwrap_or_default();
+    let issued = ca::issue_for_client(&state, &client_id, &request).await?;
+    Ok((StatusCode::CREATED, Json(issued)))
+}
+
+async fn list_client_certificates(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(client_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+    let certificates = state.db.list_client_certificates(Some(&client_id)).await?;
+    Ok(Json(certificates))
+}
+
+async fn list_certificates(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+    let certificates = state.db.list_client_certificates(None).await?;
+    Ok(Json(certificates))
+}
+
+async fn list_revoked_certificates(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+    let mut certificates = state.db.list_client_certificates(None).await?;
+    certificates.retain(|certificate| certificate.revoked_at.is_some());
+    Ok(Json(certificates))
+}
+
+async fn revoke_certificate(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(serial): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    require_admin_otp(&state, &auth_client).await?;
+
+    let revoked_at = auth::current_unix_timestamp()?;
+    if !state
+        .db
+        .revoke_client_certificate(&serial, revoked_at)
+        .await?
+    {
+        return Err(AppError::NotFound(String::from(
+            "certificate not found or already revoked",
+        )));
+    }
+
     Ok(StatusCode::NO_CONTENT)
 }
 
-fn validate_json_string(raw: &str) -> AppResult<()> {
-    serde_json::from_str::<serde_json::Value>(raw).map_err(|e| {
-        AppError::BadRequest(format!("config value must be valid JSON string: {e}"))
+async fn list_client_sessions(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(client_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+    let sessions = state.db.list_sessions(Some(&client_id)).await?;
+    Ok(Json(sessions))
+}
+
+async fn list_sessions(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+    let sessions = state.db.list_sessions(None).await?;
+    Ok(Json(sessions))
+}
+
+async fn revoke_session(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(session_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    require_admin_otp(&state, &auth_client).await?;
+
+    let revoked_at = auth::current_unix_timestamp()?;
+    if !state.db.revoke_session(&session_id, revoked_at).await? {
+        return Err(AppError::NotFound(String::from(
+            "session not found or already revoked",
+        )));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn enroll_otp(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+
+    let enrolled = state.db.get_otp(&auth_client.id).await?;
+    if enrolled.is_some_and(|enrollment| enrollment.enabled_at.is_some()) {
+        return Err(AppError::Conflict(String::from(
+            "one-time codes are already enabled; disable them first",
+        )));
+    }
+
+    let secret = otp::generate_secret()?;
+    state.db.set_otp_secret(&auth_client.id, &secret).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(OtpEnrollmentResponse {
+            otpauth_uri: otp::otpauth_uri(&auth_client.id, &secret),
+            secret,
+        }),
+    ))
+}
+
+async fn confirm_otp(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Json(payload): Json<ConfirmOtpRequest>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+    otp::confirm(&state.db, &auth_client.id, &payload.code).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn disable_otp(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    require_admin_otp(&state, &auth_client).await?;
+
+    if !state.db.delete_otp(&auth_client.id).await? {
+        return Err(AppError::NotFound(String::from(
+            "no one-time code enrollment",
+        )));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    require_admin_otp(&state, &auth_client).await?;
+
+    let recovery_codes = otp::issue_recovery_codes(&state.db, &auth_client.id).await?;
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+async fn list_pending_actions(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+    state
+        .db
+        .expire_pending_actions(auth::current_unix_timestamp()?)
+        .await?;
+    let actions = state.db.list_pending_actions().await?;
+    Ok(Json(actions))
+}
+
+async fn get_pending_action(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(action_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+    state
+        .db
+        .expire_pending_actions(auth::current_unix_timestamp()?)
+        .await?;
+    let action = state
+        .db
+        .get_pending_action(&action_id)
+        .await?
+        .ok_or_else(|| AppError::NotFound(String::from("pending action not found")))?;
+    Ok(Json(action))
+}
+
+async fn approve_pending_action(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(action_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    require_admin_otp(&state, &auth_client).await?;
+    let action = approvals::approve(&state, &auth_client, &action_id).await?;
+    Ok(Json(action))
+}
+
+async fn reject_pending_action(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(action_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    require_admin_otp(&state, &auth_client).await?;
+    let action = approvals::reject(&state, &auth_client, &action_id).await?;
+    Ok(Json(action))
+}
+
+async fn sync_replica(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+
+    let status = state.db.sync().await?.ok_or_else(|| {
+        AppError::Conflict(String::from(
+            "database is not running as an embedded replica",
+        ))
     })?;
-    Ok(())
+    Ok(Json(status))
+}
+
+async fn cache_stats(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    require_admin(&auth_client)?;
+
+    let cache = state
+        .db
+        .cache()
+        .ok_or_else(|| AppError::Conflict(String::from("read cache is disabled")))?;
+    Ok(Json(cache.stats()))
 }
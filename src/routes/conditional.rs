@@ -0,0 +1,101 @@
+use std::time::SystemTime;
+
+use axum::{
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, IntoResponseParts, Response},
+};
+use time::{OffsetDateTime, format_description::well_known::Rfc3339};
+use uuid::Uuid;
+
+/// Strong validator for a project's config list. The revision moves on every
+/// write to the project, deletes included.
+pub fn project_etag(revision: i64) -> String {
+    format!("\"r{revision}\"")
+}
+
+/// Strong validator for one config. The row id changes when a key is deleted
+/// and set again, so the version alone cannot repeat with a different value.
+pub fn config_etag(id: &Uuid, version: i64) -> String {
+    format!("\"{}.{version}\"", id.simple())
+}
+
+/// Answers `304 Not Modified` when `If-None-Match` already names `etag`,
+/// otherwise sends `body`. Both carry the validators and `parts`.
+pub fn respond(
+    headers: &HeaderMap,
+    etag: &str,
+    last_modified: Option<&str>,
+    parts: impl IntoResponseParts,
+    body: impl IntoResponse,
+) -> Response {
+    let mut response = if if_none_match(headers, etag) {
+        (StatusCode::NOT_MODIFIED, parts, ()).into_response()
+    } else {
+        (parts, body).into_response()
+    };
+
+    let response_headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        response_headers.insert(header::ETAG, value);
+    }
+    if let Some(value) = last_modified
+        .and_then(parse_timestamp)
+        .and_then(|time| HeaderValue::from_str(&httpdate::fmt_http_date(time)).ok())
+    {
+        response_headers.insert(header::LAST_MODIFIED, value);
+    }
+    // Configs are per-client; shared caches must not store them.
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static("private, no-cache"),
+    );
+    response
+}
+
+/// Weak comparison, as RFC 9110 requires for `If-None-Match`.
+fn if_none_match(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|candidate| {
+            candidate == "*" || candidate.strip_prefix("W/").unwrap_or(candidate) == etag
+        })
+}
+
+/// Parses the `YYYY-MM-DD HH:MM:SS` UTC text every backend stores: RFC 3339
+/// with a space for the `T` and the offset left off.
+fn parse_timestamp(value: &str) -> Option<SystemTime> {
+    let rfc3339 = format!("{}Z", value.trim().replacen(' ', "T", 1));
+    OffsetDateTime::parse(&rfc3339, &Rfc3339)
+        .ok()
+        .map(SystemTime::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue, header};
+
+    use super::{if_none_match, parse_timestamp, project_etag};
+
+    #[test]
+    fn matches_etags_and_formats_last_modified() {
+        let mut headers = HeaderMap::new();
+        assert!(!if_none_match(&headers, &project_etag(7)));
+        headers.insert(
+            header::IF_NONE_MATCH,
+            HeaderValue::from_static("\"r6\", W/\"r7\""),
+        );
+        assert!(if_none_match(&headers, &project_etag(7)));
+        assert!(!if_none_match(&headers, &project_etag(8)));
+
+        let time = parse_timestamp("2024-02-29 13:05:09").unwrap();
+        assert_eq!(
+            httpdate::fmt_http_date(time),
+            "Thu, 29 Feb 2024 13:05:09 GMT"
+        );
+        assert!(parse_timestamp("yesterday").is_none());
+    }
+}
@@ -1,2 +1,25 @@
 pub mod admin;
+pub mod conditional;
 pub mod user;
+
+use crate::{
+    error::{AppError, AppResult},
+    models::ConfigOperation,
+};
+
+/// Every value a batch sets must be JSON, checked before anything is written.
+pub fn validate_batch_values(operations: &[ConfigOperation]) -> AppResult<()> {
+    for operation in operations {
+        if let ConfigOperation::Set { value, .. } = operation {
+            validate_json_string(value)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn validate_json_string(raw: &str) -> AppResult<()> {
+    serde_json::from_str::<serde_json::Value>(raw).map_err(|e| {
+        AppError::BadRequest(format!("config value must be valid JSON string: {e}"))
+    })?;
+    Ok(())
+}
@@ -1,22 +1,40 @@
 use axum::{
     Json, Router,
-    extract::{Extension, Path, State},
-    response::IntoResponse,
-    routing::get,
+    extract::{Extension, Path, Query, State},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
 };
+use serde::Deserialize;
 use uuid::Uuid;
 
 use crate::{
     AppState,
-    auth::AuthenticatedClient,
+    auth::{self, AuthenticatedClient},
+    ca,
+    db::ProjectConfigs,
     error::{AppError, AppResult},
-    models::UpdateConfigValueRequest,
+    models::{
+        BatchWriteRequest, EffectivePermissions, IssueCertificateRequest, UpdateConfigValueRequest,
+    },
+    otp,
+    routes::{conditional, validate_batch_values, validate_json_string},
 };
 
 pub fn router() -> Router<AppState> {
     Router::new()
         .route("/projects", get(list_projects))
+        .route("/permissions", get(list_permissions))
+        .route(
+            "/certificates",
+            post(issue_own_certificate).get(list_own_certificates),
+        )
+        .route("/certificates/{serial}", delete(revoke_own_certificate))
+        .route("/sessions", get(list_own_sessions))
+        .route("/sessions/{session_id}", delete(revoke_own_session))
         .route("/projects/{project_id}/configs", get(list_configs))
+        .route("/projects/{project_id}/batch", post(batch_configs))
+        .route("/projects/{project_id}/changes", get(list_changes))
         .route(
             "/projects/{project_id}/configs/{key}",
             get(get_config).put(update_config),
@@ -31,26 +49,159 @@ async fn list_projects(
     Ok(Json(projects))
 }
 
-async fn list_configs(
+async fn list_permissions(
     State(state): State<AppState>,
     Extension(auth_client): Extension<AuthenticatedClient>,
-    Path(project_id): Path<Uuid>,
 ) -> AppResult<impl IntoResponse> {
-    let permission = load_permission(&state, auth_client.id, project_id).await?;
-    if !permission.can_read {
+    let permissions = state
+        .db
+        .list_permissions_for_client(&auth_client.id)
+        .await?;
+    Ok(Json(EffectivePermissions {
+        client_id: auth_client.id,
+        is_admin: auth_client.is_admin,
+        permissions,
+    }))
+}
+
+async fn issue_own_certificate(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    payload: Option<Json<IssueCertificateRequest>>,
+) -> AppResult<impl IntoResponse> {
+    // Otherwise a stolen certificate or session token could mint credentials
+    // that outlive it.
+    if auth_client.certificate.is_some() || auth_client.session.is_some() {
         return Err(AppError::Forbidden(String::from(
-            "read permission required",
+            "new certificates must be requested with a signed request",
         )));
     }
+    otp::verify(&state.db, &auth_client.id, auth_client.otp.as_deref()).await?;
+
+    let request = payload.map(|Json(request)| request).unwrap_or_default();
+    let issued = ca::issue_for_client(&state, &auth_client.id, &request).await?;
+    Ok((StatusCode::CREATED, Json(issued)))
+}
+
+async fn list_own_certificates(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    let certificates = state
+        .db
+        .list_client_certificates(Some(&auth_client.id))
+        .await?;
+    Ok(Json(certificates))
+}
+
+async fn revoke_own_certificate(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(serial): Path<String>,
+) -> AppResult<impl IntoResponse> {
+    let owned = state
+        .db
+        .list_client_certificates(Some(&auth_client.id))
+        .await?
+        .iter()
+        .any(|certificate| certificate.serial == serial);
+    let revoked_at = auth::current_unix_timestamp()?;
+    if !owned
+        || !state
+            .db
+            .revoke_client_certificate(&serial, revoked_at)
+            .await?
+    {
+        return Err(AppError::NotFound(String::from(
+            "certificate not found or already revoked",
+        )));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_own_sessions(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+) -> AppResult<impl IntoResponse> {
+    let sessions = state.db.list_sessions(Some(&auth_client.id)).await?;
+    Ok(Json(sessions))
+}
+
+async fn revoke_own_session(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(session_id): Path<Uuid>,
+) -> AppResult<impl IntoResponse> {
+    let owned = state
+        .db
+        .list_sessions(Some(&auth_client.id))
+        .await?
+        .iter()
+        .any(|session| session.id == session_id);
+    let revoked_at = auth::current_unix_timestamp()?;
+    if !owned || !state.db.revoke_session(&session_id, revoked_at).await? {
+        return Err(AppError::NotFound(String::from(
+            "session not found or already revoked",
+        )));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
 
-    let configs = state.db.list_configs_for_project(&project_id).await?;
-    Ok(Json(configs))
+async fn list_configs(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(project_id): Path<Uuid>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let read = load_readable_configs(&state, auth_client.id, project_id, None).await?;
+    let last_modified = read.configs.iter().map(|c| c.updated_at.as_str()).max();
+    Ok(conditional::respond(
+        &headers,
+        &conditional::project_etag(read.revision),
+        last_modified,
+        revision_header(read.revision),
+        Json(&read.configs),
+    ))
 }
 
 async fn get_config(
     State(state): State<AppState>,
     Extension(auth_client): Extension<AuthenticatedClient>,
     Path((project_id, key)): Path<(Uuid, String)>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let read = load_readable_configs(&state, auth_client.id, project_id, Some(&key)).await?;
+    let config_item = read
+        .configs
+        .into_iter()
+        .next()
+        .ok_or_else(|| AppError::NotFound(String::from("config not found")))?;
+    Ok(conditional::respond(
+        &headers,
+        &conditional::config_etag(&config_item.id, config_item.version),
+        Some(&config_item.updated_at),
+        revision_header(read.revision),
+        Json(&config_item),
+    ))
+}
+
+/// Changes per `/changes` page unless the request asks for fewer.
+const MAX_CHANGES_PER_PAGE: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+struct ChangesQuery {
+    #[serde(default)]
+    since: i64,
+    limit: Option<usize>,
+}
+
+async fn list_changes(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(project_id): Path<Uuid>,
+    Query(query): Query<ChangesQuery>,
 ) -> AppResult<impl IntoResponse> {
     let permission = load_permission(&state, auth_client.id, project_id).await?;
     if !permission.can_read {
@@ -59,12 +210,15 @@ async fn get_config(
         )));
     }
 
-    let config_item = state
+    let limit = query
+        .limit
+        .unwrap_or(MAX_CHANGES_PER_PAGE)
+        .clamp(1, MAX_CHANGES_PER_PAGE);
+    let feed = state
         .db
-        .get_config_by_key(&project_id, &key)
-        .await?
-        .ok_or_else(|| AppError::NotFound(String::from("config not found")))?;
-    Ok(Json(config_item))
+        .config_changes(&project_id, query.since, limit)
+        .await?;
+    Ok((revision_header(feed.revision), Json(feed)))
 }
 
 async fn update_config(
@@ -88,6 +242,27 @@ async fn update_config(
     Ok(Json(config_item))
 }
 
+async fn batch_configs(
+    State(state): State<AppState>,
+    Extension(auth_client): Extension<AuthenticatedClient>,
+    Path(project_id): Path<Uuid>,
+    Json(payload): Json<BatchWriteRequest>,
+) -> AppResult<impl IntoResponse> {
+    let permission = load_permission(&state, auth_client.id, project_id).await?;
+    if !permission.can_write {
+        return Err(AppError::Forbidden(String::from(
+            "write permission required",
+        )));
+    }
+
+    validate_batch_values(&payload.operations)?;
+    let response = state
+        .db
+        .apply_config_batch(&project_id, &payload.operations)
+        .await?;
+    Ok(Json(response))
+}
+
 async fn load_permission(
     state: &AppState,
     client_id: Uuid,
@@ -100,9 +275,33 @@ async fn load_permission(
         .ok_or_else(|| AppError::Forbidden(String::from("no project access granted")))
 }
 
-fn validate_json_string(raw: &str) -> AppResult<()> {
-    serde_json::from_str::<serde_json::Value>(raw).map_err(|e| {
-        AppError::BadRequest(format!("config value must be valid JSON string: {e}"))
-    })?;
-    Ok(())
+/// Permission check and config read in a single storage call.
+async fn load_readable_configs(
+    state: &AppState,
+    client_id: Uuid,
+    project_id: Uuid,
+    key: Option<&str>,
+) -> AppResult<ProjectConfigs> {
+    let read = state
+        .db
+        .read_configs(&client_id, &project_id, key)
+        .await?
+        .ok_or_else(|| AppError::Forbidden(String::from("no project access granted")))?;
+    if !read.permission.can_read {
+        return Err(AppError::Forbidden(String::from(
+            "read permission required",
+        )));
+    }
+
+    Ok(read)
+}
+
+pub const CONFIG_REVISION_HEADER: &str = "x-config-revision";
+
+/// Lets a reader pass the revision it saw as `since` to the change feed.
+fn revision_header(revision: i64) -> [(HeaderName, HeaderValue); 1] {
+    [(
+        HeaderName::from_static(CONFIG_REVISION_HEADER),
+        HeaderValue::from(revision),
+    )]
 }
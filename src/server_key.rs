@@ -0,0 +1,228 @@
+use std::path::Path;
+
+use axum::{
+    Json,
+    body::{self, Body},
+    extract::{OriginalUri, Request, State},
+    http::{HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use rcgen::KeyPair;
+use ring::signature::{Ed25519KeyPair, KeyPair as RingKeyPair};
+use serde_json::json;
+
+use crate::{
+    AppState, auth, crypto,
+    error::{AppError, AppResult},
+    http_signature::HttpSignature,
+};
+
+pub const SERVER_SIGNATURE_HEADER: &str = "x-server-signature";
+pub const SERVER_TIMESTAMP_HEADER: &str = "x-server-timestamp";
+
+/// The server's Ed25519 identity. It signs `/api` responses so clients can
+/// tell them apart from ones forged by a proxy between them and the server.
+pub struct ServerKey {
+    key_pair: Ed25519KeyPair,
+    public_key_b64: String,
+}
+
+impl std::fmt::Debug for ServerKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ServerKey")
+            .field("public_key", &self.public_key_b64)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ServerKey {
+    /// Loads the PKCS#8 key at `path`, generating one there if it does not
+    /// exist yet. The flag is `true` when it was generated.
+    pub fn load_or_create(path: &str) -> AppResult<(Self, bool)> {
+        if Path::new(path).exists() {
+            let pem = std::fs::read_to_string(path)
+                .map_err(|e| AppError::BadRequest(format!("failed to read {path}: {e}")))?;
+            return Ok((Self::from_pem(&pem)?, false));
+        }
+
+        let generated = crypto::generate_ed25519_keypair()?;
+        crypto::write_secret_file(Path::new(path), generated.private_key_pem.as_bytes())
+            .map_err(|e| AppError::Internal(format!("failed to write {path}: {e}")))?;
+        Ok((Self::from_pem(&generated.private_key_pem)?, true))
+    }
+
+    fn from_pem(pem: &str) -> AppResult<Self> {
+        let key = KeyPair::from_pem(pem)
+            .map_err(|e| AppError::BadRequest(format!("invalid server key PEM: {e}")))?;
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(key.serialize_der().as_ref())?;
+        let public_key_b64 = STANDARD.encode(key_pair.public_key().as_ref());
+        Ok(Self {
+            key_pair,
+            public_key_b64,
+        })
+    }
+
+    /// Base64 public key, in the same encoding as client public keys.
+    pub fn public_key(&self) -> &str {
+        &self.public_key_b64
+    }
+
+    pub fn sign(&self, canonical: &str) -> String {
+        STANDARD.encode(self.key_pair.sign(canonical.as_bytes()).as_ref())
+    }
+}
+
+/// `GET /server-key`: the public half of the response signing key.
+pub async fn public_key(State(state): State<AppState>) -> AppResult<Json<serde_json::Value>> {
+    let key = state
+        .server_key
+        .as_ref()
+        .ok_or_else(|| AppError::NotFound(String::from("response signing is not configured")))?;
+    Ok(Json(json!({
+        "algorithm": "ed25519",
+        "public_key": key.public_key(),
+    })))
+}
+
+/// Adds `X-Server-Signature` over the request's nonce, method and path, the
+/// signing time, the status and the body. Errors are signed too, so a proxy
+/// cannot forge a denial.
+pub async fn sign_response(
+    State(state): State<AppState>,
+    request: Request,
+    next: Next,
+) -> AppResult<Response> {
+    let Some(key) = state.server_key.clone() else {
+        return Ok(next.run(request).await);
+    };
+    let nonce = request_nonce(request.headers());
+    let method = request.method().as_str().to_owned();
+    let uri = request
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(request.uri(), |original| &original.0);
+    let path_and_query = uri
+        .path_and_query()
+        .map_or_else(|| uri.path().to_owned(), |value| value.as_str().to_owned());
+
+    let (mut parts, body) = next.run(request).await.into_parts();
+    let bytes = body::to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| AppError::Internal(format!("failed to read response body: {e}")))?;
+    let timestamp = auth::current_unix_timestamp()?;
+    let canonical = crypto::CanonicalResponse {
+        nonce: &nonce,
+        method: &method,
+        path_and_query: &path_and_query,
+        timestamp,
+        status: parts.status.as_u16(),
+        body: &bytes,
+    }
+    .canonical_string();
+    let signature = HeaderValue::from_str(&key.sign(&canonical))
+        .map_err(|e| AppError::Internal(format!("invalid signature header: {e}")))?;
+    parts.headers.insert(SERVER_SIGNATURE_HEADER, signature);
+    parts
+        .headers
+        .insert(SERVER_TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+
+    Ok(Response::from_parts(parts, Body::from(bytes)))
+}
+
+/// `X-Nonce`, or the `nonce` of an RFC 9421 signature.
+fn request_nonce(headers: &HeaderMap) -> String {
+    if let Some(nonce) = headers.get("X-Nonce").and_then(|value| value.to_str().ok()) {
+        return nonce.trim().to_owned();
+    }
+    if !headers.contains_key("Signature-Input") {
+        return String::new();
+    }
+    HttpSignature::from_headers(headers)
+        .ok()
+        .and_then(|signature| signature.nonce)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{Router, http::StatusCode, middleware, routing::get};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        config::AppConfig,
+        db::{Database, MemoryStore},
+        keys::KeyAlgorithm,
+        nonce::Nonces,
+    };
+
+    async fn signed(router: &Router, method: &str, nonce: Option<&str>) -> (String, i64) {
+        let mut request = Request::builder().method(method).uri("/api/configs?key=a");
+        if let Some(nonce) = nonce {
+            request = request
+                .header(
+                    "Signature-Input",
+                    format!("sig1=(\"@method\");created=1;keyid=\"k\";nonce=\"{nonce}\""),
+                )
+                .header("Signature", "sig1=:AA==:");
+        }
+        let response = router
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let header = |name| response.headers()[name].to_str().unwrap().to_owned();
+        (
+            header(SERVER_SIGNATURE_HEADER),
+            header(SERVER_TIMESTAMP_HEADER).parse().unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn responses_are_bound_to_the_request() {
+        let config = AppConfig::for_tests("memory");
+        let db = Database::new(MemoryStore::new());
+        let generated = crypto::generate_ed25519_keypair().unwrap();
+        let key = Arc::new(ServerKey::from_pem(&generated.private_key_pem).unwrap());
+        let state = AppState {
+            nonces: Nonces::from_config(&config, &db),
+            db,
+            config,
+            ca: None,
+            server_key: Some(key.clone()),
+        };
+        let router = Router::new()
+            .route(
+                "/api/configs",
+                get(|| async { "{}" }).post(|| async { "{}" }),
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), sign_response))
+            .with_state(state);
+
+        // The same path and body answer different requests differently.
+        let (first, timestamp) = signed(&router, "GET", Some("n-1")).await;
+        let (second, _) = signed(&router, "GET", Some("n-2")).await;
+        assert_ne!(first, second);
+        let (without_nonce, _) = signed(&router, "GET", None).await;
+        let (post, _) = signed(&router, "POST", None).await;
+        assert_ne!(without_nonce, post);
+
+        let canonical = crypto::CanonicalResponse {
+            nonce: "n-1",
+            method: "GET",
+            path_and_query: "/api/configs?key=a",
+            timestamp,
+            status: 200,
+            body: b"{}",
+        }
+        .canonical_string();
+        crypto::verify_signature(KeyAlgorithm::Ed25519, key.public_key(), &canonical, &first)
+            .unwrap();
+    }
+}
@@ -0,0 +1,235 @@
+//! Console sessions: a client signs a one-time server challenge and gets a
+//! short-lived bearer token, set as an `HttpOnly` cookie, in place of signing
+//! every request.
+
+use axum::{
+    Json,
+    extract::State,
+    http::{HeaderMap, HeaderValue, StatusCode, header, request::Parts},
+    response::{IntoResponse, Response},
+};
+use ring::rand::{SecureRandom, SystemRandom};
+use uuid::Uuid;
+
+use crate::{
+    AppState, auth, crypto,
+    error::{AppError, AppResult},
+    models::{
+        CreateSessionRequest, Session, SessionChallengeRequest, SessionChallengeResponse,
+        SessionScope,
+    },
+};
+
+pub const SESSION_COOKIE: &str = "cloudconfig_session";
+const CHALLENGE_TTL_SECONDS: i64 = 120;
+
+/// `POST /session/challenge`.
+pub async fn challenge(
+    State(state): State<AppState>,
+    Json(payload): Json<SessionChallengeRequest>,
+) -> AppResult<Json<SessionChallengeResponse>> {
+    let now = auth::current_unix_timestamp()?;
+    let challenge = random_hex()?;
+    let expires_at = now + CHALLENGE_TTL_SECONDS;
+
+    // Unknown clients get an unusable challenge, so this does not reveal
+    // which client ids exist.
+    if state
+        .db
+        .get_client_by_id(&payload.client_id)
+        .await?
+        .is_some()
+    {
+        state
+            .db
+            .create_session_challenge(&challenge, &payload.client_id, expires_at, now)
+            .await?;
+    }
+
+    Ok(Json(SessionChallengeResponse {
+        challenge,
+        expires_at,
+    }))
+}
+
+/// `POST /session`: trades a signed challenge for a session token.
+pub async fn create(
+    State(state): State<AppState>,
+    parts: Parts,
+    Json(payload): Json<CreateSessionRequest>,
+) -> AppResult<Response> {
+    let now = auth::current_unix_timestamp()?;
+    if payload.scopes.is_empty() {
+        return Err(AppError::BadRequest(String::from(
+            "at least one scope is required",
+        )));
+    }
+
+    let challenged = state
+        .db
+        .take_session_challenge(&payload.challenge, now)
+        .await?;
+    if challenged != Some(payload.client_id) {
+        return Err(AppError::Unauthorized(String::from(
+            "invalid or expired challenge",
+        )));
+    }
+    let client = state
+        .db
+        .get_client_by_id(&payload.client_id)
+        .await?
+        .ok_or_else(|| AppError::Unauthorized(String::from("invalid client credentials")))?;
+
+    let host = auth::request_host(&parts, auth::original_uri(&parts))?;
+    let canonical = crypto::session_canonical_string(
+        &payload.challenge,
+        &host,
+        &client.id.to_string(),
+        &payload.scopes,
+    );
+    crypto::verify_signature(
+        client.key_algorithm,
+        &client.public_key,
+        &canonical,
+        &payload.signature,
+    )?;
+    auth::check_source(&client, auth::request_source(&state, &parts))?;
+    if payload.scopes.contains(&SessionScope::Admin) && !client.is_admin {
+        return Err(AppError::Forbidden(String::from(
+            "the admin scope needs an admin client",
+        )));
+    }
+
+    let max_ttl = state.config.session_ttl_seconds;
+    let ttl = payload.ttl_seconds.unwrap_or(max_ttl).min(max_ttl);
+    if ttl == 0 {
+        return Err(AppError::BadRequest(String::from(
+            "ttl_seconds must be > 0",
+        )));
+    }
+    let mut scopes = Vec::new();
+    for scope in payload.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+
+    let token = random_hex()?;
+    let session = Session {
+        id: Uuid::new_v4(),
+        client_id: client.id,
+        scopes,
+        created_at: now,
+        expires_at: now.saturating_add(i64::try_from(ttl).unwrap_or(i64::MAX)),
+        revoked_at: None,
+    };
+    state
+        .db
+        .create_session(&session, &crypto::sha256_hex(token.as_bytes()))
+        .await?;
+
+    let cookie = session_cookie(&token, ttl, state.config.tls_enabled())?;
+    Ok((
+        StatusCode::CREATED,
+        [(header::SET_COOKIE, cookie)],
+        Json(session),
+    )
+        .into_response())
+}
+
+/// `DELETE /session`: revokes the presented session and clears the cookie.
+pub async fn logout(State(state): State<AppState>, headers: HeaderMap) -> AppResult<Response> {
+    if let Some(token) = token_from_headers(&headers) {
+        let hash = crypto::sha256_hex(token.as_bytes());
+        if let Some(session) = state.db.get_session_by_token(&hash).await? {
+            state
+                .db
+                .revoke_session(&session.id, auth::current_unix_timestamp()?)
+                .await?;
+        }
+    }
+
+    let cookie = session_cookie("", 0, state.config.tls_enabled())?;
+    Ok((StatusCode::NO_CONTENT, [(header::SET_COOKIE, cookie)]).into_response())
+}
+
+/// The token from `Authorization: Bearer`, or else the session cookie.
+pub fn token_from_headers(headers: &HeaderMap) -> Option<String> {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if let Some(token) = bearer {
+        return Some(token.trim().to_owned());
+    }
+
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| token.to_owned())
+        .filter(|token| !token.is_empty())
+}
+
+/// Whether `scopes` allow `method` on `path`: `/admin` needs `admin`, other
+/// reads need `read` and other writes need `write`.
+pub fn allows(scopes: &[SessionScope], method: &str, path: &str) -> bool {
+    let needed = if path.starts_with("/admin") {
+        SessionScope::Admin
+    } else if matches!(method, "GET" | "HEAD") {
+        SessionScope::Read
+    } else {
+        SessionScope::Write
+    };
+    scopes.contains(&needed)
+}
+
+fn session_cookie(token: &str, max_age: u64, secure: bool) -> AppResult<HeaderValue> {
+    let secure = if secure { "; Secure" } else { "" };
+    HeaderValue::from_str(&format!(
+        "{SESSION_COOKIE}={token}; Path=/; Max-Age={max_age}; HttpOnly; SameSite=Strict{secure}"
+    ))
+    .map_err(|e| AppError::Internal(format!("invalid session cookie: {e}")))
+}
+
+fn random_hex() -> AppResult<String> {
+    let mut bytes = [0_u8; 32];
+    SystemRandom::new().fill(&mut bytes)?;
+    Ok(hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{HeaderMap, HeaderValue, header};
+
+    use super::{allows, token_from_headers};
+    use crate::models::SessionScope;
+
+    #[test]
+    fn reads_bearer_tokens_before_cookies() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; cloudconfig_session=from-cookie"),
+        );
+        assert_eq!(token_from_headers(&headers).as_deref(), Some("from-cookie"));
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer abc"),
+        );
+        assert_eq!(token_from_headers(&headers).as_deref(), Some("abc"));
+    }
+
+    #[test]
+    fn scopes_follow_method_and_path() {
+        let read = [SessionScope::Read];
+        assert!(allows(&read, "GET", "/api/projects"));
+        assert!(!allows(&read, "PUT", "/api/projects/x/configs/k"));
+        assert!(!allows(&read, "GET", "/admin/clients"));
+        assert!(allows(&[SessionScope::Admin], "DELETE", "/admin/clients/x"));
+    }
+}
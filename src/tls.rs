@@ -0,0 +1,457 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use axum::extract::connect_info::Connected;
+use axum::serve::IncomingStream;
+use rustls::{
+    ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme,
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{CryptoProvider, ring as provider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime, pem::PemObject},
+    server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier},
+    sign::CertifiedKey,
+};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::{TlsAcceptor, TlsConnector, server::TlsStream};
+
+use crate::{
+    config::AppConfig,
+    crypto,
+    error::{AppError, AppResult},
+};
+
+/// A slow or silent client must not hold a handshake slot forever.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves whatever certificate is currently on disk; the reload task swaps it
+/// in place so new handshakes pick it up without a restart.
+#[derive(Debug)]
+pub struct ReloadingCert {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ReloadingCert {
+    pub fn load(cert_path: &Path, key_path: &Path) -> AppResult<Self> {
+        let certified = load_certified_key(cert_path, key_path)?;
+        Ok(Self {
+            cert_path: cert_path.to_owned(),
+            key_path: key_path.to_owned(),
+            current: RwLock::new(Arc::new(certified)),
+        })
+    }
+
+    pub fn fingerprint(&self) -> String {
+        self.current
+            .read()
+            .map(|current| fingerprint(&current.cert[0]))
+            .unwrap_or_default()
+    }
+
+    /// Polls the files' modification times and reloads when either changes.
+    /// A pair that fails to load (e.g. the cert was replaced but the key not
+    /// yet) is logged and the previous certificate stays in use.
+    pub fn spawn_reload(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut seen = self.modified();
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let modified = self.modified();
+                if modified == seen {
+                    continue;
+                }
+                seen = modified;
+
+                match load_certified_key(&self.cert_path, &self.key_path) {
+                    Ok(certified) => {
+                        let fingerprint = fingerprint(&certified.cert[0]);
+                        if let Ok(mut current) = self.current.write() {
+                            *current = Arc::new(certified);
+                        }
+                        tracing::info!("reloaded TLS certificate (SHA-256 {fingerprint})");
+                    }
+                    Err(error) => {
+                        tracing::warn!("keeping the current TLS certificate: {error}");
+                    }
+                }
+            }
+        });
+    }
+
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        (modified(&self.cert_path), modified(&self.key_path))
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.current.read().ok().map(|current| Arc::clone(&current))
+    }
+}
+
+/// Accepts TCP connections and completes TLS handshakes off the accept loop,
+/// handing axum only the connections that finished one.
+pub struct TlsListener {
+    incoming: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    /// With `client_ca`, clients may present a certificate it issued; those
+    /// that do not can still authenticate with request signatures.
+    pub fn new(
+        listener: TcpListener,
+        cert: Arc<ReloadingCert>,
+        client_ca: Option<CertificateDer<'static>>,
+    ) -> AppResult<Self> {
+        let local_addr = listener
+            .local_addr()
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        let provider = Arc::new(provider::default_provider());
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()?;
+        let builder = match client_ca {
+            Some(root) => {
+                let mut roots = RootCertStore::empty();
+                roots.add(root)?;
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .allow_unauthenticated()
+                        .build()
+                        .map_err(|e| AppError::Crypto(e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut server_config = builder.with_cert_resolver(cert);
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(server_config));
+
+        let (sender, incoming) = mpsc::channel(64);
+        tokio::spawn(accept_loop(listener, acceptor, sender));
+        Ok(Self {
+            incoming,
+            local_addr,
+        })
+    }
+}
+
+impl axum::serve::Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.incoming.recv().await {
+            Some(connection) => connection,
+            // The accept loop only stops if the runtime is shutting down.
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// Connection details available to handlers through `ConnectInfo`.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub addr: SocketAddr,
+    /// Fingerprint of the client certificate, already verified against the
+    /// internal CA during the handshake.
+    pub client_certificate: Option<String>,
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self {
+            addr: *stream.remote_addr(),
+            client_certificate: None,
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        let (_, connection) = stream.io().get_ref();
+        Self {
+            addr: *stream.remote_addr(),
+            client_certificate: connection
+                .peer_certificates()
+                .and_then(<[_]>::first)
+                .map(|cert| fingerprint(cert)),
+        }
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    sender: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(error) => {
+                tracing::warn!("failed to accept connection: {error}");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        let acceptor = acceptor.clone();
+        let sender = sender.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(tls)) => {
+                    let _ = sender.send((tls, peer)).await;
+                }
+                Ok(Err(error)) => tracing::debug!("TLS handshake with {peer} failed: {error}"),
+                Err(_) => tracing::debug!("TLS handshake with {peer} timed out"),
+            }
+        });
+    }
+}
+
+/// Writes a self-signed certificate and key to the configured paths unless a
+/// certificate is already there. Returns the new certificate's fingerprint.
+pub fn ensure_self_signed(config: &AppConfig) -> AppResult<Option<String>> {
+    let (Some(cert_path), Some(key_path)) = (&config.tls_cert_path, &config.tls_key_path) else {
+        return Ok(None);
+    };
+    if !config.tls_self_signed || Path::new(cert_path).exists() {
+        return Ok(None);
+    }
+
+    let generated = rcgen::generate_simple_self_signed(self_signed_names(&config.listen_addr))?;
+    crypto::write_secret_file(
+        Path::new(key_path),
+        generated.signing_key.serialize_pem().as_bytes(),
+    )
+    .map_err(|e| AppError::Internal(format!("failed to write {key_path}: {e}")))?;
+    std::fs::write(cert_path, generated.cert.pem())
+        .map_err(|e| AppError::Internal(format!("failed to write {cert_path}: {e}")))?;
+
+    Ok(Some(fingerprint(generated.cert.der())))
+}
+
+fn self_signed_names(listen_addr: &str) -> Vec<String> {
+    let mut names = vec![
+        String::from("localhost"),
+        String::from("127.0.0.1"),
+        String::from("::1"),
+    ];
+    if let Ok(addr) = listen_addr.parse::<SocketAddr>()
+        && !addr.ip().is_unspecified()
+        && !addr.ip().is_loopback()
+    {
+        names.push(addr.ip().to_string());
+    }
+    names
+}
+
+fn load_certified_key(cert_path: &Path, key_path: &Path) -> AppResult<CertifiedKey> {
+    let certs = read_certs(cert_path)?;
+    let key = PrivateKeyDer::from_pem_file(key_path).map_err(|e| {
+        AppError::BadRequest(format!(
+            "failed to read TLS key {}: {e}",
+            key_path.display()
+        ))
+    })?;
+    let signing_key = provider::sign::any_supported_type(&key)?;
+
+    let certified = CertifiedKey::new(certs, signing_key);
+    certified.keys_match().map_err(|e| {
+        AppError::BadRequest(format!(
+            "TLS key {} does not match certificate {}: {e}",
+            key_path.display(),
+            cert_path.display()
+        ))
+    })?;
+    Ok(certified)
+}
+
+fn read_certs(cert_path: &Path) -> AppResult<Vec<CertificateDer<'static>>> {
+    let read_error = |e: rustls::pki_types::pem::Error| {
+        AppError::BadRequest(format!(
+            "failed to read TLS certificate {}: {e}",
+            cert_path.display()
+        ))
+    };
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .map_err(read_error)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(read_error)?;
+    if certs.is_empty() {
+        return Err(AppError::BadRequest(format!(
+            "no certificate found in {}",
+            cert_path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+/// SHA-256 of the DER certificate, formatted `AB:CD:...` for pinning.
+pub fn fingerprint(der: &[u8]) -> String {
+    hex::encode_upper(Sha256::digest(der))
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| String::from_utf8_lossy(pair).into_owned())
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+pub trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+/// Where the local CLI (`status`, `doctor`) reaches the server. With TLS on it
+/// trusts exactly the configured certificate, which works for self-signed
+/// ones and needs no CA bundle.
+pub struct Endpoint {
+    pub addr: String,
+    connector: Option<TlsConnector>,
+}
+
+impl Endpoint {
+    pub fn for_config(addr: String, config: Option<&AppConfig>) -> AppResult<Self> {
+        let Some(cert_path) = config.and_then(|config| config.tls_cert_path.as_deref()) else {
+            return Ok(Self {
+                addr,
+                connector: None,
+            });
+        };
+
+        let pinned = read_certs(Path::new(cert_path))?.swap_remove(0);
+        let provider = Arc::new(provider::default_provider());
+        let client_config = ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()?
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedCert { pinned, provider }))
+            .with_no_client_auth();
+        Ok(Self {
+            addr,
+            connector: Some(TlsConnector::from(Arc::new(client_config))),
+        })
+    }
+
+    pub fn scheme(&self) -> &'static str {
+        if self.connector.is_some() {
+            "https"
+        } else {
+            "http"
+        }
+    }
+
+    pub async fn connect(&self) -> std::io::Result<Box<dyn Stream>> {
+        let stream = TcpStream::connect(&self.addr).await?;
+        let Some(connector) = &self.connector else {
+            return Ok(Box::new(stream));
+        };
+
+        // The pinned verifier ignores the name, but rustls needs one.
+        let name = ServerName::try_from(String::from("localhost"))
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+        Ok(Box::new(connector.connect(name, stream).await?))
+    }
+}
+
+impl std::fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}://{}", self.scheme(), self.addr)
+    }
+}
+
+#[derive(Debug)]
+struct PinnedCert {
+    pinned: CertificateDer<'static>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.pinned.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General(format!(
+                "server certificate {} does not match the configured one",
+                fingerprint(end_entity)
+            )))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ReloadingCert, fingerprint};
+
+    #[test]
+    fn loads_a_generated_pair_and_rejects_a_mismatched_key() {
+        let dir = std::env::temp_dir().join(format!("cloudconfig-tls-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+
+        let generated =
+            rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+        std::fs::write(&cert_path, generated.cert.pem()).unwrap();
+        std::fs::write(&key_path, generated.signing_key.serialize_pem()).unwrap();
+        let cert = ReloadingCert::load(&cert_path, &key_path).unwrap();
+        assert_eq!(cert.fingerprint(), fingerprint(generated.cert.der()));
+        assert_eq!(cert.fingerprint().len(), 32 * 3 - 1);
+
+        let other = rcgen::KeyPair::generate().unwrap();
+        std::fs::write(&key_path, other.serialize_pem()).unwrap();
+        assert!(ReloadingCert::load(&cert_path, &key_path).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}